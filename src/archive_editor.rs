@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, fmt::Debug, fs::{File, OpenOptions}, io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write}, path::Path};
+
+use crate::{codec::Codec, index_serialization::{entries_to_bytes, IndexEntry, SerializationError, ENTRY_ALIAS}, resource_library::{encode_index, index_start, read_header, write_header, ByteStream, HeaderExtras, Normalize, PathError, ResourceLibraryError, ResourceLibraryWriter, Result, WriteReport, FLAG_COMPACT_OFFSETS, FLAG_FINGERPRINT, FLAG_SOLID, FLAG_VOLUMES, FORMAT_VERSION}};
+
+const MOVE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// Copies len bytes from one offset of the file to another, in whichever direction keeps the source intact
+fn move_range(file: &mut File, from: u64, to: u64, len: u64) -> Result<()> {
+    let mut buffer = vec![0u8; MOVE_CHUNK_SIZE.min(len as usize)];
+    let mut moved = 0;
+
+    while moved < len {
+        let count = (len - moved).min(buffer.len() as u64);
+        let position = if to > from { len - moved - count } else { moved };
+
+        file.seek(SeekFrom::Start(from + position))?;
+        file.read_exact(&mut buffer[..count as usize])?;
+        file.seek(SeekFrom::Start(to + position))?;
+        file.write_all(&buffer[..count as usize])?;
+
+        moved += count;
+    }
+
+    Ok(())
+}
+
+// Archives that have grown too large for a compact index switch to a full one
+fn serialize_index(index: &[IndexEntry], flags: u16) -> Result<(Box<[u8]>, u16)> {
+    if flags & FLAG_COMPACT_OFFSETS != 0 {
+        match entries_to_bytes(index, true) {
+            Err(SerializationError::CompactOverflow(_)) => (),
+            result => return Ok((result?, flags))
+        }
+    }
+
+    Ok((entries_to_bytes(index, false)?, flags & !FLAG_COMPACT_OFFSETS))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactReport {
+    pub entries: usize,
+    pub original_size: u64,
+    pub compacted_size: u64
+}
+
+impl CompactReport {
+    pub fn reclaimed(&self) -> u64 {
+        self.original_size.saturating_sub(self.compacted_size)
+    }
+}
+
+// Copies the live entries of an archive into a fresh one, dropping any blobs left behind by edits
+pub fn compact<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q) -> Result<CompactReport> {
+    let mut source = File::open(src_path)?;
+    let original_size = source.seek(SeekFrom::End(0))?;
+    source.rewind()?;
+
+    let header = read_header(&mut source)?;
+    let mut index = header.index.into_vec();
+
+    if header.flags & FLAG_VOLUMES != 0 {
+        return Err(ResourceLibraryError::MultiVolumeArchive);
+    }
+
+    // Blobs are copied verbatim in the order they appear, and entries sharing a blob keep sharing it
+    let mut blobs = BTreeMap::new();
+    if header.flags & FLAG_SOLID != 0 {
+        blobs.insert(0, (0, header.data_size));
+    } else {
+        for entry in &index {
+            blobs.insert(entry.offset, (0, entry.compressed_len));
+        }
+    }
+
+    let mut data_size = 0;
+    for (offset, len) in blobs.values_mut() {
+        *offset = data_size;
+        data_size += *len;
+    }
+
+    if header.flags & FLAG_SOLID == 0 {
+        for entry in &mut index {
+            entry.offset = blobs[&entry.offset].0;
+        }
+    }
+
+    // No fingerprint is written after the copied data
+    let (index_data, flags) = serialize_index(&index, header.flags & !FLAG_FINGERPRINT)?;
+    let (index_data, flags) = encode_index(&index_data, flags, None)?;
+    let mut sink = BufWriter::new(File::create(dst_path)?);
+    write_header(&mut sink, &index_data, 0, data_size, flags, &header.extras)?;
+
+    for (&old_offset, &(_, len)) in &blobs {
+        source.seek(SeekFrom::Start(header.data_pointer + old_offset))?;
+        let copied = std::io::copy(&mut (&mut source).take(len), &mut sink)?;
+        if copied != len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+
+    sink.flush()?;
+
+    Ok(CompactReport { entries: index.len(), original_size, compacted_size: index_start(&header.extras) + index_data.len() as u64 + data_size })
+}
+
+// Rewrites an archive from an older format version with the current header and index, returning the version it had.
+// The data section isn't changed, though it's moved if the new index doesn't fit in front of it.
+pub fn migrate<P: AsRef<Path>>(path: P) -> Result<u16> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let header = read_header(&mut file)?;
+
+    if header.version == FORMAT_VERSION {
+        return Ok(header.version);
+    }
+
+    if header.flags & FLAG_VOLUMES != 0 {
+        return Err(ResourceLibraryError::MultiVolumeArchive);
+    }
+
+    // Unlike edits, migrating a solid archive is fine since none of its data changes
+    let mut editor = ArchiveEditor { file, index: Vec::new(), flags: header.flags, data_pointer: header.data_pointer, data_size: header.data_size, extras: header.extras, pending: ResourceLibraryWriter::new() };
+    editor.commit(header.index.into_vec(), &[])?;
+
+    Ok(header.version)
+}
+
+#[derive(Debug)]
+pub struct ArchiveEditor {
+    file: File,
+    index: Vec<IndexEntry>,
+    flags: u16,
+    data_pointer: u64,
+    data_size: u64,
+    extras: HeaderExtras,
+    pending: ResourceLibraryWriter
+}
+
+impl ArchiveEditor {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ArchiveEditor> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let header = read_header(&mut file)?;
+
+        // Entries in a solid archive can't be touched without recompressing the whole block
+        if header.flags & FLAG_SOLID != 0 {
+            return Err(ResourceLibraryError::SolidArchive);
+        }
+
+        if header.flags & FLAG_VOLUMES != 0 {
+            return Err(ResourceLibraryError::MultiVolumeArchive);
+        }
+
+        Ok(ArchiveEditor { file, index: header.index.into_vec(), flags: header.flags, data_pointer: header.data_pointer, data_size: header.data_size, extras: header.extras, pending: ResourceLibraryWriter::new() })
+    }
+
+    fn find(&self, path: &str) -> Option<usize> {
+        self.index.binary_search_by(|entry| entry.path[..].cmp(path)).ok()
+    }
+
+    // Same as ResourceLibraryWriter::set_path_normalization, for the entries written to the editor and its lookups
+    pub fn set_path_normalization(&mut self, normalization: Normalize) {
+        self.pending.set_path_normalization(normalization);
+    }
+
+    // Invalid paths can never be in the archive
+    pub fn contains_path(&self, path: impl AsRef<str>) -> bool {
+        self.pending.normalize_path(path.as_ref().to_owned()).is_ok_and(|path| self.find(&path).is_some() || self.pending.contains_path(&path[..]))
+    }
+
+    // Existing entries are looked up by the path the new one will be stored under, so another spelling of the same
+    // path can't end up in the index twice
+    pub fn write_stream<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T) -> Result<()> {
+        let path = path.into();
+        let normalized = self.pending.normalize_path(path.clone())?;
+        if self.find(&normalized).is_some() {
+            return Err(PathError::PathExists(normalized.into()).into());
+        }
+
+        self.pending.write_stream(path, stream)
+    }
+
+    pub fn write_data(&mut self, path: impl Into<String>, data: impl Into<Box<[u8]>>) -> Result<()> {
+        self.write_stream(path, ByteStream::from(data.into()))
+    }
+
+    pub fn write_str(&mut self, path: impl Into<String>, data: &str) -> Result<()> {
+        self.write_data(path, data.as_bytes())
+    }
+
+    // Packs a writer into a standalone archive and returns its index along with the raw data section
+    fn pack(writer: &mut ResourceLibraryWriter, codec: impl Into<Codec>) -> Result<(WriteReport, Vec<IndexEntry>, Vec<u8>)> {
+        let mut packed = Cursor::new(Vec::new());
+        let report = writer.write_to(&mut packed, codec)?;
+        packed.rewind()?;
+
+        let header = read_header(&mut packed)?;
+        let data = packed.into_inner().drain(header.data_pointer as usize..(header.data_pointer + header.data_size) as usize).collect();
+
+        Ok((report, header.index.into_vec(), data))
+    }
+
+    // Rewrites the header and index, then appends data to the end of the data section. The data overwrites the fingerprint,
+    // which would take rehashing the whole data section to bring up to date.
+    fn commit(&mut self, index: Vec<IndexEntry>, appended: &[u8]) -> Result<u64> {
+        let (index_data, flags) = serialize_index(&index, self.flags & !FLAG_FINGERPRINT)?;
+        let (index_data, flags) = encode_index(&index_data, flags, None)?;
+
+        // Existing entries keep their offsets, and the data section only has to move once the index outgrows its reserved space
+        let index_start = index_start(&self.extras);
+        let index_region = self.data_pointer.saturating_sub(index_start);
+        let (data_pointer, index_padding) = match index_region.checked_sub(index_data.len() as u64) {
+            Some(index_padding) => (self.data_pointer, index_padding),
+            None => (index_start + index_data.len() as u64, 0)
+        };
+        let data_size = self.data_size + appended.len() as u64;
+        if data_pointer != self.data_pointer {
+            move_range(&mut self.file, self.data_pointer, data_pointer, self.data_size)?;
+        }
+
+        self.file.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.file, &index_data, index_padding, data_size, flags, &self.extras)?;
+
+        self.file.seek(SeekFrom::Start(data_pointer + self.data_size))?;
+        self.file.write_all(appended)?;
+        self.file.set_len(data_pointer + data_size)?;
+
+        self.index = index;
+        self.data_pointer = data_pointer;
+        self.data_size = data_size;
+
+        Ok(index_data.len() as u64)
+    }
+
+    pub fn save(&mut self, codec: impl Into<Codec>) -> Result<WriteReport> {
+        // New entries are packed on their own first, then their data is spliced onto the end of this archive
+        let (mut report, new_index, new_data) = Self::pack(&mut self.pending, codec)?;
+
+        let mut index = self.index.clone();
+        index.extend(new_index.into_iter().map(|entry| IndexEntry { offset: entry.offset + self.data_size, ..entry }));
+        index.sort_by(|a, b| a.path.cmp(&b.path));
+
+        report.index_size = self.commit(index, &new_data)?;
+        report.fingerprint = None;
+        self.pending.clear();
+
+        Ok(report)
+    }
+
+    pub fn replace_entry(&mut self, path: impl AsRef<str>, data: impl Into<Box<[u8]>>, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let path = &*self.pending.normalize_path(path.as_ref().to_owned())?;
+        let position = self.find(path).ok_or_else(|| PathError::InvalidPath(path.to_owned()))?;
+
+        let mut writer = ResourceLibraryWriter::new();
+        writer.write_data(path, data)?;
+        let (mut report, new_index, new_data) = Self::pack(&mut writer, codec)?;
+
+        // The new blob goes into the old slot if it fits, otherwise it's appended and the old one is left as garbage.
+        // An alias's slot belongs to its target, so replacing an alias always appends.
+        let old = &self.index[position];
+        let fits = old.flags & ENTRY_ALIAS == 0 && new_data.len() as u64 <= old.compressed_len;
+        let offset = if fits { old.offset } else { self.data_size };
+
+        let mut index = self.index.clone();
+        let replaced = IndexEntry { offset, ..new_index.into_iter().next().unwrap() };
+        for alias in index.iter_mut().filter(|entry| entry.flags & ENTRY_ALIAS != 0 && entry.target == path) {
+            *alias = IndexEntry { path: alias.path.clone(), flags: replaced.flags | ENTRY_ALIAS, target: path.to_owned(), ..replaced.clone() };
+        }
+        index[position] = replaced;
+
+        report.fingerprint = None;
+        if fits {
+            // The blob is in place before the index that points at it is written
+            self.file.seek(SeekFrom::Start(self.data_pointer + offset))?;
+            self.file.write_all(&new_data)?;
+            report.index_size = self.commit(index, &[])?;
+        } else {
+            report.index_size = self.commit(index, &new_data)?;
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, fmt::Debug, io::{Cursor, Read, Write}, sync::atomic::{AtomicBool, Ordering}};
+
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::resource_library::{CompressionLevel, ResourceLibraryError, Result};
+
+pub(crate) const CODEC_STORED: u8 = 0;
+pub(crate) const CODEC_LZMA: u8 = 1;
+pub(crate) const CODEC_ZSTD: u8 = 2;
+pub(crate) const CODEC_DEFLATE: u8 = 3;
+
+// Ids below this are reserved for built-in codecs
+pub const MIN_CUSTOM_CODEC_ID: u8 = 128;
+// Caps how much memory an entry's recorded length can make the decoder reserve up front
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+pub trait CompressionCodec: Send + Sync {
+    fn id(&self) -> u8;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decompress(&self, data: &[u8], expected_len: Option<u64>) -> Result<Vec<u8>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LzmaCodec(pub CompressionLevel);
+
+impl Default for LzmaCodec {
+    fn default() -> Self {
+        LzmaCodec(CompressionLevel::Normal)
+    }
+}
+
+impl CompressionCodec for LzmaCodec {
+    fn id(&self) -> u8 {
+        CODEC_LZMA
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(lzma::compress(data, self.0.preset())?)
+    }
+
+    fn decompress(&self, data: &[u8], expected_len: Option<u64>) -> Result<Vec<u8>> {
+        read_limited(lzma::LzmaReader::new_decompressor(data)?, expected_len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Stored,
+    Lzma(CompressionLevel),
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    #[cfg(feature = "deflate")]
+    Deflate(u32),
+    Custom(u8)
+}
+
+impl From<CompressionLevel> for Codec {
+    fn from(level: CompressionLevel) -> Self {
+        match level {
+            CompressionLevel::None => Codec::Stored,
+            level => Codec::Lzma(level)
+        }
+    }
+}
+
+impl Codec {
+    pub fn id(&self) -> u8 {
+        match self {
+            Codec::Stored | Codec::Lzma(CompressionLevel::None) => CODEC_STORED,
+            Codec::Lzma(_) => CODEC_LZMA,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(_) => CODEC_ZSTD,
+            #[cfg(feature = "deflate")]
+            Codec::Deflate(_) => CODEC_DEFLATE,
+            Codec::Custom(id) => *id
+        }
+    }
+
+    pub(crate) fn validate(self) -> Result<Codec> {
+        match self {
+            Codec::Lzma(level) => Ok(Codec::Lzma(level.validate()?)),
+            #[cfg(feature = "deflate")]
+            Codec::Deflate(level) if level > 9 => Err(ResourceLibraryError::InvalidCompressionLevel(level)),
+            Codec::Custom(id) if id < MIN_CUSTOM_CODEC_ID => Err(ResourceLibraryError::ReservedCodecId(id)),
+            codec => Ok(codec)
+        }
+    }
+
+    pub(crate) fn is_streamable(&self) -> bool {
+        !matches!(self, Codec::Custom(_))
+    }
+
+    pub(crate) fn compress_stream<R: Read + ?Sized, W: Write>(&self, source: &mut R, sink: &mut W, chunk_size: usize, cancel: Option<&AtomicBool>) -> Result<()> {
+        match self {
+            Codec::Stored | Codec::Lzma(CompressionLevel::None) => copy_chunked(source, sink, chunk_size, cancel),
+            Codec::Lzma(level) => {
+                let mut encoder = lzma::LzmaWriter::new_compressor(sink, level.preset())?;
+                copy_chunked(source, &mut encoder, chunk_size, cancel)?;
+                encoder.finish()?;
+                Ok(())
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => {
+                let mut encoder = zstd::stream::Encoder::new(sink, *level)?;
+                copy_chunked(source, &mut encoder, chunk_size, cancel)?;
+                encoder.finish()?;
+                Ok(())
+            },
+            #[cfg(feature = "deflate")]
+            Codec::Deflate(level) => {
+                let mut encoder = flate2::write::DeflateEncoder::new(sink, flate2::Compression::new(*level));
+                copy_chunked(source, &mut encoder, chunk_size, cancel)?;
+                encoder.finish()?;
+                Ok(())
+            },
+            Codec::Custom(id) => Err(ResourceLibraryError::UnknownCodec(*id))
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::Stored | Codec::Lzma(CompressionLevel::None) => Ok(data.to_vec()),
+            Codec::Lzma(level) => LzmaCodec(*level).compress(data),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => Ok(zstd::encode_all(data, *level)?),
+            #[cfg(feature = "deflate")]
+            Codec::Deflate(level) => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::new(*level));
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            },
+            Codec::Custom(id) => Err(ResourceLibraryError::UnknownCodec(*id))
+        }
+    }
+}
+
+pub(crate) fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<()> {
+    match cancel {
+        Some(cancel) if cancel.load(Ordering::Relaxed) => Err(ResourceLibraryError::Cancelled),
+        _ => Ok(())
+    }
+}
+
+fn copy_chunked<R: Read + ?Sized, W: Write>(source: &mut R, sink: &mut W, chunk_size: usize, cancel: Option<&AtomicBool>) -> Result<()> {
+    let mut chunk = vec![0u8; chunk_size];
+    loop {
+        check_cancelled(cancel)?;
+
+        let read = source.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        sink.write_all(&chunk[..read])?;
+    }
+}
+
+// Decoding stops one byte past the expected length, which is enough to tell that an entry is larger than it claims
+fn read_limited<R: Read>(decoder: R, expected_len: Option<u64>) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::with_capacity(expected_len.unwrap_or(0).min(MAX_PREALLOCATION) as usize);
+    match expected_len {
+        Some(len) => decoder.take(len.saturating_add(1)).read_to_end(&mut decompressed)?,
+        None => { let mut decoder = decoder; decoder.read_to_end(&mut decompressed)? }
+    };
+
+    Ok(decompressed)
+}
+
+fn decompress(codec: u8, data: Vec<u8>, expected_len: Option<u64>) -> Result<Vec<u8>> {
+    match codec {
+        CODEC_STORED => Ok(data),
+        CODEC_LZMA => LzmaCodec::default().decompress(&data, expected_len),
+        #[cfg(feature = "zstd")]
+        CODEC_ZSTD => read_limited(zstd::stream::read::Decoder::with_buffer(&data[..])?, expected_len),
+        #[cfg(not(feature = "zstd"))]
+        CODEC_ZSTD => Err(ResourceLibraryError::CodecNotEnabled("zstd")),
+        #[cfg(feature = "deflate")]
+        CODEC_DEFLATE => read_limited(flate2::read::DeflateDecoder::new(&data[..]), expected_len),
+        #[cfg(not(feature = "deflate"))]
+        CODEC_DEFLATE => Err(ResourceLibraryError::CodecNotEnabled("deflate")),
+        codec => Err(ResourceLibraryError::UnknownCodec(codec))
+    }
+}
+
+// A reader that decompresses source as it's read, for the built-in codecs
+fn decoder<'a, R: Read + 'a>(codec: u8, source: R) -> Result<Box<dyn Read + 'a>> {
+    match codec {
+        CODEC_STORED => Ok(Box::new(source)),
+        CODEC_LZMA => Ok(Box::new(lzma::LzmaReader::new_decompressor(source)?)),
+        #[cfg(feature = "zstd")]
+        CODEC_ZSTD => Ok(Box::new(zstd::stream::read::Decoder::new(source)?)),
+        #[cfg(not(feature = "zstd"))]
+        CODEC_ZSTD => Err(ResourceLibraryError::CodecNotEnabled("zstd")),
+        #[cfg(feature = "deflate")]
+        CODEC_DEFLATE => Ok(Box::new(flate2::read::DeflateDecoder::new(source))),
+        #[cfg(not(feature = "deflate"))]
+        CODEC_DEFLATE => Err(ResourceLibraryError::CodecNotEnabled("deflate")),
+        codec => Err(ResourceLibraryError::UnknownCodec(codec))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CodecRegistry {
+    codecs: BTreeMap<u8, Box<dyn CompressionCodec>>
+}
+
+impl CodecRegistry {
+    pub fn register(&mut self, codec: impl CompressionCodec + 'static) -> Result<()> {
+        let id = codec.id();
+        if id < MIN_CUSTOM_CODEC_ID {
+            return Err(ResourceLibraryError::ReservedCodecId(id));
+        }
+
+        self.codecs.insert(id, Box::new(codec));
+        Ok(())
+    }
+
+    pub fn contains(&self, codec: Codec) -> bool {
+        match codec {
+            Codec::Custom(id) => self.codecs.contains_key(&id),
+            _ => true
+        }
+    }
+
+    pub fn compress(&self, codec: Codec, data: &[u8]) -> Result<Vec<u8>> {
+        match codec {
+            Codec::Custom(id) => self.codecs.get(&id).ok_or(ResourceLibraryError::UnknownCodec(id))?.compress(data),
+            codec => codec.compress(data)
+        }
+    }
+
+    fn compress_or_store<D: AsRef<[u8]> + From<Vec<u8>>>(&self, codec: Codec, data: D, min_savings: Option<f64>) -> Result<(Codec, D)> {
+        if codec.id() == CODEC_STORED {
+            return Ok((Codec::Stored, data));
+        }
+
+        // Keep the compressed bytes only if they save enough over storing the entry as-is
+        let compressed = self.compress(codec, data.as_ref())?;
+        match min_savings {
+            Some(min_savings) if compressed.len() as f64 >= data.as_ref().len() as f64 * (1.0 - min_savings) => Ok((Codec::Stored, data)),
+            _ => Ok((codec, compressed.into()))
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn compress_all<D: AsRef<[u8]> + From<Vec<u8>> + Send>(&self, jobs: Vec<(Codec, D)>, min_savings: Option<f64>) -> Result<Vec<(Codec, D)>> {
+        jobs.into_par_iter().map(|(codec, data)| self.compress_or_store(codec, data, min_savings)).collect()
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    pub fn compress_all<D: AsRef<[u8]> + From<Vec<u8>>>(&self, jobs: Vec<(Codec, D)>, min_savings: Option<f64>) -> Result<Vec<(Codec, D)>> {
+        jobs.into_iter().map(|(codec, data)| self.compress_or_store(codec, data, min_savings)).collect()
+    }
+
+    // Custom codecs only decompress whole buffers, so their entries are read in full first
+    pub fn decoder<'a, R: Read + 'a>(&self, id: u8, mut source: R, expected_len: Option<u64>) -> Result<Box<dyn Read + 'a>> {
+        match self.codecs.get(&id) {
+            Some(codec) => {
+                let mut data = Vec::new();
+                source.read_to_end(&mut data)?;
+                Ok(Box::new(Cursor::new(codec.decompress(&data, expected_len)?)))
+            },
+            None => decoder(id, source)
+        }
+    }
+
+    // For compressed data that's borrowed rather than read into a buffer of its own
+    pub fn decompress_slice(&self, id: u8, data: &[u8], expected_len: Option<u64>) -> Result<Vec<u8>> {
+        match self.codecs.get(&id) {
+            Some(codec) => codec.decompress(data, expected_len),
+            None => read_limited(decoder(id, data)?, expected_len)
+        }
+    }
+
+    pub fn decompress(&self, id: u8, data: Vec<u8>, expected_len: Option<u64>) -> Result<Vec<u8>> {
+        match self.codecs.get(&id) {
+            Some(codec) => codec.decompress(&data, expected_len),
+            None => decompress(id, data, expected_len)
+        }
+    }
+}
+
+impl Debug for CodecRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.codecs.keys()).finish()
+    }
+}
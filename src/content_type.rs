@@ -0,0 +1,47 @@
+// Content types are sniffed from the first few bytes of an entry where the format has a signature,
+// and from the path's extension otherwise
+pub(crate) const SNIFF_LEN: u64 = 16;
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xFF\xD8\xFF", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"OggS", "audio/ogg"),
+    (b"\0asm", "application/wasm"),
+    (b"%PDF-", "application/pdf")
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("ogg", "audio/ogg"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("json", "application/json"),
+    ("txt", "text/plain"),
+    ("html", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript")
+];
+
+// The longest type detection can come up with, so an entry can hold space for its type before it's been sniffed
+pub(crate) fn max_content_type_len() -> usize {
+    let signatures = SIGNATURES.iter().map(|(_, content_type)| content_type.len());
+    let extensions = EXTENSIONS.iter().map(|(_, content_type)| content_type.len());
+
+    signatures.chain(extensions).max().unwrap_or(0)
+}
+
+pub fn detect_content_type(path: &str, head: &[u8]) -> Option<&'static str> {
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(signature, _)| head.starts_with(signature)) {
+        return Some(content_type);
+    }
+
+    // Text formats like JSON have no signature to go on
+    let (_, ext) = path.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    EXTENSIONS.iter().find(|(known, _)| *known == ext).map(|(_, content_type)| *content_type)
+}
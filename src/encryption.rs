@@ -0,0 +1,281 @@
+#[cfg(feature = "encryption")]
+use std::fmt::Debug;
+
+#[cfg(feature = "encryption")]
+use aes_gcm::{aead::{consts::U12, rand_core::RngCore, Aead, KeyInit, OsRng}, Aes256Gcm, Nonce};
+#[cfg(feature = "encryption")]
+use argon2::{Algorithm, Argon2, Params, Version};
+
+#[cfg(feature = "encryption")]
+use crate::resource_library::{ResourceLibraryError, Result};
+
+// The layout of encrypted archives is always known, so that their sizes can be reported and they fail to open cleanly
+// without the encryption feature. Everything that needs a key is behind the feature.
+#[cfg(feature = "encryption")]
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: u64 = 16;
+
+// The salt, nonce and key check stored in front of an encrypted index
+pub(crate) const ENCRYPTION_PARAMS_LEN: u64 = (SALT_LEN + NONCE_LEN) as u64 + TAG_LEN;
+// How much larger sealing makes the index
+pub(crate) const INDEX_OVERHEAD: u64 = ENCRYPTION_PARAMS_LEN + TAG_LEN;
+
+// The data section is encrypted in segments that each carry their own tag, so any part of it can be read on its own
+pub(crate) const SEGMENT_SIZE: u64 = 64 * 1024;
+pub(crate) const SEALED_SEGMENT_SIZE: u64 = SEGMENT_SIZE + TAG_LEN;
+
+// Nonces count up from the archive's random base: the key check, then the index, then each data segment
+#[cfg(feature = "encryption")]
+const KEY_CHECK_NONCE: u64 = 0;
+#[cfg(feature = "encryption")]
+const INDEX_NONCE: u64 = 1;
+#[cfg(feature = "encryption")]
+const FIRST_SEGMENT_NONCE: u64 = 2;
+
+// Entries encrypted on their own start with the salt their key was derived with and a random nonce
+#[cfg(feature = "encryption")]
+const ENTRY_HEADER_LEN: usize = SALT_LEN + NONCE_LEN;
+
+// These are fixed so that archives don't depend on the Argon2 crate's defaults
+#[cfg(feature = "encryption")]
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+#[cfg(feature = "encryption")]
+const ARGON2_ITERATIONS: u32 = 2;
+#[cfg(feature = "encryption")]
+const ARGON2_LANES: u32 = 1;
+
+#[cfg(feature = "encryption")]
+#[derive(Clone, PartialEq, Eq)]
+pub enum ArchiveKey {
+    // Stretched with Argon2id using the salt stored in the archive
+    Password(String),
+    Raw([u8; KEY_LEN])
+}
+
+#[cfg(feature = "encryption")]
+impl ArchiveKey {
+    pub fn password(password: impl Into<String>) -> ArchiveKey {
+        ArchiveKey::Password(password.into())
+    }
+
+    fn derive(&self, salt: &[u8]) -> [u8; KEY_LEN] {
+        let password = match self {
+            ArchiveKey::Password(password) => password,
+            ArchiveKey::Raw(key) => return *key
+        };
+
+        // The parameters and lengths are all fixed and valid, so hashing can't fail
+        let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_LANES, Some(KEY_LEN)).unwrap();
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(password.as_bytes(), salt, &mut key).unwrap();
+
+        key
+    }
+}
+
+// Keys never show up in logs
+#[cfg(feature = "encryption")]
+impl Debug for ArchiveKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveKey::Password(_) => write!(f, "ArchiveKey::Password(..)"),
+            ArchiveKey::Raw(_) => write!(f, "ArchiveKey::Raw(..)")
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) struct ArchiveCipher {
+    cipher: Aes256Gcm,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN]
+}
+
+#[cfg(feature = "encryption")]
+impl ArchiveCipher {
+    // Every archive gets a fresh salt and nonce
+    pub fn new(key: &ArchiveKey) -> ArchiveCipher {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        ArchiveCipher { cipher: Aes256Gcm::new(&key.derive(&salt).into()), salt, nonce }
+    }
+
+    fn nonce(&self, counter: u64) -> Nonce<U12> {
+        let mut nonce = self.nonce;
+        for (byte, count) in nonce[NONCE_LEN - 8..].iter_mut().zip(counter.to_be_bytes()) {
+            *byte ^= count;
+        }
+
+        nonce.into()
+    }
+
+    fn seal(&self, counter: u64, data: &[u8]) -> Result<Vec<u8>> {
+        self.cipher.encrypt(&self.nonce(counter), data).map_err(|_| ResourceLibraryError::AuthenticationFailed)
+    }
+
+    fn open(&self, counter: u64, data: &[u8]) -> Result<Vec<u8>> {
+        self.cipher.decrypt(&self.nonce(counter), data).map_err(|_| ResourceLibraryError::AuthenticationFailed)
+    }
+
+    // The stored index is the encryption parameters followed by the sealed index
+    pub fn seal_index(&self, index_data: &[u8]) -> Result<Box<[u8]>> {
+        let mut stored = Vec::with_capacity(index_data.len() + INDEX_OVERHEAD as usize);
+        stored.extend(self.salt);
+        stored.extend(self.nonce);
+        stored.extend(self.seal(KEY_CHECK_NONCE, &[])?);
+        stored.extend(self.seal(INDEX_NONCE, index_data)?);
+
+        Ok(stored.into_boxed_slice())
+    }
+
+    // The key check is tried first so that a wrong key can be told apart from a damaged index
+    pub fn open_index(key: &ArchiveKey, stored: &[u8]) -> Result<(ArchiveCipher, Vec<u8>)> {
+        if (stored.len() as u64) < INDEX_OVERHEAD {
+            return Err(ResourceLibraryError::CorruptIndex);
+        }
+
+        let (salt, rest) = stored.split_at(SALT_LEN);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (key_check, sealed) = rest.split_at(TAG_LEN as usize);
+
+        let cipher = ArchiveCipher { cipher: Aes256Gcm::new(&key.derive(salt).into()), salt: salt.try_into().unwrap(), nonce: nonce.try_into().unwrap() };
+        cipher.open(KEY_CHECK_NONCE, key_check).map_err(|_| ResourceLibraryError::WrongKey)?;
+        let index_data = cipher.open(INDEX_NONCE, sealed)?;
+
+        Ok((cipher, index_data))
+    }
+
+    pub fn seal_segment(&self, segment: u64, data: &[u8]) -> Result<Vec<u8>> {
+        self.seal(FIRST_SEGMENT_NONCE + segment, data)
+    }
+
+    pub fn open_segment(&self, segment: u64, data: &[u8]) -> Result<Vec<u8>> {
+        self.open(FIRST_SEGMENT_NONCE + segment, data)
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) struct EntryCipher {
+    cipher: Aes256Gcm,
+    salt: [u8; SALT_LEN]
+}
+
+#[cfg(feature = "encryption")]
+impl EntryCipher {
+    // One salt is shared by every entry in the archive, so the key is only derived once
+    pub fn new(key: &ArchiveKey) -> EntryCipher {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        EntryCipher { cipher: Aes256Gcm::new(&key.derive(&salt).into()), salt }
+    }
+
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut sealed = Vec::with_capacity(ENTRY_HEADER_LEN + data.len() + TAG_LEN as usize);
+        sealed.extend(self.salt);
+        sealed.extend(nonce);
+        sealed.extend(self.cipher.encrypt(&nonce.into(), data).map_err(|_| ResourceLibraryError::AuthenticationFailed)?);
+
+        Ok(sealed)
+    }
+}
+
+// Keeps the keys derived for encrypted entries, since deriving one from a password is deliberately slow
+#[cfg(feature = "encryption")]
+#[derive(Default)]
+pub(crate) struct EntryKeys {
+    ciphers: Vec<(ArchiveKey, [u8; SALT_LEN], Aes256Gcm)>
+}
+
+#[cfg(feature = "encryption")]
+impl EntryKeys {
+    pub fn open(&mut self, key: &ArchiveKey, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < ENTRY_HEADER_LEN + TAG_LEN as usize {
+            return Err(ResourceLibraryError::AuthenticationFailed);
+        }
+
+        let (salt, rest) = sealed.split_at(SALT_LEN);
+        let (nonce, data) = rest.split_at(NONCE_LEN);
+
+        let position = match self.ciphers.iter().position(|(k, s, _)| k == key && s == salt) {
+            Some(position) => position,
+            None => {
+                self.ciphers.push((key.clone(), salt.try_into().unwrap(), Aes256Gcm::new(&key.derive(salt).into())));
+                self.ciphers.len() - 1
+            }
+        };
+
+        self.ciphers[position].2.decrypt(Nonce::from_slice(nonce), data).map_err(|_| ResourceLibraryError::AuthenticationFailed)
+    }
+}
+
+// How much of an encrypted data section of the given stored length is actual data
+pub(crate) fn plain_len(sealed_len: u64) -> u64 {
+    sealed_len - sealed_len.div_ceil(SEALED_SEGMENT_SIZE) * TAG_LEN
+}
+
+// Without the encryption feature there's no way to make a key, so none of the ciphers can exist either. Archives and
+// entries that were encrypted elsewhere fail with EncryptionNotEnabled before a cipher would be needed.
+#[cfg(not(feature = "encryption"))]
+mod disabled {
+    use crate::resource_library::Result;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(crate) enum ArchiveKey {}
+
+    pub(crate) enum ArchiveCipher {}
+
+    impl ArchiveCipher {
+        pub fn new(key: &ArchiveKey) -> ArchiveCipher {
+            match *key {}
+        }
+
+        pub fn seal_index(&self, _index_data: &[u8]) -> Result<Box<[u8]>> {
+            match *self {}
+        }
+
+        pub fn open_index(key: &ArchiveKey, _stored: &[u8]) -> Result<(ArchiveCipher, Vec<u8>)> {
+            match *key {}
+        }
+
+        pub fn seal_segment(&self, _segment: u64, _data: &[u8]) -> Result<Vec<u8>> {
+            match *self {}
+        }
+
+        pub fn open_segment(&self, _segment: u64, _data: &[u8]) -> Result<Vec<u8>> {
+            match *self {}
+        }
+    }
+
+    pub(crate) enum EntryCipher {}
+
+    impl EntryCipher {
+        pub fn new(key: &ArchiveKey) -> EntryCipher {
+            match *key {}
+        }
+
+        pub fn seal(&self, _data: &[u8]) -> Result<Vec<u8>> {
+            match *self {}
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct EntryKeys;
+
+    impl EntryKeys {
+        pub fn open(&mut self, key: &ArchiveKey, _sealed: &[u8]) -> Result<Vec<u8>> {
+            match *key {}
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) use disabled::{ArchiveCipher, ArchiveKey, EntryCipher, EntryKeys};
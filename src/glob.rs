@@ -0,0 +1,198 @@
+// Glob patterns over archive paths. * and ? never match a slash, ** as a whole component matches any
+// number of directories, and [abc], [a-z] and [!abc] match one character from a class. A backslash
+// takes the next character literally.
+use crate::resource_library::{PathError, Result};
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(char),
+    AnyRun,
+    AnyChar,
+    Class(bool, Vec<(char, char)>)
+}
+
+#[derive(Clone, Debug)]
+enum Component {
+    AnyDirs,
+    Segment(Vec<Token>)
+}
+
+#[derive(Clone, Debug)]
+pub struct Glob {
+    pattern: String,
+    components: Vec<Component>,
+    case_insensitive: bool
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob> {
+        let invalid = |reason: &str| PathError::InvalidPattern(pattern.to_string(), reason.to_string());
+
+        if pattern.is_empty() {
+            return Err(invalid("pattern is empty").into());
+        }
+
+        let mut components = Vec::new();
+        for component in pattern.split('/') {
+            if component.is_empty() {
+                return Err(invalid("pattern has an empty component").into());
+            }
+
+            if component == "**" {
+                components.push(Component::AnyDirs);
+                continue;
+            }
+
+            let mut tokens = Vec::new();
+            let mut chars = component.chars().peekable();
+            while let Some(c) = chars.next() {
+                let token = match c {
+                    '*' if chars.peek() == Some(&'*') => return Err(invalid("** has to be a whole component").into()),
+                    '*' => Token::AnyRun,
+                    '?' => Token::AnyChar,
+                    '\\' => Token::Literal(chars.next().ok_or_else(|| invalid("pattern ends with a backslash"))?),
+                    '[' => {
+                        let negated = chars.next_if(|&c| c == '!').is_some();
+                        let mut ranges = Vec::new();
+                        loop {
+                            let start = match chars.next() {
+                                Some(']') if !ranges.is_empty() => break,
+                                Some('\\') => chars.next(),
+                                c => c
+                            }.ok_or_else(|| invalid("character class isn't closed"))?;
+
+                            let end = match chars.next_if_eq(&'-') {
+                                Some(_) => match chars.next() {
+                                    Some('\\') => chars.next(),
+                                    c => c
+                                }.ok_or_else(|| invalid("character class isn't closed"))?,
+                                None => start
+                            };
+                            if end < start {
+                                return Err(invalid("character range is backwards").into());
+                            }
+
+                            ranges.push((start, end));
+                        }
+
+                        Token::Class(negated, ranges)
+                    },
+                    c => Token::Literal(c)
+                };
+
+                tokens.push(token);
+            }
+
+            components.push(Component::Segment(tokens));
+        }
+
+        Ok(Glob { pattern: pattern.to_string(), components, case_insensitive: false })
+    }
+
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Glob {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // The literal text every match has to start with, for narrowing a sorted list of paths down before matching.
+    // Case insensitive globs could start with anything.
+    pub fn literal_prefix(&self) -> String {
+        let mut prefix = String::new();
+        if self.case_insensitive {
+            return prefix;
+        }
+
+        for (i, component) in self.components.iter().enumerate() {
+            let Component::Segment(tokens) = component else {
+                break;
+            };
+
+            if i > 0 {
+                prefix.push('/');
+            }
+            for token in tokens {
+                match token {
+                    Token::Literal(c) => prefix.push(*c),
+                    _ => return prefix
+                }
+            }
+        }
+
+        prefix
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let path: Vec<_> = path.split('/').collect();
+        self.match_components(&self.components, &path)
+    }
+
+    fn match_components(&self, components: &[Component], path: &[&str]) -> bool {
+        match components.split_first() {
+            None => path.is_empty(),
+            // A trailing ** needs something under the directory, so "shaders/**" doesn't match a file called shaders
+            Some((Component::AnyDirs, [])) => !path.is_empty(),
+            Some((Component::AnyDirs, rest)) => (0..=path.len()).any(|skip| self.match_components(rest, &path[skip..])),
+            Some((Component::Segment(tokens), rest)) => match path.split_first() {
+                Some((name, path)) => self.match_segment(tokens, name) && self.match_components(rest, path),
+                None => false
+            }
+        }
+    }
+
+    fn match_segment(&self, tokens: &[Token], name: &str) -> bool {
+        let name: Vec<_> = name.chars().collect();
+        let (mut t, mut n) = (0, 0);
+        // Where to pick back up if what followed the last * stops matching
+        let mut backtrack = None;
+
+        while n < name.len() {
+            match tokens.get(t) {
+                Some(Token::AnyRun) => {
+                    backtrack = Some((t, n));
+                    t += 1;
+                    continue;
+                },
+                Some(token) if self.match_char(token, name[n]) => {
+                    t += 1;
+                    n += 1;
+                    continue;
+                },
+                _ => ()
+            }
+
+            match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    t = star + 1;
+                    n = start + 1;
+                },
+                None => return false
+            }
+        }
+
+        tokens[t..].iter().all(|token| matches!(token, Token::AnyRun))
+    }
+
+    fn match_char(&self, token: &Token, c: char) -> bool {
+        match token {
+            Token::Literal(literal) if self.case_insensitive => literal.to_lowercase().eq(c.to_lowercase()),
+            Token::Literal(literal) => *literal == c,
+            Token::AnyChar => true,
+            Token::Class(negated, ranges) => {
+                let in_class = |c: char| ranges.iter().any(|&(start, end)| (start..=end).contains(&c));
+                let found = if self.case_insensitive {
+                    in_class(c) || c.to_lowercase().any(in_class) || c.to_uppercase().any(in_class)
+                } else {
+                    in_class(c)
+                };
+
+                found != *negated
+            },
+            Token::AnyRun => false
+        }
+    }
+}
@@ -1,14 +1,18 @@
-use std::fmt::Display;
+use std::{collections::{BTreeMap, BTreeSet}, fmt::Display};
 
-use serde::{ser::{Impossible, SerializeSeq, SerializeTuple}, Deserialize, Deserializer, Serializer};
+use serde::{de::IntoDeserializer, ser::{Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeTuple}, Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
+use crate::{codec::CODEC_LZMA, resource_library::ResourcePath};
+
 #[derive(Error, Debug)]
 pub enum SerializationError {
     #[error("serialization error: {0}")]
     SerializeError(String),
     #[error("deserialization error: {0}")]
-    DeserializeError(String)
+    DeserializeError(String),
+    #[error("{0} doesn't fit in a compact index")]
+    CompactOverflow(u64)
 }
 
 impl serde::ser::Error for SerializationError {
@@ -23,13 +27,134 @@ impl serde::de::Error for SerializationError {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub path: ResourcePath,
+    pub offset: u64,
+    pub compressed_len: u64,
+    pub codec: u8,
+    // Chunked entries are split into independently compressed blocks of block_size uncompressed bytes,
+    // with blocks holding each block's compressed length. A block size of 0 means the entry is one blob.
+    #[serde(default)]
+    pub block_size: u64,
+    #[serde(default)]
+    pub blocks: Vec<u64>,
+    // Which part of a multi-volume archive holds the entry's data, with the first volume being 0
+    #[serde(default)]
+    pub volume: u64,
+    // Length of the entry once decompressed. Entries from before version 5 don't have it, and keep UNKNOWN_LEN
+    // even after being carried over into a newer archive, since finding it would mean decompressing them.
+    #[serde(default = "unknown_len")]
+    pub original_len: u64,
+    // CRC32 of the uncompressed entry, or NO_CRC for entries from archives that predate it
+    #[serde(default = "no_crc")]
+    pub crc32: u64,
+    // SHA-256 of the uncompressed entry, empty unless the writer was asked to store hashes
+    #[serde(default)]
+    pub sha256: Vec<u8>,
+    // Modification time in seconds since the unix epoch, or NO_MTIME for entries that weren't given one
+    #[serde(default = "no_mtime")]
+    pub mtime: u64,
+    // Unix permission bits, or NO_MODE for entries that didn't come from a unix filesystem. Kept as a u64
+    // since the index has no narrower integers besides u8.
+    #[serde(default = "no_mode")]
+    pub mode: u64,
+    // Arbitrary key/value pairs attached by the writer
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Box<[u8]>>,
+    // MIME type of the entry, empty if it's unknown
+    #[serde(default)]
+    pub content_type: String,
+    // Per-entry feature bits. Unknown bits within ENTRY_FLAGS_CRITICAL change how the entry has to be read,
+    // so archives using them are rejected, while unknown bits outside of it are safe to ignore.
+    #[serde(default)]
+    pub flags: u16,
+    // Path of the entry an alias shares its data with, empty for everything else
+    #[serde(default)]
+    pub target: String
+}
+
+pub(crate) const UNKNOWN_LEN: u64 = u64::MAX;
+pub(crate) const NO_CRC: u64 = u64::MAX;
+pub(crate) const NO_MTIME: u64 = u64::MAX;
+pub(crate) const NO_MODE: u64 = u64::MAX;
+
+// The entry is split into independently compressed blocks, see block_size
+pub(crate) const ENTRY_CHUNKED: u16 = 0x0001;
+// The entry's data is sealed with its own key after it's compressed
+pub(crate) const ENTRY_ENCRYPTED: u16 = 0x0002;
+pub(crate) const ENTRY_FLAGS_CRITICAL: u16 = 0x00FF;
+// The entry is an alias of target. Its location is copied from the target, so readers that don't know about
+// aliases still read it correctly.
+pub(crate) const ENTRY_ALIAS: u16 = 0x0100;
+pub(crate) const KNOWN_ENTRY_FLAGS: u16 = ENTRY_CHUNKED | ENTRY_ENCRYPTED | ENTRY_ALIAS;
+
+fn unknown_len() -> u64 {
+    UNKNOWN_LEN
+}
+
+fn no_crc() -> u64 {
+    NO_CRC
+}
+
+fn no_mtime() -> u64 {
+    NO_MTIME
+}
+
+fn no_mode() -> u64 {
+    NO_MODE
+}
+
+impl IndexEntry {
+    // An entry with nothing set besides its path, and sentinels for the fields that have them
+    pub fn new(path: ResourcePath) -> IndexEntry {
+        IndexEntry { path, offset: 0, compressed_len: 0, codec: 0, block_size: 0, blocks: Vec::new(), volume: 0, original_len: UNKNOWN_LEN, crc32: NO_CRC, sha256: Vec::new(), mtime: NO_MTIME, mode: NO_MODE, metadata: BTreeMap::new(), content_type: String::new(), flags: 0, target: String::new() }
+    }
+}
+
+impl TryFrom<(String, u64, u64)> for IndexEntry {
+    type Error = SerializationError;
+
+    fn try_from((path, offset, compressed_len): (String, u64, u64)) -> Result<Self, SerializationError> {
+        let path = ResourcePath::new(path).map_err(|err| SerializationError::DeserializeError(err.to_string()))?;
+
+        // Archives from before the codec byte existed are always LZMA
+        Ok(IndexEntry { offset, compressed_len, codec: CODEC_LZMA, ..IndexEntry::new(path) })
+    }
+}
+
+// Archives from this version on front-code entry paths: each one is stored as the length of the prefix it
+// shares with the previous entry's path, followed by the rest of it
+pub(crate) const FRONT_CODING_VERSION: u16 = 8;
+
+// Paths are rebuilt from the previous one when they're front-coded, so without a cap a small index could describe
+// paths that take up far more memory than the index itself
+pub(crate) const MAX_PATH_LEN: usize = 64 * 1024;
+
 pub struct IndexSerializer {
-    buffer: Vec<u8>
+    buffer: Vec<u8>,
+    front_coding: bool,
+    // Compact indexes store every u64 in 32 bits, with u64::MAX kept as u32::MAX so sentinels survive
+    compact: bool,
+    last_path: String
 }
 
 impl IndexSerializer {
     pub fn new() -> IndexSerializer {
-        IndexSerializer { buffer: Vec::new() }
+        IndexSerializer { buffer: Vec::new(), front_coding: true, compact: false, last_path: String::new() }
+    }
+
+    pub fn compact() -> IndexSerializer {
+        IndexSerializer { compact: true, ..IndexSerializer::new() }
+    }
+
+    fn int_size(&self) -> usize {
+        if self.compact { std::mem::size_of::<u32>() } else { std::mem::size_of::<u64>() }
+    }
+
+    // Writes paths in full, like archives from before FRONT_CODING_VERSION
+    pub fn plain() -> IndexSerializer {
+        IndexSerializer { front_coding: false, ..IndexSerializer::new() }
     }
 
     pub fn take(self) -> Box<[u8]> {
@@ -51,9 +176,9 @@ impl<'a> Serializer for &'a mut IndexSerializer {
 
     type SerializeTupleVariant = Impossible<(), Self::Error>;
 
-    type SerializeMap = Impossible<(), Self::Error>;
+    type SerializeMap = Self;
 
-    type SerializeStruct = Impossible<(), Self::Error>;
+    type SerializeStruct = StructSerializer<'a>;
 
     type SerializeStructVariant = Impossible<(), Self::Error>;
 
@@ -78,11 +203,15 @@ impl<'a> Serializer for &'a mut IndexSerializer {
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        self.buffer.push(v);
+
+        Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        self.buffer.extend(v.to_be_bytes());
+
+        Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
@@ -90,7 +219,12 @@ impl<'a> Serializer for &'a mut IndexSerializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.buffer.extend(v.to_be_bytes());
+        match v {
+            _ if !self.compact => self.buffer.extend(v.to_be_bytes()),
+            u64::MAX => self.buffer.extend(u32::MAX.to_be_bytes()),
+            v if v < u32::MAX as u64 => self.buffer.extend((v as u32).to_be_bytes()),
+            v => return Err(SerializationError::CompactOverflow(v))
+        }
 
         Ok(())
     }
@@ -118,14 +252,18 @@ impl<'a> Serializer for &'a mut IndexSerializer {
         Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
     }
 
+    // Options are a tag byte, followed by the value when there is one
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        self.buffer.push(0);
+
+        Ok(())
     }
 
     fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        self.buffer.push(1);
+        value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
@@ -199,7 +337,12 @@ impl<'a> Serializer for &'a mut IndexSerializer {
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        if len.is_none() {
+            return Err(SerializationError::SerializeError("unsupported serialization".to_owned()));
+        }
+
+        self.serialize_u64(len.unwrap() as u64)?;
+        Ok(self)
     }
 
     fn serialize_struct(
@@ -207,7 +350,11 @@ impl<'a> Serializer for &'a mut IndexSerializer {
         name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Err(SerializationError::SerializeError("unsupported serialization".to_owned()))
+        // Structs are length prefixed so fields can be added later without breaking older readers
+        let start = self.buffer.len();
+        self.serialize_u64(0)?;
+
+        Ok(StructSerializer { ser: self, start })
     }
 
     fn serialize_struct_variant(
@@ -254,16 +401,98 @@ impl<'a> SerializeTuple for &'a mut IndexSerializer {
     }
 }
 
+impl<'a> SerializeMap for &'a mut IndexSerializer {
+    type Ok = ();
+
+    type Error = SerializationError;
+
+    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: serde::Serialize {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: serde::Serialize {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+pub struct StructSerializer<'a> {
+    ser: &'a mut IndexSerializer,
+    start: usize
+}
+
+impl<'a> SerializeStruct for StructSerializer<'a> {
+    type Ok = ();
+
+    type Error = SerializationError;
+
+    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: serde::Serialize {
+        if key != "path" || !self.ser.front_coding {
+            return value.serialize(&mut *self.ser);
+        }
+
+        // Get at the path by serializing it on its own, which leaves its length followed by its bytes
+        let mut plain = IndexSerializer::plain();
+        value.serialize(&mut plain)?;
+        let path = std::str::from_utf8(&plain.buffer[std::mem::size_of::<u64>()..]).map_err(|_| SerializationError::SerializeError("UTF-8 Error".to_owned()))?;
+
+        let shared = path.char_indices().zip(self.ser.last_path.chars()).take_while(|((_, a), b)| a == b).last().map_or(0, |((i, c), _)| i + c.len_utf8());
+        self.ser.serialize_u64(shared as u64)?;
+        self.ser.serialize_str(&path[shared..])?;
+        self.ser.last_path = path.to_owned();
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        let int_size = self.ser.int_size();
+        let len = (self.ser.buffer.len() - self.start - int_size) as u64;
+
+        // Serializing the length again puts it at the end of the buffer, from where it's moved into place
+        let end = self.ser.buffer.len();
+        self.ser.serialize_u64(len)?;
+        self.ser.buffer.copy_within(end.., self.start);
+        self.ser.buffer.truncate(end);
+
+        Ok(())
+    }
+}
+
 pub struct IndexDeserializer<'de> {
-    buffer: &'de [u8]
+    buffer: &'de [u8],
+    front_coding: bool,
+    compact: bool,
+    last_path: String
 }
 
 impl<'de> IndexDeserializer<'de> {
     pub fn new(data: &'de [u8]) -> IndexDeserializer<'de> {
-        IndexDeserializer { buffer: data }
+        IndexDeserializer { buffer: data, front_coding: false, compact: false, last_path: String::new() }
+    }
+
+    pub fn front_coded(data: &'de [u8]) -> IndexDeserializer<'de> {
+        IndexDeserializer { front_coding: true, ..IndexDeserializer::new(data) }
     }
 
     pub fn next_u64(&mut self) -> Result<u64, SerializationError> {
+        if self.compact {
+            let bytes = self.next_bytes(std::mem::size_of::<u32>() as u64)?;
+
+            return match u32::from_be_bytes(bytes.try_into().unwrap()) {
+                u32::MAX => Ok(u64::MAX),
+                value => Ok(value as u64)
+            };
+        }
+
         if self.buffer.len() < std::mem::size_of::<u64>() {
             return Err(SerializationError::DeserializeError("EOF".to_owned()));
         }
@@ -275,10 +504,53 @@ impl<'de> IndexDeserializer<'de> {
         Ok(value)
     }
 
+    pub fn next_u16(&mut self) -> Result<u16, SerializationError> {
+        let bytes = self.next_bytes(std::mem::size_of::<u16>() as u64)?;
+
+        Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn next_u8(&mut self) -> Result<u8, SerializationError> {
+        let (&value, rest) = self.buffer.split_first().ok_or(SerializationError::DeserializeError("EOF".to_owned()))?;
+        self.buffer = rest;
+
+        Ok(value)
+    }
+
+    pub fn next_bytes(&mut self, len: u64) -> Result<&'de [u8], SerializationError> {
+        if (self.buffer.len() as u64) < len {
+            return Err(SerializationError::DeserializeError("EOF".to_owned()));
+        }
+
+        let (bytes, rest) = self.buffer.split_at(len as usize);
+        self.buffer = rest;
+
+        Ok(bytes)
+    }
+
+    // Every element takes at least a byte, so a count larger than what's left can't be right
+    fn next_len(&mut self) -> Result<u64, SerializationError> {
+        let len = self.next_u64()?;
+        if len > self.buffer.len() as u64 {
+            return Err(SerializationError::DeserializeError(format!("Declared {} elements, but only {} bytes are left", len, self.buffer.len())));
+        }
+
+        Ok(len)
+    }
+
+    // Whatever follows the index wasn't written by the serializer
+    fn finish(&self) -> Result<(), SerializationError> {
+        match self.buffer.len() {
+            0 => Ok(()),
+            len => Err(SerializationError::DeserializeError(format!("{} trailing bytes after the index", len)))
+        }
+    }
+
     pub fn next_str(&mut self) -> Result<&str, SerializationError> {
+        // Compared as a u64 so that a huge length can't wrap around to a small one on 32-bit targets
         let len = self.next_u64()?;
-        if self.buffer.len() < len as usize {
-            return Err(SerializationError::DeserializeError("EOF".to_owned()));
+        if (self.buffer.len() as u64) < len {
+            return Err(SerializationError::DeserializeError(format!("String length {} is longer than the {} bytes left", len, self.buffer.len())));
         }
 
         let bytes = &self.buffer[..len as usize];
@@ -333,13 +605,13 @@ impl<'de, 'a> Deserializer<'de> for &'a mut IndexDeserializer<'de> {
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        Err(SerializationError::DeserializeError("unsupported deserialization".to_owned()))
+        visitor.visit_u8(self.next_u8()?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        Err(SerializationError::DeserializeError("unsupported deserialization".to_owned()))
+        visitor.visit_u16(self.next_u16()?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -399,7 +671,11 @@ impl<'de, 'a> Deserializer<'de> for &'a mut IndexDeserializer<'de> {
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        Err(SerializationError::DeserializeError("unsupported deserialization".to_owned()))
+        match self.next_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            tag => Err(SerializationError::DeserializeError(format!("Invalid option tag {}", tag)))
+        }
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -431,7 +707,7 @@ impl<'de, 'a> Deserializer<'de> for &'a mut IndexDeserializer<'de> {
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        let len = self.next_u64()?;
+        let len = self.next_len()?;
 
         visitor.visit_seq(SeqAccess::new(self, len))
     }
@@ -456,7 +732,9 @@ impl<'de, 'a> Deserializer<'de> for &'a mut IndexDeserializer<'de> {
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        Err(SerializationError::DeserializeError("unsupported deserialization".to_owned()))
+        let len = self.next_len()?;
+
+        visitor.visit_map(SeqAccess::new(self, len))
     }
 
     fn deserialize_struct<V>(
@@ -467,7 +745,13 @@ impl<'de, 'a> Deserializer<'de> for &'a mut IndexDeserializer<'de> {
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de> {
-        Err(SerializationError::DeserializeError("unsupported deserialization".to_owned()))
+        let len = self.next_u64()?;
+        let mut de = IndexDeserializer { compact: self.compact, ..IndexDeserializer::new(self.next_bytes(len)?) };
+        let last_path = self.front_coding.then_some(&mut self.last_path);
+
+        // Fields missing from the end of the struct were written by an older version and fall back to their
+        // defaults, and any extra bytes at the end were written by a newer version and are skipped
+        visitor.visit_seq(StructAccess { de: &mut de, fields, next: 0, last_path })
     }
 
     fn deserialize_enum<V>(
@@ -521,8 +805,112 @@ impl<'a, 'de> serde::de::SeqAccess<'de> for SeqAccess<'a, 'de> {
     }
 }
 
+// Maps are written as their length followed by each key and value in turn
+impl<'a, 'de> serde::de::MapAccess<'de> for SeqAccess<'a, 'de> {
+    type Error = SerializationError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: serde::de::DeserializeSeed<'de> {
+        if self.len == self.pos {
+            return Ok(None);
+        }
+
+        self.pos += 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::DeserializeSeed<'de> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+struct StructAccess<'a, 'de: 'a> {
+    de: &'a mut IndexDeserializer<'de>,
+    fields: &'static [&'static str],
+    next: usize,
+    // The previous entry's path when paths are front-coded
+    last_path: Option<&'a mut String>
+}
+
+impl<'a, 'de> serde::de::SeqAccess<'de> for StructAccess<'a, 'de> {
+    type Error = SerializationError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: serde::de::DeserializeSeed<'de> {
+        if self.next == self.fields.len() || self.de.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let field = self.fields[self.next];
+        self.next += 1;
+
+        match &mut self.last_path {
+            Some(last_path) if field == "path" => {
+                let shared = self.de.next_u64()? as usize;
+                let prefix = last_path.get(..shared).ok_or(SerializationError::DeserializeError("Bad shared path prefix".to_owned()))?;
+                let rest = self.de.next_str()?;
+                check_path_len(prefix.len() + rest.len())?;
+                let path = prefix.to_owned() + rest;
+                **last_path = path.clone();
+
+                seed.deserialize(path.into_deserializer()).map(Some)
+            },
+            _ => seed.deserialize(&mut *self.de).map(Some)
+        }
+    }
+}
+
+pub fn entries_to_bytes(entries: &[IndexEntry], compact: bool) -> Result<Box<[u8]>, SerializationError> {
+    let mut serializer = if compact { IndexSerializer::compact() } else { IndexSerializer::new() };
+    entries.serialize(&mut serializer)?;
+
+    Ok(serializer.take())
+}
+
+pub fn entries_from_bytes(bytes: &[u8], version: u16, compact: bool) -> Result<Box<[IndexEntry]>, SerializationError> {
+    let mut deserializer = match version {
+        FRONT_CODING_VERSION.. => IndexDeserializer::front_coded(bytes),
+        _ => IndexDeserializer::new(bytes)
+    };
+    deserializer.compact = compact;
+
+    let entries = Box::<[IndexEntry]>::deserialize(&mut deserializer)?;
+    deserializer.finish()?;
+    check_paths(entries.iter().map(|entry| &entry.path[..]))?;
+
+    Ok(entries)
+}
+
 pub fn index_from_bytes(bytes: &[u8]) -> Result<Box<[(String, u64, u64)]>, SerializationError> {
     let mut deserializer = IndexDeserializer::new(bytes);
-    
-    Box::<[(String, u64, u64)]>::deserialize(&mut deserializer)
+
+    let index = Box::<[(String, u64, u64)]>::deserialize(&mut deserializer)?;
+    deserializer.finish()?;
+    check_paths(index.iter().map(|(path, _, _)| &path[..]))?;
+
+    Ok(index)
+}
+
+fn check_path_len(len: usize) -> Result<(), SerializationError> {
+    match len {
+        0..=MAX_PATH_LEN => Ok(()),
+        _ => Err(SerializationError::DeserializeError(format!("Path length {} is over the limit of {}", len, MAX_PATH_LEN)))
+    }
+}
+
+// Entries are looked up with a binary search, which can't tell entries with the same path apart
+fn check_paths<'a>(paths: impl Iterator<Item = &'a str>) -> Result<(), SerializationError> {
+    let mut seen = BTreeSet::new();
+    for path in paths {
+        check_path_len(path.len())?;
+        if !seen.insert(path) {
+            return Err(SerializationError::DeserializeError(format!("Duplicate path in index: {}", path)));
+        }
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -1,17 +1,36 @@
 pub mod resource_library;
+pub mod codec;
+pub mod archive_editor;
+pub mod content_type;
+pub mod glob;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(not(feature = "encryption"))]
+mod encryption;
+#[cfg(feature = "signing")]
+pub mod signing;
 mod index_serialization;
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::{File, OpenOptions}, io::Write, sync::Mutex};
+    use std::{fs::{File, OpenOptions}, io::{BufRead, Cursor, Read, Seek, SeekFrom, Write}, ops::{Bound, RangeBounds}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
 
     use resource_library::Result;
     use serde::Serialize;
     
 
-    use crate::resource_library::{CompressionLevel, ResourceLibraryReader};
+    use crate::archive_editor::{compact, migrate, ArchiveEditor};
+    use crate::codec::{Codec, CompressionCodec};
+    #[cfg(feature = "encryption")]
+    use crate::encryption::ArchiveKey;
+    use crate::glob::Glob;
+    #[cfg(feature = "signing")]
+    use crate::signing::{sign, signature_path, SigningKey};
+    #[cfg(feature = "hashes")]
+    use sha2::{Digest, Sha256};
+    use crate::resource_library::{MissingPolicy, ArchiveSummary, EntrySizes, CaseCollisions, CompressionLevel, ConflictPolicy, DirEntry, PathError, ReaderOptions, ResourceLibraryError, ResourceLibraryReader, SyncPolicy, Layout, Normalize, OffsetSize, PathPolicy, ResourcePath, is_valid_resource_path, EMBED_FOOTER_SIZE, FORMAT_VERSION, HEADER_SIZE};
 
-    use self::{index_serialization::{index_from_bytes, IndexSerializer}, resource_library::{ByteStream, ResourceLibraryWriter}};
+    use self::{index_serialization::{entries_from_bytes, entries_to_bytes, index_from_bytes, IndexDeserializer, IndexEntry, IndexSerializer, SerializationError}, resource_library::{ByteStream, ResourceLibraryWriter}};
 
     use super::*;
 
@@ -82,6 +101,4189 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn remove_file() -> Result<()> {
+        let _guard = FILE_LOCK.lock().unwrap();
+        let mut lib1 = ResourceLibraryWriter::new();
+
+        lib1.write_stream("test/a.txt".to_owned(), ByteStream::from(b"Test file A".to_vec()))?;
+        lib1.write_stream("test/old/b.txt".to_owned(), ByteStream::from(b"Test file B".to_vec()))?;
+        lib1.write_stream("test/old/c.txt".to_owned(), ByteStream::from(b"Test file C".to_vec()))?;
+        lib1.write_stream("test/older.txt".to_owned(), ByteStream::from(b"Test file D".to_vec()))?;
+
+        let mut removed = lib1.remove_file("test/a.txt")?;
+        let mut data = Vec::new();
+        removed.read_to_end(&mut data)?;
+        assert_eq!(data, b"Test file A");
+
+        lib1.remove_file("test/a.txt").expect_err("Path should no longer exist!");
+        lib1.remove_file("test/a?.txt").expect_err("Path should be invalid!");
+
+        assert_eq!(lib1.remove_prefix("test/old/"), 2);
+        assert_eq!(lib1.remove_prefix("test/old/"), 0);
+        assert_eq!(&*lib1.get_all_files(), ["test/older.txt"]);
+
+        lib1.write_stream("test/a.txt".to_owned(), ByteStream::from(b"New file A".to_vec()))?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/test.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/test.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), ["test/a.txt", "test/older.txt"]);
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"New file A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_file() -> Result<()> {
+        let mut lib = ResourceLibraryWriter::new();
+
+        lib.write_stream("ui/icons/a.png".to_owned(), ByteStream::from(b"icon A".to_vec()))?;
+        lib.write_stream("ui/icons/b.png".to_owned(), ByteStream::from(b"icon B".to_vec()))?;
+        lib.write_stream("ui/font.ttf".to_owned(), ByteStream::from(b"font".to_vec()))?;
+        lib.write_stream("hud/b.png".to_owned(), ByteStream::from(b"hud B".to_vec()))?;
+
+        lib.rename_file("ui/font.ttf", "hud/font.ttf")?;
+        assert_eq!(&*lib.read_data("hud/font.ttf")?, b"font");
+        lib.read_data("ui/font.ttf").expect_err("Path should no longer exist!");
+
+        lib.rename_file("ui/icons/a.png", "hud/b.png").expect_err("Destination should already exist!");
+        lib.rename_file("ui/missing.png", "hud/missing.png").expect_err("Source should not exist!");
+        lib.rename_file("ui/icons/a.png", "hud/a?.png").expect_err("Destination should be invalid!");
+
+        lib.rename_prefix("ui/icons/", "hud/").expect_err("hud/b.png should collide!");
+        assert_eq!(&*lib.get_all_files(), ["hud/b.png", "hud/font.ttf", "ui/icons/a.png", "ui/icons/b.png"]);
+
+        assert_eq!(lib.rename_prefix("ui/icons/", "hud/icons/")?, 2);
+        assert_eq!(&*lib.get_all_files(), ["hud/b.png", "hud/font.ttf", "hud/icons/a.png", "hud/icons/b.png"]);
+        assert_eq!(&*lib.read_data("hud/icons/b.png")?, b"icon B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn contains_path() -> Result<()> {
+        let mut lib = ResourceLibraryWriter::new();
+
+        lib.write_stream("levels/act1/map.bin".to_owned(), ByteStream::from(b"map 1".to_vec()))?;
+        lib.write_stream("levels/act1/script.lua".to_owned(), ByteStream::from(b"script 1".to_vec()))?;
+        lib.write_stream("levels/act2/map.bin".to_owned(), ByteStream::from(b"map 2".to_vec()))?;
+        lib.write_stream("music/theme.ogg".to_owned(), ByteStream::from(b"theme".to_vec()))?;
+
+        assert!(lib.contains_path("levels/act1/map.bin"));
+        assert!(!lib.contains_path("levels/act1"));
+        assert!(!lib.contains_path("levels/act3/map.bin"));
+        assert!(!lib.contains_path("levels/act1/map?.bin"));
+        assert!(!lib.contains_path(""));
+
+        assert_eq!(&*lib.get_paths_with_prefix("levels/act1/"), ["levels/act1/map.bin", "levels/act1/script.lua"]);
+        assert_eq!(&*lib.get_paths_with_prefix("levels/"), ["levels/act1/map.bin", "levels/act1/script.lua", "levels/act2/map.bin"]);
+        assert_eq!(lib.get_paths_with_prefix("").len(), 4);
+        assert!(lib.get_paths_with_prefix("textures/").is_empty());
+        assert!(lib.get_paths_with_prefix("zzz").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn library_len() -> Result<()> {
+        for count in [0, 1, 5, 20] {
+            let mut lib1 = ResourceLibraryWriter::new();
+            assert!(lib1.is_empty());
+
+            for i in 0..count {
+                lib1.write_stream(format!("test/{}.txt", i), ByteStream::from(format!("Test file {}", i).into_bytes()))?;
+            }
+
+            assert_eq!(lib1.len(), count);
+            assert_eq!(lib1.is_empty(), count == 0);
+
+            let path = format!("test/len_{}.rcslib", count);
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?;
+            lib1.write_to_file(file, CompressionLevel::Fastest)?;
+
+            let lib2 = ResourceLibraryReader::new(&path)?;
+            assert_eq!(lib2.len(), count);
+            assert_eq!(lib2.is_empty(), count == 0);
+
+            let bytes = std::fs::read(&path)?;
+            let data_size = u64::from_be_bytes(bytes[20..28].try_into().unwrap());
+            assert_eq!(lib2.total_compressed_size(), data_size);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn writer_iter() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+
+        lib1.write_stream("test/c.txt".to_owned(), ByteStream::from(b"Test file C".to_vec()))?;
+        lib1.write_stream("test/a.txt".to_owned(), ByteStream::from(b"Test file A".to_vec()))?;
+        lib1.write_stream("test/b.txt".to_owned(), ByteStream::from(b"Test file B".to_vec()))?;
+
+        let paths: Vec<&str> = lib1.iter().map(|(path, _)| path).collect();
+        assert_eq!(&paths[..], &*lib1.get_all_files());
+
+        // Read every stream to the end so write_to_file has to rewind them
+        for (path, resource) in lib1.iter_mut() {
+            let mut data = Vec::new();
+            resource.read_to_end(&mut data)?;
+            assert_eq!(data, format!("Test file {}", &path[5..6].to_uppercase()).into_bytes());
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/iter.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/iter.rcslib")?;
+        for path in ["test/a.txt", "test/b.txt", "test/c.txt"] {
+            assert_eq!(lib2.read_file(path)?, lib1.read_data(path)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_data() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+
+        lib1.write_data("test/vec.bin".to_owned(), vec![0u8, 1, 2, 3])?;
+        lib1.write_data("test/box.bin".to_owned(), vec![4u8, 5, 6].into_boxed_slice())?;
+        lib1.write_str("test/string.txt".to_owned(), &String::from("Test string"))?;
+        lib1.write_str("test/str?.txt".to_owned(), "Test string").expect_err("Path should be invalid!");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/write_data.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/write_data.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/vec.bin")?, [0, 1, 2, 3]);
+        assert_eq!(&*lib2.read_file("test/box.bin")?, [4, 5, 6]);
+        assert_eq!(&*lib2.read_file("test/string.txt")?, b"Test string");
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_file() -> Result<()> {
+        std::fs::create_dir_all("test/add_file")?;
+        std::fs::write("test/add_file/a.txt", "Test file A")?;
+        std::fs::write("test/add_file/empty.txt", "")?;
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.add_file("test/add_file/a.txt", "a.txt".to_owned())?;
+        lib1.add_file("test/add_file/empty.txt", "empty.txt".to_owned())?;
+        lib1.add_file("test/testfile.png", "testfile.png".to_owned())?;
+
+        match lib1.add_file("test/add_file", "dir".to_owned()) {
+            Err(ResourceLibraryError::NotAFile(path)) => assert_eq!(path, std::path::Path::new("test/add_file")),
+            result => panic!("Expected NotAFile, got {:?}", result)
+        }
+
+        match lib1.add_file("test/add_file/missing.txt", "missing.txt".to_owned()) {
+            Err(err @ ResourceLibraryError::DiskFileError(..)) => assert!(err.to_string().contains("test/add_file/missing.txt")),
+            result => panic!("Expected DiskFileError, got {:?}", result)
+        }
+
+        // The file shouldn't be read until the archive is written
+        std::fs::write("test/add_file/a.txt", "Changed file A")?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/add_file.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/add_file.rcslib")?;
+        assert_eq!(&*lib2.read_file("a.txt")?, b"Changed file A");
+        assert_eq!(&*lib2.read_file("empty.txt")?, b"");
+        assert_eq!(lib2.read_file("testfile.png")?, std::fs::read("test/testfile.png")?.into_boxed_slice());
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_dir() -> Result<()> {
+        let root = std::path::Path::new("test/add_dir");
+        if root.exists() {
+            std::fs::remove_dir_all(root)?;
+        }
+
+        std::fs::create_dir_all(root.join("textures/environment/forest"))?;
+        std::fs::create_dir_all(root.join("empty_dir"))?;
+        std::fs::write(root.join("readme.txt"), "Test readme")?;
+        std::fs::write(root.join(".hidden"), "")?;
+        std::fs::write(root.join("textures/environment/forest/tree.png"), "")?;
+        std::fs::write(root.join("textures/environment/forest/grüße.txt"), "Grüße")?;
+        std::fs::write(root.join("textures/日本.txt"), "")?;
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::fs::canonicalize(root.join("textures"))?, root.join("textures/environment/loop"))?;
+            std::os::unix::fs::symlink(std::fs::canonicalize(root.join("readme.txt"))?, root.join("link.txt"))?;
+        }
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.set_follow_symlinks(false);
+        assert_eq!(lib1.add_dir(root, "assets")?, 5);
+        assert_eq!(&*lib1.get_all_files(), [
+            "assets/.hidden",
+            "assets/readme.txt",
+            "assets/textures/environment/forest/grüße.txt",
+            "assets/textures/environment/forest/tree.png",
+            "assets/textures/日本.txt"
+        ]);
+
+        let mut lib2 = ResourceLibraryWriter::new();
+        lib2.set_include_hidden(false);
+        let expected: &[&str] = if cfg!(unix) {
+            &["link.txt", "readme.txt", "textures/environment/forest/grüße.txt", "textures/environment/forest/tree.png", "textures/日本.txt"]
+        } else {
+            &["readme.txt", "textures/environment/forest/grüße.txt", "textures/environment/forest/tree.png", "textures/日本.txt"]
+        };
+        assert_eq!(lib2.add_dir(root, "")?, expected.len());
+        assert_eq!(&*lib2.get_all_files(), expected);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/add_dir.rcslib")?;
+        lib2.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib3 = ResourceLibraryReader::new("test/add_dir.rcslib")?;
+        assert_eq!(&*lib3.get_all_files(), expected);
+        assert_eq!(&*lib3.read_file("textures/environment/forest/grüße.txt")?, "Grüße".as_bytes());
+
+        // Files that are already in the library aren't counted when they're kept
+        let mut lib4 = ResourceLibraryWriter::new();
+        lib4.set_include_hidden(false);
+        lib4.set_duplicate_policy(ConflictPolicy::KeepExisting);
+        lib4.write_str("readme.txt", "Kept")?;
+        assert_eq!(lib4.add_dir(root, "")?, expected.len() - 1);
+        assert_eq!(&*lib4.get_all_files(), expected);
+        assert_eq!(&*lib4.read_data("readme.txt")?, b"Kept");
+
+        Ok(())
+    }
+
+    // Every file is opened as it's written and closed once it's been read, so packing more files than the process
+    // can have open at once has to work. The limit is lowered in a child process so it doesn't affect other tests.
+    #[cfg(unix)]
+    #[test]
+    fn add_dir_file_limit() -> Result<()> {
+        const LIMIT: u64 = 64;
+
+        if std::env::var_os("RESOURCE_PACKAGER_FILE_LIMIT").is_none() {
+            let status = std::process::Command::new(std::env::current_exe()?)
+                .args(["--exact", "tests::add_dir_file_limit", "--test-threads=1"])
+                .env("RESOURCE_PACKAGER_FILE_LIMIT", LIMIT.to_string())
+                .status()?;
+            assert!(status.success());
+
+            return Ok(());
+        }
+
+        let root = std::path::Path::new("test/file_limit");
+        if root.exists() {
+            std::fs::remove_dir_all(root)?;
+        }
+        std::fs::create_dir_all(root)?;
+
+        // Half of the files span several blocks so they're written in chunks
+        let count = LIMIT as usize * 4;
+        for n in 0..count {
+            let data = if n % 2 == 0 { format!("small file {}", n) } else { format!("chunked file {} ", n).repeat(8) };
+            std::fs::write(root.join(format!("{:04}.txt", n)), data)?;
+        }
+
+        unsafe {
+            let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+            assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit), 0);
+            limit.rlim_cur = LIMIT as libc::rlim_t;
+            assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &limit), 0);
+        }
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.set_block_size(Some(64));
+        lib1.set_detect_content_types(true);
+        assert_eq!(lib1.add_dir(root, "")?, count);
+        let data = lib1.write_to_vec(CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::from_bytes(data)?;
+        assert_eq!(lib2.len(), count);
+        assert_eq!(&*lib2.read_file("0000.txt")?, b"small file 0");
+        assert_eq!(&*lib2.read_file("0001.txt")?, "chunked file 1 ".repeat(8).as_bytes());
+        assert_eq!(lib2.content_type("0001.txt"), Some("text/plain"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<()> {
+        let build = |files: &[(&str, &str)]| -> Result<ResourceLibraryWriter> {
+            let mut lib = ResourceLibraryWriter::new();
+            for (path, data) in files {
+                lib.write_str(path.to_string(), data)?;
+            }
+
+            Ok(lib)
+        };
+
+        let ui = [("ui/button.png", "ui button"), ("shared/font.ttf", "ui font")];
+        let audio = [("audio/theme.ogg", "audio theme"), ("shared/font.ttf", "audio font")];
+
+        let mut lib = build(&ui)?;
+        let report = lib.merge(build(&audio)?, ConflictPolicy::KeepExisting)?;
+        assert_eq!(report.skipped, ["shared/font.ttf"]);
+        assert!(report.replaced.is_empty());
+        assert_eq!(&*lib.get_all_files(), ["audio/theme.ogg", "shared/font.ttf", "ui/button.png"]);
+        assert_eq!(&*lib.read_data("shared/font.ttf")?, b"ui font");
+
+        let mut lib = build(&ui)?;
+        let report = lib.merge(build(&audio)?, ConflictPolicy::Overwrite)?;
+        assert_eq!(report.replaced, ["shared/font.ttf"]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(&*lib.read_data("shared/font.ttf")?, b"audio font");
+
+        let mut lib = build(&ui)?;
+        lib.merge(build(&audio)?, ConflictPolicy::Error).expect_err("shared/font.ttf should conflict!");
+        assert_eq!(&*lib.get_all_files(), ["shared/font.ttf", "ui/button.png"]);
+
+        lib.merge(build(&[("levels/1.bin", "level 1")])?, ConflictPolicy::Error)?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/merge.rcslib")?;
+        lib.write_to_file(file, CompressionLevel::Fast)?;
+
+        let lib2 = ResourceLibraryReader::new("test/merge.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), ["levels/1.bin", "shared/font.ttf", "ui/button.png"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clear() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+
+        lib1.write_str("build1/a.txt".to_owned(), "Build 1 A")?;
+        lib1.write_str("build1/b.txt".to_owned(), "Build 1 B")?;
+        lib1.write_str("shared/c.txt".to_owned(), "Build 1 C")?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/clear.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        lib1.add_alias("build1/c.txt".to_owned(), "shared/c.txt")?;
+        lib1.add_alias("shared/d.txt".to_owned(), "build1/c.txt")?;
+        assert_eq!(lib1.clear_prefix("build1/"), 2);
+        assert_eq!(lib1.clear_prefix("build1/"), 0);
+        assert_eq!(&*lib1.get_all_files(), ["shared/c.txt"]);
+
+        // The aliases under the prefix, and the ones that led to them, went with the entries
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/clear.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let lib2 = ResourceLibraryReader::new("test/clear.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), ["shared/c.txt"]);
+        assert_eq!(lib2.alias_target("build1/c.txt"), None);
+        assert_eq!(lib2.alias_target("shared/d.txt"), None);
+
+        lib1.clear();
+        assert!(lib1.is_empty());
+
+        lib1.write_str("build2/a.txt".to_owned(), "Build 2 A")?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/clear.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/clear.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), ["build2/a.txt"]);
+        assert_eq!(&*lib2.read_file("build2/a.txt")?, b"Build 2 A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_stream() -> Result<()> {
+        let testfile = File::open("test/testfile.png").expect("Please add testfile.png to test folder.");
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_stream("test/testfile.png".to_owned(), testfile)?;
+
+        let mut header = [0u8; 8];
+        let stream = lib1.get_stream("test/testfile.png")?;
+        stream.read_exact(&mut header)?;
+        assert_eq!(header[..], std::fs::read("test/testfile.png")?[..8]);
+
+        lib1.get_stream("test/missing.png").expect_err("Path should not exist!");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/get_stream.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fastest)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/get_stream.rcslib")?;
+        assert_eq!(lib2.read_file("test/testfile.png")?, std::fs::read("test/testfile.png")?.into_boxed_slice());
+
+        Ok(())
+    }
+
+    #[test]
+    fn take_stream() -> Result<()> {
+        let mut lib = ResourceLibraryWriter::new();
+
+        lib.write_str("test/a.txt".to_owned(), "Test file A")?;
+        lib.write_str("test/b.txt".to_owned(), "Test file B")?;
+
+        let mut stream = lib.take_stream("test/a.txt")?;
+        let mut data = String::new();
+        stream.read_to_string(&mut data)?;
+        assert_eq!(data, "Test file A");
+        assert_eq!(&*lib.get_all_files(), ["test/b.txt"]);
+
+        lib.take_stream("test/a.txt").expect_err("Path should no longer exist!");
+
+        match lib.take_data("test/b?.txt") {
+            Err(ResourceLibraryError::PathError(PathError::DisallowedCharacter('?', path))) => assert_eq!(path, "test/b?.txt"),
+            result => panic!("Expected DisallowedCharacter, got {:?}", result)
+        }
+        assert_eq!(&*lib.take_data("test/b.txt")?, b"Test file B");
+        assert!(lib.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_extend() -> Result<()> {
+        let files = vec![
+            ("test/a.txt".to_owned(), ByteStream::from(b"Test file A".to_vec())),
+            ("test/b.txt".to_owned(), ByteStream::from(b"Test file B".to_vec())),
+            ("test/a.txt".to_owned(), ByteStream::from(b"Test file A again".to_vec()))
+        ];
+
+        // Later duplicates replace earlier ones, same as calling write_stream in a loop
+        let mut lib = ResourceLibraryWriter::try_from_iter(files)?;
+        assert_eq!(&*lib.get_all_files(), ["test/a.txt", "test/b.txt"]);
+        assert_eq!(&*lib.read_data("test/a.txt")?, b"Test file A again");
+
+        let files = vec![
+            ("test/c.txt".to_owned(), ByteStream::from(b"Test file C".to_vec())),
+            ("test/d|.txt".to_owned(), ByteStream::from(b"Test file D".to_vec())),
+            ("test/e.txt".to_owned(), ByteStream::from(b"Test file E".to_vec()))
+        ];
+
+        match lib.try_extend(files) {
+            Err(ResourceLibraryError::PathError(PathError::DisallowedCharacter('|', path))) => assert_eq!(path, "test/d|.txt"),
+            result => panic!("Expected DisallowedCharacter, got {:?}", result)
+        }
+
+        // Entries before the invalid one are kept, nothing after it is added
+        assert_eq!(&*lib.get_all_files(), ["test/a.txt", "test/b.txt", "test/c.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_policy() -> Result<()> {
+        let mut lib = ResourceLibraryWriter::new();
+
+        lib.write_str("test/a.txt".to_owned(), "Test file A")?;
+        lib.write_str("test/a.txt".to_owned(), "Test file A 2")?;
+        assert_eq!(&*lib.read_data("test/a.txt")?, b"Test file A 2");
+
+        let mut old = lib.replace_stream("test/a.txt".to_owned(), ByteStream::from(b"Test file A 3".to_vec()))?.expect("Old stream should be returned!");
+        let mut data = Vec::new();
+        old.rewind()?;
+        old.read_to_end(&mut data)?;
+        assert_eq!(data, b"Test file A 2");
+        assert!(lib.replace_stream("test/b.txt".to_owned(), ByteStream::from(b"Test file B".to_vec()))?.is_none());
+
+        match lib.write_stream_new("test/a.txt".to_owned(), ByteStream::from(b"Test file A 4".to_vec())) {
+            Err(ResourceLibraryError::PathError(PathError::PathExists(path))) => assert_eq!(path, "test/a.txt"),
+            result => panic!("Expected PathExists, got {:?}", result)
+        }
+        lib.write_stream_new("test/c.txt".to_owned(), ByteStream::from(b"Test file C".to_vec()))?;
+
+        lib.set_duplicate_policy(ConflictPolicy::Error);
+        lib.write_str("test/a.txt".to_owned(), "Test file A 5").expect_err("Path should already exist!");
+        assert_eq!(&*lib.read_data("test/a.txt")?, b"Test file A 3");
+
+        lib.set_duplicate_policy(ConflictPolicy::KeepExisting);
+        lib.write_str("test/a.txt".to_owned(), "Test file A 6")?;
+        assert_eq!(&*lib.read_data("test/a.txt")?, b"Test file A 3");
+        lib.write_stream_with_compression("test/a.txt", ByteStream::from(b"Test file A 7".to_vec()), Codec::Stored)?;
+        assert_eq!(&*lib.read_data("test/a.txt")?, b"Test file A 3");
+        assert_eq!(lib.get_compression("test/a.txt"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flexible_paths() -> Result<()> {
+        let owned = String::from("test/owned.txt");
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_stream("test/literal.txt", ByteStream::from(b"literal".to_vec()))?;
+        lib1.write_stream(owned.clone(), ByteStream::from(b"owned".to_vec()))?;
+        lib1.write_str(&owned[..4], "borrowed")?;
+        lib1.write_stream("test/bad?.txt", ByteStream::from(Vec::new())).expect_err("Path should be invalid!");
+
+        assert!(lib1.contains_path(&owned));
+        assert!(lib1.contains_path("test/literal.txt"));
+        assert_eq!(&*lib1.read_data(&owned)?, b"owned");
+        assert_eq!(&*lib1.read_data("test")?, b"borrowed");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/flexible_paths.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/flexible_paths.rcslib")?;
+        assert_eq!(&*lib2.read_file(&owned)?, b"owned");
+        assert_eq!(&*lib2.read_file(owned.clone())?, b"owned");
+        assert_eq!(&*lib2.read_file("test/literal.txt")?, b"literal");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_to_cursor() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+
+        let mut cursor = Cursor::new(b"junk".to_vec());
+        cursor.seek(SeekFrom::End(0))?;
+        lib1.write_to(&mut cursor, CompressionLevel::Fast)?;
+
+        // The sink is left after the archive, and anything before it is untouched
+        assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+        assert_eq!(&cursor.get_ref()[..4], b"junk");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("test/write_to_cursor.rcslib")?;
+        lib1.write_to_file(file, CompressionLevel::Fast)?;
+        assert_eq!(&cursor.get_ref()[4..], &std::fs::read("test/write_to_cursor.rcslib")?[..]);
+
+        std::fs::write("test/write_to_cursor.rcslib", &cursor.get_ref()[4..])?;
+        let mut lib2 = ResourceLibraryReader::new("test/write_to_cursor.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_to_path() -> Result<()> {
+        let root = std::path::Path::new("test/write_to_path");
+        if root.exists() {
+            std::fs::remove_dir_all(root)?;
+        }
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+
+        let path = root.join("nested/dir/test.rcslib");
+        lib1.write_to_path(&path, CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new(&path)?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+
+        // A file in the way of the parent directory can't be replaced
+        std::fs::write(root.join("blocker"), "")?;
+        let err = lib1.write_to_path(root.join("blocker/test.rcslib"), CompressionLevel::Fast).expect_err("Parent should not be creatable!");
+        assert!(err.to_string().contains("blocker"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_to_vec() -> Result<()> {
+        let testfile = File::open("test/testfile.png").expect("Please add testfile.png to test folder.");
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_stream("test/testfile.png", testfile)?;
+
+        let data = lib1.write_to_vec(CompressionLevel::Fast)?;
+        lib1.write_to_path("test/write_to_vec.rcslib", CompressionLevel::Fast)?;
+        assert_eq!(data, std::fs::read("test/write_to_vec.rcslib")?);
+
+        assert_eq!(&data[..10], &[0x67, 0xD7, 0x70, 0x3A, 0x54, 0x3D, 0xDB, 0xF5, 0x17, 0x95]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compression_override() -> Result<()> {
+        let text: String = (0..40000).map(|i| format!("{} ", i * 7 % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", &text)?;
+        lib1.write_to_path("test/compression_default.rcslib", CompressionLevel::Fastest)?;
+        let default_size = ResourceLibraryReader::new("test/compression_default.rcslib")?.total_compressed_size();
+
+        lib1.set_compression("test/a.txt", CompressionLevel::Ultra)?;
+        lib1.write_stream_with_compression("test/c.txt", ByteStream::from(text.clone().into_bytes()), CompressionLevel::Ultra)?;
+        lib1.set_compression("test/missing.txt", CompressionLevel::Ultra).expect_err("Path should not exist!");
+
+        assert_eq!(lib1.get_compression("test/a.txt"), Some(Codec::Lzma(CompressionLevel::Ultra)));
+        assert_eq!(lib1.get_compression("test/b.txt"), None);
+        assert_eq!(lib1.get_compression("test/c.txt"), Some(Codec::Lzma(CompressionLevel::Ultra)));
+
+        // Overrides follow the entry when it's renamed
+        lib1.rename_file("test/c.txt", "test/d.txt")?;
+        assert_eq!(lib1.get_compression("test/d.txt"), Some(Codec::Lzma(CompressionLevel::Ultra)));
+        lib1.remove_file("test/d.txt")?;
+
+        lib1.write_to_path("test/compression_override.rcslib", CompressionLevel::Fastest)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/compression_override.rcslib")?;
+        assert!(lib2.total_compressed_size() < default_size);
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, text.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_entries() -> Result<()> {
+        let testfile = File::open("test/testfile.png").expect("Please add testfile.png to test folder.");
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_stream_with_compression("test/testfile.png", testfile, CompressionLevel::None)?;
+        lib1.write_str("test/text.txt", &text)?;
+        lib1.write_str("test/empty.txt", "")?;
+        lib1.set_compression("test/empty.txt", CompressionLevel::None)?;
+        lib1.write_to_path("test/stored.rcslib", CompressionLevel::Normal)?;
+
+        let png = std::fs::read("test/testfile.png")?;
+        let mut lib2 = ResourceLibraryReader::new("test/stored.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/testfile.png")?, &png[..]);
+        assert_eq!(&*lib2.read_file("test/text.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/empty.txt")?, b"");
+        assert!(lib2.total_compressed_size() < (png.len() + text.len()) as u64);
+
+        // Stored entries are written as is
+        let archive = std::fs::read("test/stored.rcslib")?;
+        assert!(archive.windows(png.len()).any(|window| window == &png[..]));
+
+        // Everything stored
+        let data = lib1.write_to_vec(CompressionLevel::None)?;
+        assert!(data.windows(text.len()).any(|window| window == text.as_bytes()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_format() -> Result<()> {
+        // Version 0 archives have no version field and every entry is LZMA compressed
+        let a = lzma::compress(b"Test file A", 3)?;
+        let b = lzma::compress(b"Test file B", 3)?;
+        let index = vec![
+            ("test/a.txt".to_owned(), 0u64, a.len() as u64),
+            ("test/b.txt".to_owned(), a.len() as u64, b.len() as u64)
+        ];
+
+        let mut serializer = IndexSerializer::new();
+        index.serialize(&mut serializer)?;
+        let index_data = serializer.take();
+
+        let mut archive = vec![0x67, 0xD7, 0x70, 0x3A, 0x54, 0x3D, 0xDB, 0xF5, 0x17, 0x95];
+        archive.extend((index_data.len() as u64).to_be_bytes());
+        archive.extend(((a.len() + b.len()) as u64).to_be_bytes());
+        archive.extend(&index_data[..]);
+        archive.extend(a);
+        archive.extend(b);
+        std::fs::write("test/legacy.rcslib", &archive)?;
+
+        let mut lib = ResourceLibraryReader::new("test/legacy.rcslib")?;
+        assert_eq!(lib.version(), 0);
+        assert_eq!(&*lib.get_all_files(), ["test/a.txt", "test/b.txt"]);
+        assert_eq!(&*lib.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib.read_file("test/b.txt")?, b"Test file B");
+        assert_eq!(lib.uncompressed_len("test/a.txt"), None);
+
+        // Entries carried over into a newer archive still have no recorded length
+        let mut editor = ArchiveEditor::open("test/legacy.rcslib")?;
+        editor.write_str("test/c.txt", "Test file C")?;
+        editor.save(CompressionLevel::Normal)?;
+
+        let mut lib = ResourceLibraryReader::new("test/legacy.rcslib")?;
+        assert_eq!(lib.version(), FORMAT_VERSION);
+        assert_eq!(lib.uncompressed_len("test/a.txt"), None);
+        assert_eq!(lib.uncompressed_len("test/c.txt"), Some(11));
+        assert_eq!(&*lib.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib.read_file("test/c.txt")?, b"Test file C");
+
+        // A version from the future
+        archive[10..12].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        std::fs::write("test/future.rcslib", &archive)?;
+        match ResourceLibraryReader::new("test/future.rcslib") {
+            Err(ResourceLibraryError::UnsupportedVersion(0xFFFF, FORMAT_VERSION)) => (),
+            result => panic!("Expected UnsupportedVersion, got {:?}", result.map(|_| ()))
+        }
+
+        // The next version up is just as unreadable
+        archive[10..12].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        std::fs::write("test/future.rcslib", &archive)?;
+        match ResourceLibraryReader::new("test/future.rcslib") {
+            Err(err @ ResourceLibraryError::UnsupportedVersion(..)) => assert_eq!(err.to_string(), format!("Unsupported format version {}, this library supports up to version {}", FORMAT_VERSION + 1, FORMAT_VERSION)),
+            result => panic!("Expected UnsupportedVersion, got {:?}", result.map(|_| ()))
+        }
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        assert_eq!(lib1.format_version(), FORMAT_VERSION);
+        lib1.write_to_path("test/current.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(ResourceLibraryReader::new("test/current.rcslib")?.version(), FORMAT_VERSION);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom_compression_level() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", &text)?;
+        lib1.set_compression("test/b.txt", CompressionLevel::Custom(0))?;
+
+        lib1.write_to_path("test/custom_level.rcslib", CompressionLevel::Custom(6))?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/custom_level.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, text.as_bytes());
+
+        assert_eq!(CompressionLevel::Custom(6).preset(), 6);
+        assert_eq!(CompressionLevel::Ultra.preset(), 9);
+
+        lib1.set_compression("test/a.txt", CompressionLevel::Custom(10)).expect_err("Level should be out of range!");
+
+        let mut cursor = Cursor::new(Vec::new());
+        match lib1.write_to(&mut cursor, CompressionLevel::Custom(42)) {
+            Err(ResourceLibraryError::InvalidCompressionLevel(42)) => (),
+            result => panic!("Expected InvalidCompressionLevel, got {:?}", result)
+        }
+        assert!(cursor.get_ref().is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_codec() -> Result<()> {
+        let testfile = File::open("test/testfile.png").expect("Please add testfile.png to test folder.");
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/zstd.txt", &text)?;
+        lib1.write_str("test/lzma.txt", &text)?;
+        lib1.write_stream("test/testfile.png", testfile)?;
+        lib1.set_compression("test/lzma.txt", CompressionLevel::Normal)?;
+        lib1.set_compression("test/testfile.png", CompressionLevel::None)?;
+        lib1.write_to_path("test/zstd.rcslib", Codec::Zstd(3))?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/zstd.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/zstd.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/lzma.txt")?, text.as_bytes());
+        assert_eq!(lib2.read_file("test/testfile.png")?, std::fs::read("test/testfile.png")?.into_boxed_slice());
+
+        Ok(())
+    }
+
+    fn read_with_codec_id(path: &str, codec: u8) -> Result<Box<[u8]>> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/codec.txt", "Test file")?;
+        let mut archive = lib1.write_to_vec(CompressionLevel::None)?;
+
+        // Header, index length, entry length, shared path prefix, path, offset and compressed length come before the codec.
+        // An archive this small gets a compact index, so each of those integers is 4 bytes.
+        let codec_offset = HEADER_SIZE as usize + 4 + 4 + 4 + 4 + "test/codec.txt".len() + 4 + 4;
+        archive[codec_offset] = codec;
+        reseal_header(&mut archive);
+        std::fs::write(path, &archive)?;
+
+        ResourceLibraryReader::new(path)?.read_file("test/codec.txt")
+    }
+
+    #[test]
+    fn codec_ids() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/lzma.txt", &text)?;
+        lib1.write_str("test/stored.txt", &text)?;
+        lib1.set_compression("test/stored.txt", CompressionLevel::None)?;
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        let index_size = u64::from_be_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let index = entries_from_bytes(&archive[HEADER_SIZE as usize..HEADER_SIZE as usize + index_size], FORMAT_VERSION, true)?;
+        assert_eq!(index.iter().map(|entry| entry.codec).collect::<Vec<_>>(), vec![1, 0]);
+
+        std::fs::write("test/codec_ids.rcslib", &archive)?;
+        let mut lib2 = ResourceLibraryReader::new("test/codec_ids.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/lzma.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/stored.txt")?, text.as_bytes());
+
+        // An archive using an id from a newer version still opens, and reading the entry names the codec
+        match read_with_codec_id("test/codec_ids.rcslib", 150) {
+            Err(ResourceLibraryError::UnknownCodec(150)) => (),
+            result => panic!("Expected UnknownCodec, got {:?}", result)
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_codec_disabled() {
+        match read_with_codec_id("test/zstd_disabled.rcslib", 2) {
+            Err(ResourceLibraryError::CodecNotEnabled("zstd")) => (),
+            result => panic!("Expected CodecNotEnabled, got {:?}", result)
+        }
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn deflate_codec() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file")?;
+        lib1.write_to_path("test/deflate.rcslib", Codec::Deflate(6))?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/deflate.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file");
+
+        let mut lib3 = ResourceLibraryWriter::new();
+        lib3.write_str("test/deflate.txt", &text)?;
+        lib3.write_str("test/lzma.txt", &text)?;
+        lib3.write_str("test/stored.txt", &text)?;
+        lib3.set_compression("test/lzma.txt", CompressionLevel::Fast)?;
+        lib3.set_compression("test/stored.txt", CompressionLevel::None)?;
+        lib3.write_to_path("test/deflate_mixed.rcslib", Codec::Deflate(9))?;
+
+        let mut lib4 = ResourceLibraryReader::new("test/deflate_mixed.rcslib")?;
+        assert_eq!(&*lib4.read_file("test/deflate.txt")?, text.as_bytes());
+        assert_eq!(&*lib4.read_file("test/lzma.txt")?, text.as_bytes());
+        assert_eq!(&*lib4.read_file("test/stored.txt")?, text.as_bytes());
+
+        match lib3.write_to_vec(Codec::Deflate(10)) {
+            Err(ResourceLibraryError::InvalidCompressionLevel(10)) => (),
+            result => panic!("Expected InvalidCompressionLevel, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    #[test]
+    fn deflate_codec_disabled() {
+        match read_with_codec_id("test/deflate_disabled.rcslib", 3) {
+            Err(ResourceLibraryError::CodecNotEnabled("deflate")) => (),
+            result => panic!("Expected CodecNotEnabled, got {:?}", result)
+        }
+    }
+
+    struct XorCodec(u8);
+
+    impl CompressionCodec for XorCodec {
+        fn id(&self) -> u8 {
+            200
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn decompress(&self, data: &[u8], _expected_len: Option<u64>) -> Result<Vec<u8>> {
+            self.compress(data)
+        }
+    }
+
+    #[test]
+    fn custom_codec() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_compression("test/b.txt", CompressionLevel::Fast)?;
+
+        match lib1.write_to_vec(Codec::Custom(200)) {
+            Err(ResourceLibraryError::UnknownCodec(200)) => (),
+            result => panic!("Expected UnknownCodec, got {:?}", result.map(|_| ()))
+        }
+
+        // XOR never shrinks anything, so keep it from falling back to stored
+        lib1.register_codec(XorCodec(0x5A))?;
+        lib1.set_min_savings(None);
+        lib1.write_to_path("test/custom_codec.rcslib", Codec::Custom(200))?;
+
+        // The XOR'd bytes should not appear in the archive as-is
+        let raw = std::fs::read("test/custom_codec.rcslib")?;
+        assert!(!raw.windows(11).any(|window| window == b"Test file A"));
+
+        let mut lib2 = ResourceLibraryReader::new("test/custom_codec.rcslib")?;
+        match lib2.read_file("test/a.txt") {
+            Err(ResourceLibraryError::UnknownCodec(200)) => (),
+            result => panic!("Expected UnknownCodec, got {:?}", result)
+        }
+
+        lib2.register_codec(XorCodec(0x5A))?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        struct Reserved;
+        impl CompressionCodec for Reserved {
+            fn id(&self) -> u8 {
+                1
+            }
+
+            fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+                Ok(data.to_vec())
+            }
+
+            fn decompress(&self, data: &[u8], _expected_len: Option<u64>) -> Result<Vec<u8>> {
+                Ok(data.to_vec())
+            }
+        }
+
+        match lib2.register_codec(Reserved) {
+            Err(ResourceLibraryError::ReservedCodecId(1)) => (),
+            result => panic!("Expected ReservedCodecId, got {:?}", result)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_compression() -> Result<()> {
+        let build = |max_in_flight| -> Result<Vec<u8>> {
+            let mut lib = ResourceLibraryWriter::new();
+            for i in 0..40 {
+                let text: String = (0..i * 50).map(|j| format!("{} ", j % (i + 1))).collect();
+                lib.write_str(format!("test/{}.txt", i), &text)?;
+            }
+            lib.write_stream("test/testfile.png", File::open("test/testfile.png").expect("Please add testfile.png to test folder."))?;
+            lib.set_compression("test/3.txt", CompressionLevel::None)?;
+            lib.set_max_in_flight(max_in_flight);
+            lib.write_to_vec(CompressionLevel::Fast)
+        };
+
+        let serial = build(1)?;
+        assert_eq!(serial, build(8)?);
+        assert_eq!(serial, build(0)?);
+        assert_eq!(serial, build(100)?);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct SyntheticStream {
+        len: u64,
+        position: u64
+    }
+
+    impl SyntheticStream {
+        fn byte_at(position: u64) -> u8 {
+            ((position % 251) ^ (position / 65536)) as u8
+        }
+    }
+
+    impl Read for SyntheticStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let count = buf.len().min((self.len - self.position.min(self.len)) as usize);
+            for (i, byte) in buf[..count].iter_mut().enumerate() {
+                *byte = SyntheticStream::byte_at(self.position + i as u64);
+            }
+
+            self.position += count as u64;
+            Ok(count)
+        }
+    }
+
+    impl Seek for SyntheticStream {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset,
+                SeekFrom::End(offset) => (self.len as i64 + offset) as u64,
+                SeekFrom::Current(offset) => (self.position as i64 + offset) as u64
+            };
+
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn streaming_compression() -> Result<()> {
+        let len = 256 * 1024 * 1024;
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_stream("test/large.bin", SyntheticStream { len, position: 0 })?;
+        lib1.write_str("test/small.txt", "Test file")?;
+        lib1.write_to_path("test/streaming.rcslib", CompressionLevel::Fastest)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/streaming.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/small.txt")?, b"Test file");
+
+        let data = lib2.read_file("test/large.bin")?;
+        assert_eq!(data.len() as u64, len);
+        assert!(data.iter().enumerate().all(|(i, &byte)| byte == SyntheticStream::byte_at(i as u64)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_report() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file")?;
+        lib1.write_data("test/empty.bin", Vec::new())?;
+        lib1.set_compression("test/b.txt", CompressionLevel::None)?;
+        let report = lib1.write_to_path("test/report.rcslib", CompressionLevel::Normal)?;
+
+        let paths: Vec<_> = report.entries.iter().map(|entry| &entry.path[..]).collect();
+        assert_eq!(paths, ["test/a.txt", "test/b.txt", "test/empty.bin"]);
+        assert_eq!(report.entries[0].original_size, text.len() as u64);
+        assert_eq!(report.entries[0].codec, Codec::Lzma(CompressionLevel::Normal));
+        assert!(report.entries[0].ratio() < 0.5);
+        assert_eq!(report.entries[1].codec, Codec::Stored);
+        assert_eq!(report.entries[1].ratio(), 1.0);
+        assert_eq!(report.original_size, text.len() as u64 + 9);
+
+        let lib2 = ResourceLibraryReader::new("test/report.rcslib")?;
+        for entry in &report.entries {
+            assert_eq!(lib2.compressed_len(&entry.path), Some(entry.compressed_size));
+        }
+        assert_eq!(report.compressed_size, lib2.total_compressed_size());
+
+        let archive = std::fs::read("test/report.rcslib")?;
+        assert_eq!(report.index_size, u64::from_be_bytes(archive[12..20].try_into().unwrap()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_fallback() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        // Simple xorshift so the bytes are effectively incompressible
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let random: Vec<u8> = (0..64 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/text.txt", &text)?;
+        lib1.write_data("test/random.bin", random.clone())?;
+        let report = lib1.write_to_path("test/fallback.rcslib", CompressionLevel::Normal)?;
+
+        assert_eq!(report.entries[0].codec, Codec::Stored);
+        assert_eq!(report.entries[0].compressed_size, random.len() as u64);
+        assert_eq!(report.entries[1].codec, Codec::Lzma(CompressionLevel::Normal));
+
+        let mut lib2 = ResourceLibraryReader::new("test/fallback.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/random.bin")?, &random[..]);
+        assert_eq!(&*lib2.read_file("test/text.txt")?, text.as_bytes());
+
+        // Nothing can save 100%, so everything ends up stored
+        lib1.set_min_savings(Some(1.0));
+        let report = lib1.write_to(&mut Cursor::new(Vec::new()), CompressionLevel::Normal)?;
+        assert_eq!(report.entries[1].codec, Codec::Stored);
+
+        lib1.set_min_savings(None);
+        let report = lib1.write_to_path("test/fallback.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(report.entries[0].codec, Codec::Lzma(CompressionLevel::Normal));
+        assert!(report.entries[0].compressed_size > random.len() as u64);
+
+        let mut lib3 = ResourceLibraryReader::new("test/fallback.rcslib")?;
+        assert_eq!(&*lib3.read_file("test/random.bin")?, &random[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_compress_extensions() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/foo.PNG", &text)?;
+        lib1.write_str("test/foo.png.txt", &text)?;
+        lib1.write_str("test/bar.ogg", &text)?;
+        lib1.write_str("test/bar.mp4", &text)?;
+        lib1.write_str("test/png", &text)?;
+        lib1.set_compression("test/bar.mp4", CompressionLevel::Fast)?;
+        lib1.set_no_compress_extensions(&["png", ".ogg", "mp4"]);
+        let report = lib1.write_to_path("test/no_compress.rcslib", CompressionLevel::Normal)?;
+
+        let codecs: Vec<_> = report.entries.iter().map(|entry| (&entry.path[..], entry.codec)).collect();
+        assert_eq!(codecs, [
+            ("test/bar.mp4", Codec::Lzma(CompressionLevel::Fast)),
+            ("test/bar.ogg", Codec::Stored),
+            ("test/foo.PNG", Codec::Stored),
+            ("test/foo.png.txt", Codec::Lzma(CompressionLevel::Normal)),
+            ("test/png", Codec::Lzma(CompressionLevel::Normal))
+        ]);
+
+        let mut lib2 = ResourceLibraryReader::new("test/no_compress.rcslib")?;
+        assert_eq!(lib2.compressed_len("test/foo.PNG"), Some(text.len() as u64));
+        for path in lib2.get_all_files().iter().map(|path| path.to_string()).collect::<Vec<_>>() {
+            assert_eq!(&*lib2.read_file(path)?, text.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn solid_archive() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        for i in 0..200 {
+            lib1.write_str(format!("test/solid/{}.txt", i), &format!("Small text file number {}, mostly the same as the others.", i))?;
+        }
+        lib1.write_data("test/solid/empty.bin", Vec::new())?;
+        lib1.set_compression("test/solid/0.txt", CompressionLevel::None)?;
+
+        let mut cursor = Cursor::new(Vec::new());
+        let loose_report = lib1.write_to(&mut cursor, CompressionLevel::Normal)?;
+        let mut loose = cursor.into_inner();
+
+        lib1.set_solid(true);
+        let report = lib1.write_to_path("test/solid.rcslib", CompressionLevel::Normal)?;
+        assert!(std::fs::metadata("test/solid.rcslib")?.len() < loose.len() as u64);
+        assert!(report.compressed_size * 4 < loose_report.compressed_size);
+
+        let mut lib2 = ResourceLibraryReader::new("test/solid.rcslib")?;
+        assert!(lib2.is_solid());
+        assert_eq!(lib2.total_compressed_size(), report.compressed_size);
+        for i in 0..200 {
+            let expected = format!("Small text file number {}, mostly the same as the others.", i);
+            assert_eq!(&*lib2.read_file(format!("test/solid/{}.txt", i))?, expected.as_bytes());
+        }
+        assert_eq!(&*lib2.read_file("test/solid/empty.bin")?, b"");
+
+        std::fs::write("test/loose.rcslib", &loose)?;
+        assert!(!ResourceLibraryReader::new("test/loose.rcslib")?.is_solid());
+
+        // Flags this build doesn't know about can't be safely ignored
+        loose[28..30].copy_from_slice(&0x8000u16.to_be_bytes());
+        std::fs::write("test/loose.rcslib", &loose)?;
+        match ResourceLibraryReader::new("test/loose.rcslib") {
+            Err(ResourceLibraryError::UnsupportedFlags(0x8000)) => (),
+            result => panic!("Expected UnsupportedFlags, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_entries() -> Result<()> {
+        let len = 1024 * 1024 + 100;
+        let data: Vec<u8> = (0..len).map(|i| ((i % 251) ^ (i / 4096)) as u8).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_data("test/large.bin", data.clone())?;
+        lib1.write_str("test/small.txt", "Test file")?;
+        lib1.set_block_size(Some(256 * 1024));
+        lib1.write_to_path("test/chunked.rcslib", CompressionLevel::Fast)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/chunked.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/large.bin")?, &data[..]);
+        assert_eq!(&*lib2.read_file("test/small.txt")?, b"Test file");
+
+        let block = 256 * 1024;
+        for range in [0..10, block - 10..block, block..block + 10, block - 10..block + 10, 3 * block - 1..4 * block + 1, len - 50..len + 50, 0..len] {
+            let expected = &data[range.start as usize..range.end.min(len) as usize];
+            assert_eq!(&*lib2.read_range("test/large.bin", range)?, expected);
+        }
+
+        assert_eq!(&*lib2.read_range("test/large.bin", len + 10..len + 20)?, b"");
+        assert_eq!(&*lib2.read_range("test/small.txt", 5..100)?, b"file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn aligned_entries() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", &text)?;
+        lib1.write_str("test/c.txt", "Test file C")?;
+        lib1.write_data("test/d.bin", text.as_bytes()[..3000].to_vec())?;
+        lib1.set_no_compress_extensions(&["txt"]);
+        lib1.set_block_size(Some(1000));
+        lib1.set_compression("test/d.bin", CompressionLevel::Fast)?;
+
+        let tight = lib1.write_to_vec(CompressionLevel::Normal)?;
+        lib1.set_alignment(1);
+        assert_eq!(lib1.write_to_vec(CompressionLevel::Normal)?, tight);
+        let mut streamed = Vec::new();
+        lib1.write_to_stream(&mut streamed, CompressionLevel::Normal)?;
+        lib1.set_alignment(0);
+        assert_eq!(lib1.write_to_vec(CompressionLevel::Normal)?, tight);
+        let mut streamed_tight = Vec::new();
+        lib1.write_to_stream(&mut streamed_tight, CompressionLevel::Normal)?;
+        assert_eq!(streamed_tight, streamed);
+
+        lib1.set_alignment(4096);
+        let report = lib1.write_to_path("test/aligned.rcslib", CompressionLevel::Normal)?;
+        let archive = std::fs::read("test/aligned.rcslib")?;
+        let index_size = u64::from_be_bytes(archive[12..20].try_into().unwrap());
+        let data_pointer = HEADER_SIZE + index_size;
+        assert!(report.compressed_size > 3 * 4096);
+
+        for entry in entries_from_bytes(&archive[HEADER_SIZE as usize..data_pointer as usize], FORMAT_VERSION, true)?.iter() {
+            assert_eq!((data_pointer + entry.offset) % 4096, 0, "{} is not aligned", entry.path);
+        }
+
+        let mut lib2 = ResourceLibraryReader::new("test/aligned.rcslib")?;
+
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib2.read_file("test/b.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/c.txt")?, b"Test file C");
+        assert_eq!(&*lib2.read_file("test/d.bin")?, &text.as_bytes()[..3000]);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct FailingStream {
+        position: u64
+    }
+
+    impl Read for FailingStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.position >= 1000 {
+                return Err(std::io::Error::other("read failed"));
+            }
+
+            let count = buf.len().min(100);
+            buf[..count].fill(b'a');
+            self.position += count as u64;
+            Ok(count)
+        }
+    }
+
+    impl Seek for FailingStream {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset,
+                _ => 2000
+            };
+
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn write_to_path_atomic() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_to_path_atomic("test/atomic/lib.rcslib", CompressionLevel::Normal)?;
+        let original = std::fs::read("test/atomic/lib.rcslib")?;
+
+        let mut lib2 = ResourceLibraryWriter::new();
+        lib2.write_str("test/b.txt", "Test file B")?;
+        lib2.write_stream("test/failing.bin", FailingStream { position: 0 })?;
+        assert!(lib2.write_to_path_atomic("test/atomic/lib.rcslib", CompressionLevel::Normal).is_err());
+
+        // The destination is untouched and the temp file is cleaned up
+        assert_eq!(std::fs::read("test/atomic/lib.rcslib")?, original);
+        assert_eq!(std::fs::read_dir("test/atomic")?.count(), 1);
+
+        lib2.remove_file("test/failing.bin")?;
+        lib2.write_to_path_atomic("test/atomic/lib.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(std::fs::read_dir("test/atomic")?.count(), 1);
+
+        let mut lib3 = ResourceLibraryReader::new("test/atomic/lib.rcslib")?;
+        assert_eq!(lib3.get_all_files(), vec!["test/b.txt"].into_boxed_slice());
+        assert_eq!(&*lib3.read_file("test/b.txt")?, b"Test file B");
+
+        Ok(())
+    }
+
+    // Cancels the write as soon as the entry is read
+    #[derive(Debug)]
+    struct CancellingStream {
+        cursor: Cursor<Vec<u8>>,
+        token: Arc<AtomicBool>
+    }
+
+    impl Read for CancellingStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.token.store(true, Ordering::Relaxed);
+            self.cursor.read(buf)
+        }
+    }
+
+    impl Seek for CancellingStream {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    #[test]
+    fn cancel_write() -> Result<()> {
+        let _ = std::fs::remove_dir_all("test/cancel");
+        let token = Arc::new(AtomicBool::new(false));
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.set_cancel_token(Some(token.clone()));
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_stream("test/b.txt", CancellingStream { cursor: Cursor::new(b"Test file B".to_vec()), token: token.clone() })?;
+        lib1.write_str("test/c.txt", "Test file C")?;
+
+        match lib1.write_to_path_atomic("test/cancel/lib.rcslib", CompressionLevel::Normal) {
+            Err(ResourceLibraryError::Cancelled) => (),
+            result => panic!("Expected Cancelled, got {:?}", result)
+        }
+        assert_eq!(std::fs::read_dir("test/cancel")?.count(), 0);
+
+        match lib1.write_to_vec(CompressionLevel::Normal) {
+            Err(ResourceLibraryError::Cancelled) => (),
+            result => panic!("Expected Cancelled, got {:?}", result)
+        }
+
+        token.store(false, Ordering::Relaxed);
+        lib1.set_cancel_token(None);
+        lib1.write_to_path_atomic("test/cancel/lib.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(ResourceLibraryReader::new("test/cancel/lib.rcslib")?.len(), 3);
+
+        Ok(())
+    }
+
+    // Takes longer to read the earlier it sorts, so parallel readers finish out of order
+    #[derive(Debug)]
+    struct SlowStream {
+        cursor: Cursor<Vec<u8>>,
+        delay: u64
+    }
+
+    impl Read for SlowStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(std::time::Duration::from_millis(self.delay));
+            self.cursor.read(buf)
+        }
+    }
+
+    impl Seek for SlowStream {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    #[test]
+    fn read_ahead() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        for i in 0..12u64 {
+            lib1.write_stream(format!("test/{:02}.txt", i), SlowStream { cursor: Cursor::new(format!("{}{}", i, text).into_bytes()), delay: 12 - i })?;
+        }
+        lib1.set_max_in_flight(3);
+
+        let sequential = lib1.write_to_vec(CompressionLevel::Normal)?;
+        lib1.set_read_ahead(4);
+        assert_eq!(lib1.write_to_vec(CompressionLevel::Normal)?, sequential);
+
+        lib1.set_solid(true);
+        let solid = lib1.write_to_vec(CompressionLevel::Normal)?;
+        lib1.set_read_ahead(0);
+        assert_eq!(lib1.write_to_vec(CompressionLevel::Normal)?, solid);
+
+        // A failing reader stops the write instead of hanging it
+        lib1.set_solid(false);
+        lib1.set_read_ahead(4);
+        lib1.write_stream("test/failing.bin", FailingStream { position: 0 })?;
+        assert!(lib1.write_to_vec(CompressionLevel::Normal).is_err());
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        cursor: Cursor<Vec<u8>>,
+        flushes: usize
+    }
+
+    impl Write for RecordingSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.cursor.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    impl Seek for RecordingSink {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    #[test]
+    fn sync_policy() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+
+        let mut sink = RecordingSink::default();
+        lib1.write_to(&mut sink, CompressionLevel::Normal)?;
+        assert_eq!(sink.flushes, 1);
+
+        lib1.set_sync_policy(SyncPolicy::None);
+        let mut sink = RecordingSink::default();
+        lib1.write_to(&mut sink, CompressionLevel::Normal)?;
+        assert_eq!(sink.flushes, 0);
+        assert_eq!(sink.cursor.into_inner(), lib1.write_to_vec(CompressionLevel::Normal)?);
+
+        for policy in [SyncPolicy::None, SyncPolicy::Flush, SyncPolicy::FsyncFile, SyncPolicy::FsyncFileAndDir] {
+            lib1.set_sync_policy(policy);
+            lib1.write_to_path("test/sync/lib.rcslib", CompressionLevel::Normal)?;
+            lib1.write_to_path_atomic("test/sync/atomic.rcslib", CompressionLevel::Normal)?;
+            lib1.write_to_file(File::create("test/sync/file.rcslib")?, CompressionLevel::Normal)?;
+
+            for path in ["test/sync/lib.rcslib", "test/sync/atomic.rcslib", "test/sync/file.rcslib"] {
+                assert_eq!(&*ResourceLibraryReader::new(path)?.read_file("test/a.txt")?, b"Test file A");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_entries() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_str("test/d.txt", &text)?;
+        lib1.write_to_path("test/append.rcslib", CompressionLevel::Normal)?;
+
+        let mut editor = ArchiveEditor::open("test/append.rcslib")?;
+        match editor.write_str("test/b.txt", "Replaced") {
+            Err(ResourceLibraryError::PathError(PathError::PathExists(_))) => (),
+            result => panic!("Expected PathExists, got {:?}", result)
+        }
+
+        // Other spellings of an existing path are caught before they can end up in the index twice
+        editor.set_path_normalization(Normalize::Backslashes);
+        assert!(editor.contains_path("test\\b.txt"));
+        match editor.write_str("test\\b.txt", "Replaced") {
+            Err(ResourceLibraryError::PathError(PathError::PathExists(path))) => assert_eq!(path, "test/b.txt"),
+            result => panic!("Expected PathExists, got {:?}", result)
+        }
+
+        editor.write_str("test/a.txt", "Test file A")?;
+        editor.write_str("test/c.txt", &text)?;
+        let report = editor.save(CompressionLevel::Normal)?;
+        assert_eq!(report.entries.len(), 2);
+        assert!(editor.contains_path("test/a.txt"));
+
+        editor.write_str("test/e.txt", "Test file E")?;
+        editor.save(CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/append.rcslib")?;
+        assert_eq!(lib2.get_all_files(), vec!["test/a.txt", "test/b.txt", "test/c.txt", "test/d.txt", "test/e.txt"].into_boxed_slice());
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+        assert_eq!(&*lib2.read_file("test/c.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/d.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/e.txt")?, b"Test file E");
+
+        lib1.set_solid(true);
+        lib1.write_to_path("test/append_solid.rcslib", CompressionLevel::Normal)?;
+        match ArchiveEditor::open("test/append_solid.rcslib") {
+            Err(ResourceLibraryError::SolidArchive) => (),
+            result => panic!("Expected SolidArchive, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_entry() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+        let files = [("test/a.txt", "Test file A".to_owned()), ("test/b.txt", text.clone()), ("test/c.txt", "Test file C".to_owned())];
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        for (path, data) in &files {
+            lib1.write_str(*path, data)?;
+        }
+        lib1.write_to_path("test/replace.rcslib", CompressionLevel::Normal)?;
+
+        let lib2 = ResourceLibraryReader::new("test/replace.rcslib")?;
+        let compressed_lens: Vec<_> = files.iter().map(|(path, _)| lib2.compressed_len(*path)).collect();
+        drop(lib2);
+
+        let mut editor = ArchiveEditor::open("test/replace.rcslib")?;
+        match editor.replace_entry("test/d.txt", b"Missing".to_vec(), CompressionLevel::Normal) {
+            Err(ResourceLibraryError::PathError(PathError::InvalidPath(_))) => (),
+            result => panic!("Expected InvalidPath, got {:?}", result)
+        }
+
+        let file_len = std::fs::metadata("test/replace.rcslib")?.len();
+        editor.replace_entry("test/b.txt", b"Smaller".to_vec(), CompressionLevel::None)?;
+        // Only the fingerprint is dropped
+        assert_eq!(std::fs::metadata("test/replace.rcslib")?.len(), file_len - 32);
+
+        let mut lib2 = ResourceLibraryReader::new("test/replace.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Smaller");
+        for (i, (path, data)) in files.iter().enumerate().filter(|(i, _)| *i != 1) {
+            assert_eq!(&*lib2.read_file(*path)?, data.as_bytes());
+            assert_eq!(lib2.compressed_len(*path), compressed_lens[i]);
+        }
+        drop(lib2);
+
+        editor.replace_entry("test/a.txt", text.as_bytes(), CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/replace.rcslib")?;
+        assert_eq!(lib2.get_all_files(), vec!["test/a.txt", "test/b.txt", "test/c.txt"].into_boxed_slice());
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Smaller");
+        assert_eq!(&*lib2.read_file("test/c.txt")?, b"Test file C");
+        assert_eq!(lib2.compressed_len("test/c.txt"), compressed_lens[2]);
+
+        // Aliases follow their target into the reused slot
+        let mut lib3 = ResourceLibraryWriter::new();
+        lib3.write_str("test/a.txt", &text)?;
+        lib3.write_str("test/b.txt", "Test file B")?;
+        lib3.add_alias("test/alias.txt".to_owned(), "test/a.txt")?;
+        lib3.add_alias("test/chain.txt".to_owned(), "test/alias.txt")?;
+        lib3.write_to_path("test/replace_aliases.rcslib", CompressionLevel::Normal)?;
+
+        let file_len = std::fs::metadata("test/replace_aliases.rcslib")?.len();
+        ArchiveEditor::open("test/replace_aliases.rcslib")?.replace_entry("test/a.txt", b"Smaller".to_vec(), CompressionLevel::None)?;
+        assert_eq!(std::fs::metadata("test/replace_aliases.rcslib")?.len(), file_len - 32);
+
+        let mut lib4 = ResourceLibraryReader::new("test/replace_aliases.rcslib")?;
+        for path in ["test/a.txt", "test/alias.txt", "test/chain.txt"] {
+            assert_eq!(&*lib4.read_file(path)?, b"Smaller");
+        }
+        assert_eq!(&*lib4.read_file("test/b.txt")?, b"Test file B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_archive() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", &text)?;
+        lib1.write_str("test/c.txt", "Test file C")?;
+        lib1.write_to_path("test/compact_src.rcslib", CompressionLevel::Normal)?;
+
+        let mut editor = ArchiveEditor::open("test/compact_src.rcslib")?;
+        // Each payload is a little larger than the last, so none of them fit in the previous slot
+        for i in 0..4 {
+            editor.replace_entry("test/a.txt", format!("{}{}", text, "!".repeat(i)).into_bytes(), CompressionLevel::None)?;
+        }
+        editor.replace_entry("test/b.txt", b"Test file B".to_vec(), CompressionLevel::Normal)?;
+        drop(editor);
+
+        let report = compact("test/compact_src.rcslib", "test/compact_dst.rcslib")?;
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.original_size, std::fs::metadata("test/compact_src.rcslib")?.len());
+        assert_eq!(report.compacted_size, std::fs::metadata("test/compact_dst.rcslib")?.len());
+        assert!(report.reclaimed() > text.len() as u64 * 3);
+
+        let mut lib2 = ResourceLibraryReader::new("test/compact_src.rcslib")?;
+        let mut lib3 = ResourceLibraryReader::new("test/compact_dst.rcslib")?;
+        assert_eq!(lib2.get_all_files(), lib3.get_all_files());
+        for path in ["test/a.txt", "test/b.txt", "test/c.txt"] {
+            assert_eq!(lib2.read_file(path)?, lib3.read_file(path)?);
+        }
+        assert_eq!(&*lib3.read_file("test/a.txt")?, format!("{}!!!", text).as_bytes());
+        assert_eq!(lib3.fingerprint()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn multi_volume() -> Result<()> {
+        let files: Vec<_> = (0..5u8).map(|i| (format!("test/{}.bin", i), vec![i; 1000])).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        for (path, data) in &files {
+            lib1.write_data(path, data.clone())?;
+        }
+
+        match lib1.write_to_volumes("test/volumes.rcslib", CompressionLevel::None, 900) {
+            Err(ResourceLibraryError::VolumeTooSmall(_, 1000, 900)) => (),
+            result => panic!("Expected VolumeTooSmall, got {:?}", result)
+        }
+
+        lib1.write_to_volumes("test/volumes.rcslib", CompressionLevel::None, 2200)?;
+        let parts = ["test/volumes.rcslib.001", "test/volumes.rcslib.002", "test/volumes.rcslib.003"];
+        for part in parts {
+            assert!(std::fs::metadata(part)?.len() <= 2200);
+        }
+        assert!(!Path::new("test/volumes.rcslib.004").exists());
+
+        let mut lib2 = ResourceLibraryReader::new(parts[0])?;
+        let mut lib3 = ResourceLibraryReader::open_parts(&parts)?;
+        assert_eq!(lib2.volume_count(), 3);
+        for (path, data) in &files {
+            assert_eq!(&*lib2.read_file(path)?, &data[..]);
+            assert_eq!(&*lib3.read_file(path)?, &data[..]);
+        }
+
+        // Alignment 0 packs the volumes as tightly as 1
+        lib1.set_alignment(0);
+        lib1.write_to_volumes("test/volumes_tight.rcslib", CompressionLevel::None, 2200)?;
+        let mut lib4 = ResourceLibraryReader::new("test/volumes_tight.rcslib.001")?;
+        assert_eq!(lib4.volume_count(), 3);
+        for (path, data) in &files {
+            assert_eq!(&*lib4.read_file(path)?, &data[..]);
+        }
+        for (tight, part) in ["test/volumes_tight.rcslib.002", "test/volumes_tight.rcslib.003"].iter().zip(&parts[1..]) {
+            assert_eq!(std::fs::read(tight)?, std::fs::read(part)?);
+        }
+
+        match ResourceLibraryReader::open_parts(&parts[..2]) {
+            Err(ResourceLibraryError::MissingVolume(2)) => (),
+            result => panic!("Expected MissingVolume, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_index_space() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.reserve_index_bytes(256);
+        lib1.write_to_path("test/reserved.rcslib", CompressionLevel::Normal)?;
+
+        let before = std::fs::read("test/reserved.rcslib")?;
+        let data_size = ResourceLibraryReader::new("test/reserved.rcslib")?.total_compressed_size() as usize;
+        let data_end = before.len() - 32;
+        let data_range = data_end - data_size..data_end;
+
+        // Both new entries fit in the reserved space, so the existing data stays where it was
+        let mut editor = ArchiveEditor::open("test/reserved.rcslib")?;
+        editor.write_str("test/c.txt", "Test file C")?;
+        editor.write_str("test/d.txt", "Test file D")?;
+        let report = editor.save(CompressionLevel::None)?;
+
+        let after = std::fs::read("test/reserved.rcslib")?;
+        assert_eq!(after.len() as u64, data_end as u64 + report.compressed_size);
+        assert_eq!(after[data_range.clone()], before[data_range.clone()]);
+
+        // Overflowing the reserved space moves the data section instead
+        for i in 0..10 {
+            editor.write_str(format!("test/e{}.txt", i), "Test file E")?;
+        }
+        editor.save(CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/reserved.rcslib")?;
+        assert_eq!(lib2.len(), 14);
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+        assert_eq!(&*lib2.read_file("test/d.txt")?, b"Test file D");
+        assert_eq!(&*lib2.read_file("test/e9.txt")?, b"Test file E");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_input() -> Result<()> {
+        let data: Vec<u8> = (0..1024 * 1024u64).map(SyntheticStream::byte_at).collect();
+        std::fs::create_dir_all("test/mmap")?;
+        std::fs::write("test/mmap/large.bin", &data)?;
+        std::fs::write("test/mmap/small.bin", b"Small file")?;
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.add_dir("test/mmap", "test")?;
+        lib1.write_str("test/stream.txt", "Not a file")?;
+
+        // Mapped and buffered inputs have to produce the same archive, whichever path the entry takes
+        let configurations: [Box<dyn Fn(&mut ResourceLibraryWriter)>; 3] = [
+            Box::new(|_| ()),
+            Box::new(|lib| lib.set_block_size(Some(64 * 1024))),
+            Box::new(|lib| { lib.set_block_size(None); lib.set_solid(true) })
+        ];
+
+        for configure in configurations {
+            configure(&mut lib1);
+
+            lib1.set_mmap_threshold(u64::MAX);
+            let buffered = lib1.write_to_vec(CompressionLevel::Fast)?;
+            lib1.set_mmap_threshold(1024);
+            let mapped = lib1.write_to_vec(CompressionLevel::Fast)?;
+            assert_eq!(mapped, buffered);
+        }
+
+        lib1.set_solid(false);
+        lib1.write_to_path("test/mmap.rcslib", CompressionLevel::Fast)?;
+        let mut lib2 = ResourceLibraryReader::new("test/mmap.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/large.bin")?, &data[..]);
+        assert_eq!(&*lib2.read_file("test/small.bin")?, b"Small file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncompressed_len() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_str("test/c.txt", "")?;
+        lib1.write_str("test/d.txt", &text[..5000])?;
+        lib1.set_compression("test/b.txt", CompressionLevel::None)?;
+        lib1.set_block_size(Some(4096));
+        lib1.write_to_path("test/uncompressed_len.rcslib", CompressionLevel::Normal)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/uncompressed_len.rcslib")?;
+        for path in ["test/a.txt", "test/b.txt", "test/c.txt", "test/d.txt"] {
+            assert_eq!(lib2.uncompressed_len(path), Some(lib2.read_file(path)?.len() as u64));
+        }
+        assert_eq!(lib2.uncompressed_len("test/a.txt"), Some(text.len() as u64));
+        assert_eq!(lib2.uncompressed_len("test/e.txt"), None);
+
+        // An entry that decompresses to more than its recorded size is rejected
+        let mut lib3 = ResourceLibraryWriter::new();
+        lib3.write_str("test/a.txt", &text)?;
+        let mut archive = lib3.write_to_vec(CompressionLevel::Normal)?;
+        let position = archive.windows(8).position(|window| window == (text.len() as u64).to_be_bytes()).unwrap();
+        archive[position..position + 8].copy_from_slice(&100u64.to_be_bytes());
+        reseal_header(&mut archive);
+        std::fs::write("test/wrong_len.rcslib", &archive)?;
+
+        let mut lib4 = ResourceLibraryReader::new("test/wrong_len.rcslib")?;
+        match lib4.read_file("test/a.txt") {
+            Err(ResourceLibraryError::SizeMismatch(path, 100)) => assert_eq!(path, "test/a.txt"),
+            result => panic!("Expected SizeMismatch, got {:?}", result)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksums() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_compression("test/a.txt", CompressionLevel::None)?;
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        // Flip a byte in the middle of the stored entry, which decodes fine but has the wrong contents
+        let data_pointer = HEADER_SIZE as usize + u64::from_be_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let mut corrupted = archive.clone();
+        corrupted[data_pointer + 100] ^= 0xFF;
+        std::fs::write("test/corrupted.rcslib", &corrupted)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/corrupted.rcslib")?;
+        match lib2.read_file("test/a.txt") {
+            Err(ResourceLibraryError::ChecksumMismatch(path, expected, actual)) => {
+                assert_eq!(path, "test/a.txt");
+                assert_eq!(expected, crc32fast::hash(text.as_bytes()));
+                assert_ne!(expected, actual);
+            },
+            result => panic!("Expected ChecksumMismatch, got {:?}", result)
+        }
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        lib2.set_verify_checksums(false);
+        assert_eq!(lib2.read_file("test/a.txt")?.len(), text.len());
+
+        // Chunked and solid entries are checked too. Their data is compressed, so the recorded checksum is changed instead.
+        let crc = crc32fast::hash(text.as_bytes()).to_be_bytes();
+        lib1.set_compression("test/a.txt", CompressionLevel::Normal)?;
+        for configure in [|lib: &mut ResourceLibraryWriter| lib.set_block_size(Some(4096)), |lib: &mut ResourceLibraryWriter| { lib.set_block_size(None); lib.set_solid(true) }] {
+            configure(&mut lib1);
+            let mut archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+            let position = archive.windows(4).position(|window| window == crc).unwrap();
+            archive[position + 3] ^= 0xFF;
+            reseal_header(&mut archive);
+            std::fs::write("test/corrupted.rcslib", &archive)?;
+
+            match ResourceLibraryReader::new("test/corrupted.rcslib")?.read_file("test/a.txt") {
+                Err(ResourceLibraryError::ChecksumMismatch(..)) => (),
+                result => panic!("Expected ChecksumMismatch, got {:?}", result)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "hashes")]
+    #[test]
+    fn entry_hashes() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", (i * 7919) % 1000)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_str("test/c.txt", &text[..5000])?;
+        lib1.set_compression("test/b.txt", CompressionLevel::None)?;
+        let plain = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        lib1.set_store_hashes(true);
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+        assert_eq!(archive.len(), plain.len() + 3 * 32);
+        std::fs::write("test/hashes.rcslib", &archive)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/hashes.rcslib")?;
+        assert_eq!(lib2.entry_hash("test/a.txt"), Some(<[u8; 32]>::from(Sha256::digest(text.as_bytes()))));
+        assert_eq!(lib2.entry_hash("test/d.txt"), None);
+
+        let report = lib2.verify_all()?;
+        assert!(report.is_ok());
+        assert_eq!(report.verified, 3);
+
+        // Corrupt the stored entry's data and its checksum so that only the hash catches it
+        let mut corrupted = archive.clone();
+        let position = corrupted.windows(11).position(|window| window == b"Test file B").unwrap();
+        corrupted[position] = b'X';
+        let crc = crc32fast::hash(b"Test file B").to_be_bytes();
+        let crc_position = corrupted.windows(4).position(|window| window == crc).unwrap();
+        corrupted[crc_position..crc_position + 4].copy_from_slice(&crc32fast::hash(b"Xest file B").to_be_bytes());
+        reseal_header(&mut corrupted);
+
+        // And break the LZMA stream of another one
+        let data_pointer = HEADER_SIZE as usize + u64::from_be_bytes(corrupted[12..20].try_into().unwrap()) as usize;
+        corrupted[data_pointer + 40] ^= 0xFF;
+        std::fs::write("test/hashes_corrupted.rcslib", &corrupted)?;
+
+        let mut lib3 = ResourceLibraryReader::new("test/hashes_corrupted.rcslib")?;
+        let report = lib3.verify_all()?;
+        assert!(!report.is_ok());
+        assert_eq!(report.verified, 1);
+        assert_eq!(report.failures.iter().map(|(path, _)| &path[..]).collect::<Vec<_>>(), vec!["test/a.txt", "test/b.txt"]);
+        assert!(matches!(report.failures[1].1, ResourceLibraryError::HashMismatch(_)));
+
+        // Archives written without hashes still verify their checksums
+        std::fs::write("test/hashes.rcslib", &plain)?;
+        let mut lib4 = ResourceLibraryReader::new("test/hashes.rcslib")?;
+        assert_eq!(lib4.entry_hash("test/a.txt"), None);
+        assert!(lib4.verify_all()?.is_ok());
+
+        Ok(())
+    }
+
+    // Recomputes the header checksum after a test deliberately edits the index
+    fn reseal_header(archive: &mut [u8]) {
+        let crc_offset = HEADER_SIZE as usize - 4;
+        let index_end = HEADER_SIZE as usize + u64::from_be_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&archive[..crc_offset]);
+        hasher.update(&archive[HEADER_SIZE as usize..index_end]);
+        let crc = hasher.finalize();
+        archive[crc_offset..HEADER_SIZE as usize].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    #[test]
+    fn corrupt_index() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_to_path("test/corrupt_index.rcslib", CompressionLevel::Normal)?;
+        let archive = std::fs::read("test/corrupt_index.rcslib")?;
+
+        // A damaged index byte is caught before any of its offsets are used
+        let mut corrupted = archive.clone();
+        let position = corrupted.windows(5).position(|window| window == b"b.txt").unwrap();
+        corrupted[position] = b'c';
+        std::fs::write("test/corrupt_index.rcslib", &corrupted)?;
+        match ResourceLibraryReader::new("test/corrupt_index.rcslib") {
+            Err(ResourceLibraryError::CorruptIndex) => (),
+            result => panic!("Expected CorruptIndex, got {:?}", result.map(|_| ()))
+        }
+
+        // So is a damaged data size in the header
+        let mut corrupted = archive.clone();
+        corrupted[27] ^= 0x01;
+        std::fs::write("test/corrupt_index.rcslib", &corrupted)?;
+        match ResourceLibraryReader::new("test/corrupt_index.rcslib") {
+            Err(ResourceLibraryError::CorruptIndex) => (),
+            result => panic!("Expected CorruptIndex, got {:?}", result.map(|_| ()))
+        }
+
+        std::fs::write("test/corrupt_index.rcslib", &archive)?;
+        assert_eq!(&*ResourceLibraryReader::new("test/corrupt_index.rcslib")?.read_file("test/b.txt")?, b"Test file B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_mtimes() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.add_file("test/testfile.png", "test/testfile.png")?;
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_mtime("test/a.txt", 1_700_000_000)?;
+        assert!(lib1.set_mtime("test/missing.txt", 0).is_err());
+
+        let expected = std::fs::metadata("test/testfile.png")?.modified()?.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(lib1.get_mtime("test/testfile.png"), Some(expected));
+        lib1.write_to_path("test/mtimes.rcslib", CompressionLevel::Normal)?;
+
+        let lib2 = ResourceLibraryReader::new("test/mtimes.rcslib")?;
+        assert_eq!(lib2.entry_mtime("test/testfile.png"), Some(expected));
+        assert_eq!(lib2.entry_mtime("test/a.txt"), Some(1_700_000_000));
+        assert_eq!(lib2.entry_mtime("test/b.txt"), None);
+        assert_eq!(lib2.entry_mtime("test/missing.txt"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn front_coded_paths() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        let mut paths = Vec::new();
+        for biome in ["desert", "forest", "forest_dense", "tundra"] {
+            for kind in ["albedo", "normal", "roughness"] {
+                for i in 0..25 {
+                    paths.push(format!("textures/environment/{}/{}/variant_{:03}.dds", biome, kind, i));
+                }
+            }
+        }
+        paths.push("textures/environment/forest/ünïcode/ßpecial.dds".to_owned());
+        paths.push("textures/environment/forest/ünïcode/ſpecial.dds".to_owned());
+        for path in &paths {
+            lib1.write_str(path.clone(), path)?;
+        }
+
+        lib1.set_offset_size(OffsetSize::Full);
+        let archive = lib1.write_to_vec(CompressionLevel::None)?;
+        std::fs::write("test/front_coded.rcslib", &archive)?;
+        let mut lib2 = ResourceLibraryReader::new("test/front_coded.rcslib")?;
+        assert_eq!(lib2.len(), paths.len());
+        for path in &paths {
+            assert_eq!(&*lib2.read_file(path)?, path.as_bytes());
+        }
+
+        // The same entries with every path written out in full
+        let index_size = u64::from_be_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let index = entries_from_bytes(&archive[HEADER_SIZE as usize..HEADER_SIZE as usize + index_size], FORMAT_VERSION, false)?;
+        let mut plain = IndexSerializer::plain();
+        index.serialize(&mut plain)?;
+        let plain_size = plain.take().len();
+        // Most of each path is shared with the one before it, which more than makes up for storing the shared length
+        let path_bytes: usize = paths.iter().map(|path| path.len()).sum();
+        assert!(plain_size - index_size > path_bytes / 2, "{} front-coded vs {} plain", index_size, plain_size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_index() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        for i in 0..100_000 {
+            lib1.write_data(format!("textures/environment/zone_{}/asset_{}.bin", i % 50, i), vec![(i % 256) as u8])?;
+        }
+
+        let plain = lib1.write_to_vec(CompressionLevel::None)?;
+        let plain_index = u64::from_be_bytes(plain[12..20].try_into().unwrap());
+
+        lib1.set_compress_index(true);
+        let report = lib1.write_to_path("test/compressed_index.rcslib", CompressionLevel::None)?;
+        let compressed = std::fs::read("test/compressed_index.rcslib")?;
+        let compressed_index = u64::from_be_bytes(compressed[12..20].try_into().unwrap());
+        assert_eq!(report.index_size, compressed_index);
+        assert!(compressed_index * 5 < plain_index, "{} compressed vs {} plain", compressed_index, plain_index);
+
+        let mut lib2 = ResourceLibraryReader::new("test/compressed_index.rcslib")?;
+        assert_eq!(lib2.len(), 100_000);
+        assert_eq!(&*lib2.read_file("textures/environment/zone_7/asset_12357.bin")?, &[(12357 % 256) as u8]);
+
+        // Rewriting the index keeps it compressed, whether it's with the index last, edited, or compacted
+        lib1.set_layout(Layout::IndexLast);
+        let mut streamed = Vec::new();
+        lib1.write_to_stream(&mut streamed, CompressionLevel::None)?;
+        assert!((streamed.len() as u64) < compressed.len() as u64 - plain_index / 2);
+        std::fs::write("test/compressed_index_last.rcslib", &streamed)?;
+        assert_eq!(&*ResourceLibraryReader::new("test/compressed_index_last.rcslib")?.read_file("textures/environment/zone_0/asset_0.bin")?, &[0]);
+
+        let mut editor = ArchiveEditor::open("test/compressed_index.rcslib")?;
+        editor.write_str("extra.txt", "Extra")?;
+        editor.save(CompressionLevel::None)?;
+        compact("test/compressed_index.rcslib", "test/compressed_index_compacted.rcslib")?;
+        let compacted = std::fs::read("test/compressed_index_compacted.rcslib")?;
+        assert!(u64::from_be_bytes(compacted[12..20].try_into().unwrap()) * 5 < plain_index);
+        assert_eq!(&*ResourceLibraryReader::new("test/compressed_index_compacted.rcslib")?.read_file("extra.txt")?, b"Extra");
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_last_layout() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_comment("footer");
+
+        for layout in [Layout::IndexFirst, Layout::IndexLast] {
+            lib1.set_layout(layout);
+            lib1.write_to_path("test/layout.rcslib", CompressionLevel::Normal)?;
+
+            let mut lib2 = ResourceLibraryReader::new("test/layout.rcslib")?;
+            assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+            assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+            assert_eq!(lib2.comment(), Some("footer"));
+        }
+
+        // The index is at the end, so the archive can be written without ever seeking back
+        let mut streamed = Vec::new();
+        lib1.set_layout(Layout::IndexFirst);
+        lib1.write_to_stream(&mut streamed, CompressionLevel::Normal)?;
+        assert_eq!(streamed, std::fs::read("test/layout.rcslib")?);
+
+        // A cut-off trailer is caught instead of reading whatever happens to be at the end
+        std::fs::write("test/layout_truncated.rcslib", &streamed[..streamed.len() - 5])?;
+        match ResourceLibraryReader::new("test/layout_truncated.rcslib") {
+            Err(ResourceLibraryError::CorruptIndex) => (),
+            result => panic!("Expected CorruptIndex, got {:?}", result.map(|_| ()))
+        }
+
+        // Editing moves the index to the front
+        let mut editor = ArchiveEditor::open("test/layout.rcslib")?;
+        editor.write_str("test/c.txt", "Test file C")?;
+        editor.save(CompressionLevel::Normal)?;
+        let mut lib3 = ResourceLibraryReader::new("test/layout.rcslib")?;
+        assert_eq!(&*lib3.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib3.read_file("test/c.txt")?, b"Test file C");
+        assert_eq!(lib3.comment(), Some("footer"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_flags() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_block_size(Some(4096));
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        let index_size = u64::from_be_bytes(archive[12..20].try_into().unwrap()) as usize;
+        let index = entries_from_bytes(&archive[HEADER_SIZE as usize..HEADER_SIZE as usize + index_size], FORMAT_VERSION, true)?;
+        assert_eq!(index.iter().map(|entry| entry.flags).collect::<Vec<_>>(), vec![1, 0]);
+
+        // Rewrites the archive with extra flags set on test/b.txt
+        let with_flags = |flags: u16| -> Result<Vec<u8>> {
+            let mut index = index.clone();
+            index[1].flags |= flags;
+
+            let mut rewritten = archive.clone();
+            rewritten[HEADER_SIZE as usize..HEADER_SIZE as usize + index_size].copy_from_slice(&entries_to_bytes(&index, true)?);
+            reseal_header(&mut rewritten);
+
+            Ok(rewritten)
+        };
+
+        // Bits outside the critical mask can be ignored
+        std::fs::write("test/entry_flags.rcslib", with_flags(0x4000)?)?;
+        let mut lib2 = ResourceLibraryReader::new("test/entry_flags.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        // But unknown critical bits mean the entry can't be read correctly
+        std::fs::write("test/entry_flags.rcslib", with_flags(0x0040)?)?;
+        match ResourceLibraryReader::new("test/entry_flags.rcslib") {
+            Err(ResourceLibraryError::UnsupportedEntryFlags(path, 0x0040)) => assert_eq!(path, "test/b.txt"),
+            result => panic!("Expected UnsupportedEntryFlags, got {:?}", result.map(|_| ()))
+        }
+        assert!(matches!(ArchiveEditor::open("test/entry_flags.rcslib"), Err(ResourceLibraryError::UnsupportedEntryFlags(..))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn content_types() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_data("images/no_extension", &b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"[..])?;
+        lib1.write_data("audio/clip.bin", &b"OggS\0\x02rest of the page"[..])?;
+        lib1.write_data("code/module.wasm", &b"\0asm\x01\0\0\0"[..])?;
+        lib1.write_str("data/config.json", "{\"key\": 1}")?;
+        lib1.write_str("data/override.json", "{}")?;
+        lib1.write_data("data/unknown.xyz", &b"nothing recognizable"[..])?;
+        lib1.set_content_type("data/override.json", "application/vnd.custom+json")?;
+
+        // Nothing is detected unless asked for
+        lib1.write_to_path("test/content_types.rcslib", CompressionLevel::Normal)?;
+        let lib2 = ResourceLibraryReader::new("test/content_types.rcslib")?;
+        assert_eq!(lib2.content_type("images/no_extension"), None);
+        assert_eq!(lib2.content_type("data/override.json"), Some("application/vnd.custom+json"));
+
+        lib1.set_detect_content_types(true);
+        lib1.write_to_path("test/content_types.rcslib", CompressionLevel::Normal)?;
+        let mut lib3 = ResourceLibraryReader::new("test/content_types.rcslib")?;
+        assert_eq!(lib3.content_type("images/no_extension"), Some("image/png"));
+        assert_eq!(lib3.content_type("audio/clip.bin"), Some("audio/ogg"));
+        assert_eq!(lib3.content_type("code/module.wasm"), Some("application/wasm"));
+        assert_eq!(lib3.content_type("data/config.json"), Some("application/json"));
+        assert_eq!(lib3.content_type("data/override.json"), Some("application/vnd.custom+json"));
+        assert_eq!(lib3.content_type("data/unknown.xyz"), None);
+        assert_eq!(lib3.content_type("missing"), None);
+
+        // Sniffing doesn't disturb the entries themselves
+        assert_eq!(&*lib3.read_file("images/no_extension")?, b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR");
+        assert_eq!(&*lib3.read_file("data/config.json")?, b"{\"key\": 1}");
+
+        assert_eq!(content_type::detect_content_type("photo.JPG", b""), Some("image/jpeg"));
+        assert_eq!(content_type::detect_content_type("photo", b"\xFF\xD8\xFF\xE0"), Some("image/jpeg"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_comment() -> Result<()> {
+        let comment: String = (0..500).map(|i| format!("build {} ", i)).collect();
+        assert!(comment.len() > 4096);
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.set_comment(&comment);
+        lib1.write_to_path("test/comment.rcslib", CompressionLevel::Normal)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/comment.rcslib")?;
+        assert_eq!(lib2.comment(), Some(&comment[..]));
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+
+        // Editing and compacting keep the comment
+        let mut editor = ArchiveEditor::open("test/comment.rcslib")?;
+        editor.write_str("test/b.txt", "Test file B")?;
+        editor.save(CompressionLevel::Normal)?;
+        compact("test/comment.rcslib", "test/comment_compacted.rcslib")?;
+        let mut lib3 = ResourceLibraryReader::new("test/comment_compacted.rcslib")?;
+        assert_eq!(lib3.comment(), Some(&comment[..]));
+        assert_eq!(&*lib3.read_file("test/b.txt")?, b"Test file B");
+
+        lib1.set_comment("");
+        lib1.write_to_path("test/comment.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(ResourceLibraryReader::new("test/comment.rcslib")?.comment(), Some(""));
+
+        lib1.clear_comment();
+        lib1.write_to_path("test/comment.rcslib", CompressionLevel::Normal)?;
+        assert_eq!(ResourceLibraryReader::new("test/comment.rcslib")?.comment(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_metadata() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_metadata("test/a.txt", "format", b"bc7")?;
+        lib1.set_metadata("test/a.txt", "guid", &[0x12, 0x34, 0x56, 0x78])?;
+        lib1.set_metadata("test/a.txt", "empty", b"")?;
+        lib1.set_metadata("test/a.txt", "format", b"bc1")?;
+
+        assert!(matches!(lib1.set_metadata("test/a.txt", "bad:key", b""), Err(ResourceLibraryError::PathError(PathError::DisallowedCharacter(':', _)))));
+        assert!(matches!(lib1.set_metadata("test/b.txt", "big", &vec![0; 64 * 1024]), Err(ResourceLibraryError::MetadataTooLarge(..))));
+        assert!(lib1.set_metadata("test/missing.txt", "key", b"").is_err());
+        assert_eq!(lib1.get_metadata("test/b.txt"), None);
+
+        lib1.write_to_path("test/metadata.rcslib", CompressionLevel::Normal)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/metadata.rcslib")?;
+        let metadata = lib2.metadata("test/a.txt").unwrap();
+        assert_eq!(metadata.keys().collect::<Vec<_>>(), vec!["empty", "format", "guid"]);
+        assert_eq!(&*metadata["format"], b"bc1");
+        assert_eq!(&*metadata["guid"], &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(&*metadata["empty"], b"");
+        assert_eq!(lib2.metadata("test/b.txt"), None);
+        assert_eq!(lib2.metadata("test/missing.txt"), None);
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entry_modes() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::write("test/script.sh", "#!/bin/sh\necho hello\n")?;
+        std::fs::set_permissions("test/script.sh", std::fs::Permissions::from_mode(0o755))?;
+        std::fs::write("test/plain.txt", "Plain file")?;
+        std::fs::set_permissions("test/plain.txt", std::fs::Permissions::from_mode(0o644))?;
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.add_file("test/script.sh", "bin/script.sh")?;
+        lib1.add_file("test/plain.txt", "plain.txt")?;
+        lib1.write_str("generated.txt", "Not from disk")?;
+        lib1.write_to_path("test/modes.rcslib", CompressionLevel::Normal)?;
+
+        let lib2 = ResourceLibraryReader::new("test/modes.rcslib")?;
+        assert_eq!(lib2.entry_mode("bin/script.sh"), Some(0o755));
+        assert_eq!(lib2.entry_mode("plain.txt"), Some(0o644));
+        assert_eq!(lib2.entry_mode("generated.txt"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_offsets() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        for i in 0..100 {
+            lib1.write_str(format!("test/{}.txt", i), &format!("Test file {}", i))?;
+        }
+
+        // Small archives get a compact index by default
+        let compact = lib1.write_to_vec(CompressionLevel::Normal)?;
+        lib1.set_offset_size(OffsetSize::Full);
+        let full = lib1.write_to_vec(CompressionLevel::Normal)?;
+        assert!(compact.len() < full.len(), "{} compact vs {} full", compact.len(), full.len());
+
+        for (name, archive) in [("compact", &compact), ("full", &full)] {
+            let path = format!("test/offsets_{}.rcslib", name);
+            std::fs::write(&path, archive)?;
+            let mut lib2 = ResourceLibraryReader::new(&path)?;
+            for i in 0..100 {
+                assert_eq!(&*lib2.read_file(format!("test/{}.txt", i))?, format!("Test file {}", i).as_bytes());
+            }
+        }
+
+        // A resource that claims to be over 4 GB can't go in a forced compact index
+        #[derive(Debug)]
+        struct Huge;
+        impl Read for Huge {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Ok(0)
+            }
+        }
+        impl Seek for Huge {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                Ok(match pos {
+                    SeekFrom::End(_) => 5 << 30,
+                    _ => 0
+                })
+            }
+        }
+
+        let mut lib3 = ResourceLibraryWriter::new();
+        lib3.write_stream("test/huge.bin", Huge)?;
+        lib3.set_offset_size(OffsetSize::Compact);
+        match lib3.write_to_vec(CompressionLevel::None) {
+            Err(ResourceLibraryError::SerializationError(SerializationError::CompactOverflow(_))) => (),
+            result => panic!("Expected CompactOverflow, got {:?}", result.map(|archive| archive.len()))
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_archive() -> Result<()> {
+        // Long enough to span several encrypted segments
+        let text: String = (0..100_000).map(|i| format!("{} ", i)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_compression("test/a.txt", CompressionLevel::None)?;
+        lib1.set_encryption(Some(ArchiveKey::password("hunter2")));
+        lib1.write_to_path("test/encrypted.rcslib", CompressionLevel::Normal)?;
+
+        let archive = std::fs::read("test/encrypted.rcslib")?;
+        assert!(!archive.windows(10).any(|window| window == b"test/a.txt"));
+
+        let mut lib2 = ResourceLibraryReader::open_encrypted("test/encrypted.rcslib", &ArchiveKey::password("hunter2"))?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        match ResourceLibraryReader::new("test/encrypted.rcslib") {
+            Err(ResourceLibraryError::Encrypted) => (),
+            result => panic!("Expected Encrypted, got {:?}", result.map(|_| ()))
+        }
+
+        match ResourceLibraryReader::open_encrypted("test/encrypted.rcslib", &ArchiveKey::password("hunter3")) {
+            Err(ResourceLibraryError::WrongKey) => (),
+            result => panic!("Expected WrongKey, got {:?}", result.map(|_| ()))
+        }
+
+        // Raw keys work with either layout, and with solid archives
+        let key = ArchiveKey::Raw([7; 32]);
+        lib1.set_encryption(Some(key.clone()));
+        lib1.set_solid(true);
+        let mut streamed = Vec::new();
+        lib1.write_to_stream(&mut streamed, CompressionLevel::Normal)?;
+        std::fs::write("test/encrypted_stream.rcslib", &streamed)?;
+
+        let mut lib3 = ResourceLibraryReader::open_encrypted("test/encrypted_stream.rcslib", &key)?;
+        assert_eq!(&*lib3.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib3.read_file("test/b.txt")?, b"Test file B");
+
+        // Changing a single byte of the data is caught when it's read
+        lib1.set_solid(false);
+        let mut tampered = lib1.write_to_vec(CompressionLevel::Normal)?;
+        let last = tampered.len() - 1 - 32;
+        tampered[last] ^= 1;
+        std::fs::write("test/encrypted_tampered.rcslib", &tampered)?;
+
+        let mut lib4 = ResourceLibraryReader::open_encrypted("test/encrypted_tampered.rcslib", &key)?;
+        match lib4.read_file("test/b.txt") {
+            Err(ResourceLibraryError::AuthenticationFailed) => (),
+            result => panic!("Expected AuthenticationFailed, got {:?}", result)
+        }
+
+        // As is a changed index, even with the header checksum updated to match
+        let index_end = HEADER_SIZE as usize + u64::from_be_bytes(tampered[12..20].try_into().unwrap()) as usize;
+        tampered[last] ^= 1;
+        tampered[index_end - 1] ^= 1;
+        reseal_header(&mut tampered);
+        std::fs::write("test/encrypted_tampered.rcslib", &tampered)?;
+
+        match ResourceLibraryReader::open_encrypted("test/encrypted_tampered.rcslib", &key) {
+            Err(ResourceLibraryError::AuthenticationFailed) => (),
+            result => panic!("Expected AuthenticationFailed, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_entries() -> Result<()> {
+        let text: String = (0..10000).map(|i| format!("{} ", i % 100)).collect();
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_stream_encrypted("test/b.txt", ByteStream::from(b"Secret file B".to_vec()))?;
+        lib1.write_str("test/c.txt", &text)?;
+        lib1.set_encrypted("test/c.txt", true)?;
+        lib1.set_block_size(Some(4096));
+
+        match lib1.write_to_vec(CompressionLevel::Normal) {
+            Err(ResourceLibraryError::MissingKey(path)) => assert_eq!(path, "test/b.txt"),
+            result => panic!("Expected MissingKey, got {:?}", result.map(|archive| archive.len()))
+        }
+
+        lib1.set_entry_key(Some(ArchiveKey::password("hunter2")));
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+        assert!(!archive.windows(6).any(|window| window == b"Secret"));
+        std::fs::write("test/encrypted_entries.rcslib", &archive)?;
+
+        // Plain entries are read without ever asking for the key
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let mut lib2 = ResourceLibraryReader::new("test/encrypted_entries.rcslib")?;
+        assert!(!lib2.is_encrypted("test/a.txt"));
+        assert!(lib2.is_encrypted("test/b.txt"));
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        match lib2.read_file("test/b.txt") {
+            Err(ResourceLibraryError::MissingKey(path)) => assert_eq!(path, "test/b.txt"),
+            result => panic!("Expected MissingKey, got {:?}", result)
+        }
+
+        let provider_asked = asked.clone();
+        lib2.set_key_provider(move |path| {
+            provider_asked.lock().unwrap().push(path.to_owned());
+            Some(ArchiveKey::password("hunter2"))
+        });
+        assert_eq!(&*lib2.read_file("test/a.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Secret file B");
+        assert_eq!(&*lib2.read_file("test/c.txt")?, text.as_bytes());
+        assert_eq!(*asked.lock().unwrap(), ["test/b.txt", "test/c.txt"]);
+
+        lib2.set_key_provider(|_| Some(ArchiveKey::Raw([0; 32])));
+        match lib2.read_file("test/c.txt") {
+            Err(ResourceLibraryError::AuthenticationFailed) => (),
+            result => panic!("Expected AuthenticationFailed, got {:?}", result)
+        }
+
+        lib1.set_solid(true);
+        match lib1.write_to_vec(CompressionLevel::Normal) {
+            Err(ResourceLibraryError::SolidEncryptedEntry(path)) => assert_eq!(path, "test/b.txt"),
+            result => panic!("Expected SolidEncryptedEntry, got {:?}", result.map(|archive| archive.len()))
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[test]
+    fn encryption_disabled() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        let mut archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        let flags = u16::from_be_bytes([archive[28], archive[29]]) | 64;
+        archive[28..30].copy_from_slice(&flags.to_be_bytes());
+        reseal_header(&mut archive);
+
+        match ResourceLibraryReader::from_bytes(archive) {
+            Err(ResourceLibraryError::EncryptionNotEnabled) => (),
+            result => panic!("Expected EncryptionNotEnabled, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed_archive() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_to_path("test/signed.rcslib", CompressionLevel::Normal)?;
+        let _ = std::fs::remove_file(signature_path(Path::new("test/signed.rcslib")));
+
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let public_key = signing_key.verifying_key();
+
+        match ResourceLibraryReader::open_verified("test/signed.rcslib", &public_key) {
+            Err(ResourceLibraryError::Unsigned) => (),
+            result => panic!("Expected Unsigned, got {:?}", result.map(|_| ()))
+        }
+
+        sign("test/signed.rcslib", &signing_key)?;
+        let mut lib2 = ResourceLibraryReader::open_verified("test/signed.rcslib", &public_key)?;
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        // The signature has to come from the expected key
+        let other_key = SigningKey::from_bytes(&[2; 32]).verifying_key();
+        match ResourceLibraryReader::open_verified("test/signed.rcslib", &other_key) {
+            Err(ResourceLibraryError::BadSignature) => (),
+            result => panic!("Expected BadSignature, got {:?}", result.map(|_| ()))
+        }
+
+        // Any change to the archive breaks the signature, even one that would otherwise go unnoticed
+        let mut archive = std::fs::read("test/signed.rcslib")?;
+        let last = archive.len() - 1;
+        archive[last] ^= 1;
+        std::fs::write("test/signed_tampered.rcslib", &archive)?;
+        std::fs::copy(signature_path(Path::new("test/signed.rcslib")), signature_path(Path::new("test/signed_tampered.rcslib")))?;
+        match ResourceLibraryReader::open_verified("test/signed_tampered.rcslib", &public_key) {
+            Err(ResourceLibraryError::BadSignature) => (),
+            result => panic!("Expected BadSignature, got {:?}", result.map(|_| ()))
+        }
+
+        // Signed archives still open normally
+        assert_eq!(&*ResourceLibraryReader::new("test/signed.rcslib")?.read_file("test/a.txt")?, b"Test file A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_fingerprint() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        let report1 = lib1.write_to_path("test/fingerprint1.rcslib", CompressionLevel::Normal)?;
+        let report2 = lib1.write_to_path("test/fingerprint2.rcslib", CompressionLevel::Normal)?;
+
+        // Identical inputs pack to the same fingerprint, which the reader reports
+        assert!(report1.fingerprint.is_some());
+        assert_eq!(report1.fingerprint, report2.fingerprint);
+        let mut reader = ResourceLibraryReader::new("test/fingerprint1.rcslib")?;
+        assert_eq!(reader.fingerprint()?, report1.fingerprint);
+        assert!(reader.verify_fingerprint()?);
+
+        // As does an index-last archive
+        lib1.set_layout(Layout::IndexLast);
+        let mut streamed = Vec::new();
+        let report3 = lib1.write_to_stream(&mut streamed, CompressionLevel::Normal)?;
+        std::fs::write("test/fingerprint_stream.rcslib", &streamed)?;
+        let mut reader = ResourceLibraryReader::new("test/fingerprint_stream.rcslib")?;
+        assert_eq!(reader.fingerprint()?, report3.fingerprint);
+        assert!(reader.verify_fingerprint()?);
+
+        // Changing an entry changes the fingerprint
+        lib1.set_layout(Layout::IndexFirst);
+        lib1.write_str("test/b.txt", "Test file C")?;
+        let report4 = lib1.write_to_path("test/fingerprint3.rcslib", CompressionLevel::Normal)?;
+        assert_ne!(report1.fingerprint, report4.fingerprint);
+
+        // A truncated archive no longer matches
+        let archive = std::fs::read("test/fingerprint1.rcslib")?;
+        std::fs::write("test/fingerprint_truncated.rcslib", &archive[..archive.len() - 40])?;
+        let mut reader = ResourceLibraryReader::new("test/fingerprint_truncated.rcslib")?;
+        assert!(!reader.verify_fingerprint()?);
+
+        // Editing an archive drops its fingerprint rather than leaving a stale one
+        let mut editor = ArchiveEditor::open("test/fingerprint2.rcslib")?;
+        editor.write_str("test/c.txt", "Test file C")?;
+        assert_eq!(editor.save(CompressionLevel::Normal)?.fingerprint, None);
+        assert_eq!(ResourceLibraryReader::new("test/fingerprint2.rcslib")?.fingerprint()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn alias_entries() -> Result<()> {
+        let text = "Test file A ".repeat(100);
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", &text)?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.add_alias("test/alias.txt".to_owned(), "test/a.txt")?;
+        // Chains resolve to the entry at the end
+        lib1.add_alias("test/chain.txt".to_owned(), "test/alias.txt")?;
+        match lib1.add_alias("test/b.txt".to_owned(), "test/a.txt") {
+            Err(ResourceLibraryError::PathError(PathError::PathExists(_))) => (),
+            result => panic!("Expected PathExists, got {:?}", result)
+        }
+        lib1.write_to_path("test/alias.rcslib", CompressionLevel::Normal)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/alias.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), &["test/a.txt", "test/alias.txt", "test/b.txt", "test/chain.txt"]);
+        assert_eq!(&*lib2.read_file("test/alias.txt")?, text.as_bytes());
+        assert_eq!(&*lib2.read_file("test/chain.txt")?, text.as_bytes());
+        assert_eq!(lib2.alias_target("test/alias.txt"), Some("test/a.txt"));
+        assert_eq!(lib2.alias_target("test/chain.txt"), Some("test/a.txt"));
+        assert_eq!(lib2.alias_target("test/a.txt"), None);
+
+        // The data is only stored once
+        let stored = lib2.compressed_len("test/a.txt").unwrap() + lib2.compressed_len("test/b.txt").unwrap();
+        assert_eq!(lib2.total_compressed_size(), stored);
+        drop(lib2);
+
+        // Replacing a target brings its aliases along
+        let mut editor = ArchiveEditor::open("test/alias.rcslib")?;
+        editor.replace_entry("test/a.txt", b"Replaced".to_vec(), CompressionLevel::None)?;
+        let mut lib2 = ResourceLibraryReader::new("test/alias.rcslib")?;
+        assert_eq!(&*lib2.read_file("test/alias.txt")?, b"Replaced");
+        assert_eq!(&*lib2.read_file("test/chain.txt")?, b"Replaced");
+
+        // Targets have to exist by the time the archive is written
+        let mut lib3 = ResourceLibraryWriter::new();
+        lib3.write_str("test/a.txt", "Test file A")?;
+        lib3.add_alias("test/dangling.txt".to_owned(), "test/missing.txt")?;
+        match lib3.write_to_vec(CompressionLevel::Normal) {
+            Err(ResourceLibraryError::DanglingAlias(alias, target)) => assert_eq!((&alias[..], &target[..]), ("test/dangling.txt", "test/missing.txt")),
+            result => panic!("Expected DanglingAlias, got {:?}", result.map(|_| ()))
+        }
+
+        // And aliases can't point back at themselves
+        let mut lib4 = ResourceLibraryWriter::new();
+        lib4.add_alias("test/x.txt".to_owned(), "test/y.txt")?;
+        lib4.add_alias("test/y.txt".to_owned(), "test/x.txt")?;
+        match lib4.write_to_vec(CompressionLevel::Normal) {
+            Err(ResourceLibraryError::AliasCycle(_)) => (),
+            result => panic!("Expected AliasCycle, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn alias_edits() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.write_str("levels/1.bin", "Level 1")?;
+        lib1.add_alias("test/alias.txt".to_owned(), "test/a.txt")?;
+        lib1.add_alias("test/chain.txt".to_owned(), "test/alias.txt")?;
+        lib1.add_alias("test/level.bin".to_owned(), "levels/1.bin")?;
+
+        // Removing a target takes the aliases that lead to it along
+        lib1.remove_file("test/a.txt")?;
+        assert_eq!(lib1.remove_prefix("levels/"), 1);
+        let mut lib2 = ResourceLibraryReader::from_bytes(lib1.write_to_vec(CompressionLevel::Normal)?)?;
+        assert_eq!(&*lib2.get_all_files(), ["test/b.txt"]);
+        assert_eq!(&*lib2.read_file("test/b.txt")?, b"Test file B");
+
+        // Renaming a target brings its aliases along, but nothing can be renamed onto an alias
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.add_alias("test/alias.txt".to_owned(), "test/a.txt")?;
+        lib1.add_alias("test/chain.txt".to_owned(), "test/alias.txt")?;
+        match lib1.rename_file("test/b.txt", "test/alias.txt") {
+            Err(ResourceLibraryError::PathError(PathError::PathExists(_))) => (),
+            result => panic!("Expected PathExists, got {:?}", result)
+        }
+        lib1.rename_prefix("test/b.txt", "test/alias.txt").expect_err("test/alias.txt should collide!");
+
+        lib1.rename_file("test/a.txt", "renamed/a.txt")?;
+        let mut lib2 = ResourceLibraryReader::from_bytes(lib1.write_to_vec(CompressionLevel::Normal)?)?;
+        assert_eq!(lib2.alias_target("test/alias.txt"), Some("renamed/a.txt"));
+        assert_eq!(&*lib2.read_file("test/chain.txt")?, b"Test file A");
+
+        assert_eq!(lib1.rename_prefix("renamed/", "moved/")?, 1);
+        let mut lib2 = ResourceLibraryReader::from_bytes(lib1.write_to_vec(CompressionLevel::Normal)?)?;
+        assert_eq!(lib2.alias_target("test/chain.txt"), Some("moved/a.txt"));
+        assert_eq!(&*lib2.read_file("test/alias.txt")?, b"Test file A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn creation_info() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.set_comment("Packed for testing");
+        lib1.set_creation_info(true);
+        lib1.set_tool(Some("asset-pipeline 2.3"));
+        lib1.write_to_path("test/creation_info.rcslib", CompressionLevel::Normal)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/creation_info.rcslib")?;
+        let info = lib2.creation_info().unwrap();
+        assert_eq!(info.library_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.tool.as_deref(), Some("asset-pipeline 2.3"));
+        assert!(info.created.is_some());
+        assert_eq!(lib2.comment(), Some("Packed for testing"));
+        assert_eq!(&*lib2.read_file("test/a.txt")?, b"Test file A");
+
+        // Deterministic archives leave out the timestamp, so packing twice gives the same bytes
+        lib1.set_deterministic(true);
+        lib1.set_layout(Layout::IndexLast);
+        let mut streamed = Vec::new();
+        lib1.write_to_stream(&mut streamed, CompressionLevel::Normal)?;
+        assert_eq!(lib1.write_to_vec(CompressionLevel::Normal)?, streamed);
+        std::fs::write("test/creation_info_deterministic.rcslib", &streamed)?;
+
+        let lib3 = ResourceLibraryReader::new("test/creation_info_deterministic.rcslib")?;
+        let info = lib3.creation_info().unwrap();
+        assert_eq!(info.tool.as_deref(), Some("asset-pipeline 2.3"));
+        assert_eq!(info.created, None);
+
+        // Nothing is recorded unless asked for
+        lib1.set_creation_info(false);
+        lib1.write_to_path("test/creation_info_none.rcslib", CompressionLevel::Normal)?;
+        assert!(ResourceLibraryReader::new("test/creation_info_none.rcslib")?.creation_info().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_field_sizes() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_comment("Sized");
+        let archive = lib1.write_to_vec(CompressionLevel::Normal)?;
+
+        // Every size in the header is 8 bytes wide no matter what target wrote it: the index size and data size,
+        // then the comment's length ahead of the comment, the index itself, the data and the fingerprint
+        let index_size = u64::from_be_bytes(archive[12..20].try_into().unwrap());
+        let data_size = u64::from_be_bytes(archive[20..28].try_into().unwrap());
+        let comment_start = HEADER_SIZE as usize;
+        assert_eq!(u64::from_be_bytes(archive[comment_start..comment_start + 8].try_into().unwrap()), 5);
+        assert_eq!(&archive[comment_start + 8..comment_start + 13], b"Sized");
+        assert_eq!(archive.len() as u64, HEADER_SIZE + 8 + 5 + index_size + data_size + 32);
+
+        // A header written with a 4-byte index size is rejected instead of being read as a huge index
+        let mut narrow = archive[..12].to_vec();
+        narrow.extend((index_size as u32).to_be_bytes());
+        narrow.extend(&archive[20..]);
+        std::fs::write("test/narrow_header.rcslib", &narrow)?;
+        match ResourceLibraryReader::new("test/narrow_header.rcslib") {
+            Err(ResourceLibraryError::IndexOutOfBounds(size, available)) => assert!(size > available),
+            result => panic!("Expected IndexOutOfBounds, got {:?}", result.map(|_| ()))
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_index() -> Result<()> {
+        fn entry(blob: &mut Vec<u8>, path: &str, offset: u64, len: u64) {
+            blob.extend((path.len() as u64).to_be_bytes());
+            blob.extend(path.as_bytes());
+            blob.extend(offset.to_be_bytes());
+            blob.extend(len.to_be_bytes());
+        }
+
+        fn expect_error(blob: &[u8], message: &str) {
+            match index_from_bytes(blob) {
+                Err(SerializationError::DeserializeError(error)) => assert!(error.contains(message), "Expected {:?}, got {:?}", message, error),
+                result => panic!("Expected a DeserializeError, got {:?}", result)
+            }
+        }
+
+        let mut valid = 2u64.to_be_bytes().to_vec();
+        entry(&mut valid, "test/a.txt", 0, 10);
+        entry(&mut valid, "test/b.txt", 10, 10);
+        assert_eq!(index_from_bytes(&valid)?.len(), 2);
+
+        let mut trailing = valid.clone();
+        trailing.extend(b"garbage");
+        expect_error(&trailing, "7 trailing bytes");
+
+        let mut duplicate = 2u64.to_be_bytes().to_vec();
+        entry(&mut duplicate, "test/a.txt", 0, 10);
+        entry(&mut duplicate, "test/a.txt", 10, 10);
+        expect_error(&duplicate, "Duplicate path in index: test/a.txt");
+
+        let mut long_string = 1u64.to_be_bytes().to_vec();
+        long_string.extend((1u64 << 60).to_be_bytes());
+        long_string.extend(b"test/a.txt");
+        expect_error(&long_string, "String length 1152921504606846976");
+
+        let mut huge_count = (1u64 << 60).to_be_bytes().to_vec();
+        entry(&mut huge_count, "test/a.txt", 0, 10);
+        expect_error(&huge_count, "Declared 1152921504606846976 elements");
+
+        // The same checks apply to current indexes
+        let entries = entries_from_bytes(&entries_to_bytes(&[IndexEntry::new(ResourcePath::new("test/a.txt")?)], false)?, FORMAT_VERSION, false)?;
+        let mut doubled = entries_to_bytes(&[entries[0].clone(), entries[0].clone()], false)?.into_vec();
+        match entries_from_bytes(&doubled, FORMAT_VERSION, false) {
+            Err(SerializationError::DeserializeError(error)) => assert!(error.contains("Duplicate path"), "{}", error),
+            result => panic!("Expected a DeserializeError, got {:?}", result)
+        }
+        doubled.push(0);
+        assert!(entries_from_bytes(&doubled, FORMAT_VERSION, false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_entries() -> Result<()> {
+        // Every field survives the struct encoding, in both integer widths
+        let entry = IndexEntry {
+            path: ResourcePath::new("test/a.txt")?,
+            offset: 12,
+            compressed_len: 34,
+            codec: Codec::Stored.id(),
+            block_size: 16,
+            blocks: vec![10, 24],
+            volume: 1,
+            original_len: 32,
+            crc32: 0xDEADBEEF,
+            sha256: vec![7; 32],
+            mtime: 1_700_000_000,
+            mode: 0o644,
+            metadata: std::collections::BTreeMap::from([("key".to_owned(), b"value".to_vec().into_boxed_slice())]),
+            content_type: "text/plain".to_owned(),
+            flags: 1,
+            target: String::new()
+        };
+        let entries = [entry.clone(), IndexEntry::new(ResourcePath::new("test/b.txt")?)];
+        for compact in [false, true] {
+            assert_eq!(&*entries_from_bytes(&entries_to_bytes(&entries, compact)?, FORMAT_VERSION, compact)?, &entries);
+        }
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.write_str("test/b.txt", "Test file B")?;
+        lib1.set_mtime("test/b.txt", 1_700_000_000)?;
+        lib1.write_to_path("test/index_entries.rcslib", CompressionLevel::Normal)?;
+
+        let lib2 = ResourceLibraryReader::new("test/index_entries.rcslib")?;
+        let entries = lib2.index_entries();
+        assert_eq!(entries.iter().map(|entry| &entry.path[..]).collect::<Vec<_>>(), ["test/a.txt", "test/b.txt"]);
+        assert_eq!(entries[0].original_len, 11);
+        assert_eq!(entries[1].mtime, 1_700_000_000);
+        assert_eq!(entries[1].offset, entries[0].offset + entries[0].compressed_len);
+
+        Ok(())
+    }
+
+    #[test]
+    fn map_serialization() -> Result<()> {
+        use std::collections::BTreeMap;
+        use serde::{Deserialize, Serialize};
+
+        fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> std::result::Result<T, SerializationError> {
+            let mut serializer = IndexSerializer::new();
+            value.serialize(&mut serializer)?;
+            let bytes = serializer.take();
+
+            T::deserialize(&mut IndexDeserializer::new(&bytes))
+        }
+
+        let sizes = BTreeMap::from([("a".to_owned(), 1u64), ("b".to_owned(), u64::MAX), ("c".to_owned(), 0)]);
+        assert_eq!(round_trip(&sizes)?, sizes);
+        assert_eq!(round_trip(&BTreeMap::<String, u64>::new())?, BTreeMap::new());
+
+        // Maps nested in sequences, and maps of bytes like entry metadata
+        let nested = vec![sizes.clone(), BTreeMap::new(), BTreeMap::from([("d".to_owned(), 4)])];
+        assert_eq!(round_trip(&nested)?, nested);
+        let attributes = BTreeMap::from([("key".to_owned(), b"value".to_vec().into_boxed_slice()), ("empty".to_owned(), Box::default())]);
+        assert_eq!(round_trip(&attributes)?, attributes);
+
+        // A map claiming more pairs than there are bytes fails before anything is read
+        let mut serializer = IndexSerializer::new();
+        sizes.serialize(&mut serializer)?;
+        let mut bytes = serializer.take().into_vec();
+        bytes[..8].copy_from_slice(&u64::MAX.to_be_bytes());
+        match BTreeMap::<String, u64>::deserialize(&mut IndexDeserializer::new(&bytes)) {
+            Err(SerializationError::DeserializeError(error)) => assert!(error.contains("Declared"), "{}", error),
+            result => panic!("Expected a DeserializeError, got {:?}", result)
+        }
+
+        // As does one that runs out partway through
+        bytes[..8].copy_from_slice(&4u64.to_be_bytes());
+        assert!(BTreeMap::<String, u64>::deserialize(&mut IndexDeserializer::new(&bytes)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn option_serialization() -> Result<()> {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct OptionalFields {
+            path: String,
+            mtime: Option<u64>,
+            content_type: Option<String>,
+            blocks: Vec<Option<u64>>
+        }
+
+        fn to_bytes<T: Serialize>(value: &T) -> std::result::Result<Box<[u8]>, SerializationError> {
+            let mut serializer = IndexSerializer::new();
+            value.serialize(&mut serializer)?;
+
+            Ok(serializer.take())
+        }
+
+        // None is just the tag, Some is the tag and the value
+        assert_eq!(&*to_bytes(&None::<u64>)?, [0]);
+        assert_eq!(&*to_bytes(&Some(5u64))?, [1, 0, 0, 0, 0, 0, 0, 0, 5]);
+
+        let entries = vec![
+            OptionalFields { path: "test/a.txt".to_owned(), mtime: Some(1_700_000_000), content_type: Some("text/plain".to_owned()), blocks: vec![Some(1), None] },
+            OptionalFields { path: "test/b.txt".to_owned(), mtime: None, content_type: None, blocks: Vec::new() }
+        ];
+        let bytes = to_bytes(&entries)?;
+        assert_eq!(Vec::<OptionalFields>::deserialize(&mut IndexDeserializer::front_coded(&bytes))?, entries);
+
+        let tuple = (Some("test/a.txt".to_owned()), None::<u64>, Some(3u64));
+        assert_eq!(<(Option<String>, Option<u64>, Option<u64>)>::deserialize(&mut IndexDeserializer::new(&to_bytes(&tuple)?))?, tuple);
+
+        // A buffer that ends right after the tag, or a tag that isn't 0 or 1, is an error
+        assert!(Option::<u64>::deserialize(&mut IndexDeserializer::new(&[1])).is_err());
+        match Option::<u64>::deserialize(&mut IndexDeserializer::new(&[2, 0, 0, 0, 0, 0, 0, 0, 5])) {
+            Err(SerializationError::DeserializeError(error)) => assert_eq!(error, "Invalid option tag 2"),
+            result => panic!("Expected a DeserializeError, got {:?}", result)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn format_fixtures() -> Result<()> {
+        // Written by the last release of each format version, and kept so every version stays readable
+        let fixtures: [&[u8]; 9] = [
+            include_bytes!("../tests/fixtures/v0.rcslib"),
+            include_bytes!("../tests/fixtures/v1.rcslib"),
+            include_bytes!("../tests/fixtures/v2.rcslib"),
+            include_bytes!("../tests/fixtures/v3.rcslib"),
+            include_bytes!("../tests/fixtures/v4.rcslib"),
+            include_bytes!("../tests/fixtures/v5.rcslib"),
+            include_bytes!("../tests/fixtures/v6.rcslib"),
+            include_bytes!("../tests/fixtures/v7.rcslib"),
+            include_bytes!("../tests/fixtures/v8.rcslib")
+        ];
+        assert_eq!(fixtures.len(), FORMAT_VERSION as usize + 1);
+
+        for (version, fixture) in fixtures.iter().enumerate() {
+            let path = format!("test/fixture_v{}.rcslib", version);
+            std::fs::write(&path, fixture)?;
+
+            // Version 0 had no per-entry codecs, so its fixture only has the first two entries
+            let expected: &[(&str, &[u8])] = match version {
+                0 => &[("test/a.txt", b"Test file A"), ("test/b.txt", b"Test file B")],
+                _ => &[("test/a.txt", b"Test file A"), ("test/b.txt", b"Test file B"), ("test/c.txt", b"Test file C")]
+            };
+
+            let mut lib = ResourceLibraryReader::new(&path)?;
+            assert_eq!(lib.version() as usize, version);
+            assert_eq!(lib.len(), expected.len());
+            for (file, contents) in expected {
+                assert_eq!(&*lib.read_file(file)?, *contents, "{} in version {}", file, version);
+            }
+            let data_size = lib.total_compressed_size() as usize;
+            drop(lib);
+
+            // Migrating brings the header and index up to date and leaves the data alone
+            assert_eq!(migrate(&path)?, version as u16);
+            let migrated = std::fs::read(&path)?;
+            match version == FORMAT_VERSION as usize {
+                true => assert_eq!(&migrated[..], *fixture),
+                false => assert_eq!(&migrated[migrated.len() - data_size..], &fixture[fixture.len() - data_size..], "data of version {}", version)
+            }
+
+            let mut lib = ResourceLibraryReader::new(&path)?;
+            assert_eq!(lib.version(), FORMAT_VERSION);
+            for (file, contents) in expected {
+                assert_eq!(&*lib.read_file(file)?, *contents, "{} migrated from version {}", file, version);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn dump_index() -> Result<()> {
+        let lib = ResourceLibraryReader::new("tests/fixtures/v8.rcslib")?;
+        let mut dump = Vec::new();
+        lib.dump_index(&mut dump)?;
+
+        let expected = "\
+version       8
+flags         0x00a0
+index_size    225
+data_size     33
+data_pointer  267
+entries       3
+      offset   compressed     original codec  flags volume    crc32  path
+           0           11           11     0    0x0      0 5b9b6437  test/a.txt
+          11           11           11     0    0x0      0 c292358d  test/b.txt
+          22           11           11     0    0x0      0 b595051b  test/c.txt
+";
+        assert_eq!(String::from_utf8(dump).unwrap(), expected);
+
+        // The standalone version prints the same entry lines from the raw index
+        let bytes = include_bytes!("../tests/fixtures/v8.rcslib");
+        let mut dump = Vec::new();
+        resource_library::dump_index_bytes(&bytes[HEADER_SIZE as usize..267], FORMAT_VERSION, true, &mut dump)?;
+        assert_eq!(String::from_utf8(dump).unwrap(), expected.lines().skip(6).map(|line| format!("{}\n", line)).collect::<String>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_validation() -> Result<()> {
+        let accepted = ["a", "a.txt", "test/a.txt", "a/b/c/d", ".hidden", "a/..b", "a/b..", "...", "a b/c"];
+        let rejected = [
+            ("", PathError::EmptyPath),
+            ("/absolute", PathError::AbsolutePath("/absolute".to_owned())),
+            ("/", PathError::AbsolutePath("/".to_owned())),
+            ("a//b", PathError::EmptyComponent("a//b".to_owned())),
+            ("a/", PathError::TrailingSlash("a/".to_owned())),
+            ("a/b/", PathError::TrailingSlash("a/b/".to_owned())),
+            (".", PathError::DisallowedComponent(".".to_owned(), ".".to_owned())),
+            ("..", PathError::DisallowedComponent("..".to_owned(), "..".to_owned())),
+            ("./a", PathError::DisallowedComponent(".".to_owned(), "./a".to_owned())),
+            ("a/./b", PathError::DisallowedComponent(".".to_owned(), "a/./b".to_owned())),
+            ("../../etc/passwd", PathError::DisallowedComponent("..".to_owned(), "../../etc/passwd".to_owned())),
+            ("a/..", PathError::DisallowedComponent("..".to_owned(), "a/..".to_owned())),
+            ("a\\b", PathError::DisallowedCharacter('\\', "a\\b".to_owned())),
+            ("C:/a", PathError::DisallowedCharacter(':', "C:/a".to_owned()))
+        ];
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        for path in accepted {
+            lib1.write_str(path, path)?;
+        }
+        for &(path, ref expected) in &rejected {
+            match lib1.write_str(path, "") {
+                Err(ResourceLibraryError::PathError(err)) => assert_eq!(err.to_string(), expected.to_string()),
+                other => panic!("{:?} gave {:?}", path, other.map(|_| ()))
+            }
+        }
+        lib1.write_to_path("test/path_validation.rcslib", CompressionLevel::None)?;
+
+        // Lookups follow the same rules, so a bad path is reported as such rather than as missing
+        let mut lib2 = ResourceLibraryReader::new("test/path_validation.rcslib")?;
+        for path in accepted {
+            assert_eq!(&*lib2.read_file(path)?, path.as_bytes());
+        }
+        for &(path, ref expected) in &rejected {
+            match lib2.read_file(path) {
+                Err(ResourceLibraryError::PathError(err)) => assert_eq!(err.to_string(), expected.to_string()),
+                other => panic!("{:?} gave {:?}", path, other.map(|_| ()))
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn backslash_paths() -> Result<()> {
+        // Backslashes are still an error unless normalization is turned on
+        let mut lib1 = ResourceLibraryWriter::new();
+        assert!(matches!(lib1.write_str("ui\\icon.png", ""), Err(ResourceLibraryError::PathError(PathError::DisallowedCharacter('\\', _)))));
+
+        lib1.set_path_normalization(Normalize::Backslashes);
+        lib1.write_str("ui\\icon.png", "icon")?;
+        lib1.write_str("ui\\fonts/main.ttf", "font")?;
+        lib1.write_str("test/a.txt", "Test file A")?;
+        lib1.add_alias("ui\\logo.png".to_owned(), "ui\\icon.png")?;
+
+        // Normalized paths are validated like any other, so UNC prefixes and doubled separators are still rejected
+        assert!(matches!(lib1.write_str("\\\\server\\share\\a.txt", ""), Err(ResourceLibraryError::PathError(PathError::AbsolutePath(path))) if path == "//server/share/a.txt"));
+        assert!(matches!(lib1.write_str("\\a.txt", ""), Err(ResourceLibraryError::PathError(PathError::AbsolutePath(_)))));
+        assert!(matches!(lib1.write_str("ui\\/a.txt", ""), Err(ResourceLibraryError::PathError(PathError::EmptyComponent(_)))));
+        assert!(matches!(lib1.write_str("ui\\..\\a.txt", ""), Err(ResourceLibraryError::PathError(PathError::DisallowedComponent(..)))));
+
+        // Lookups go through the same normalization as the paths that were added
+        assert!(lib1.contains_path("ui\\icon.png"));
+        assert_eq!(&*lib1.read_data("ui\\fonts\\main.ttf")?, b"font");
+        lib1.set_compression("ui\\icon.png", Codec::Stored)?;
+        assert_eq!(lib1.get_compression("ui\\icon.png"), Some(Codec::Stored));
+        lib1.write_str("ui\\old.txt", "old")?;
+        lib1.rename_file("ui\\old.txt", "ui\\new.txt")?;
+        assert_eq!(&*lib1.take_data("ui\\new.txt")?, b"old");
+        lib1.write_to_path("test/backslash_paths.rcslib", CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/backslash_paths.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), ["test/a.txt", "ui/fonts/main.ttf", "ui/icon.png", "ui/logo.png"]);
+        assert!(matches!(lib2.read_file("ui\\icon.png"), Err(ResourceLibraryError::PathError(PathError::DisallowedCharacter(..)))));
+
+        lib2.set_path_normalization(Normalize::Backslashes);
+        assert_eq!(&*lib2.read_file("ui\\icon.png")?, b"icon");
+        assert_eq!(&*lib2.read_file("ui/fonts\\main.ttf")?, b"font");
+        assert_eq!(&*lib2.read_file("ui\\logo.png")?, b"icon");
+        assert_eq!(&*lib2.read_range("test\\a.txt", 5..9)?, b"file");
+        assert_eq!(lib2.uncompressed_len("ui\\icon.png"), Some(4));
+        assert_eq!(lib2.compressed_len("ui\\fonts\\main.ttf"), Some(4));
+        assert_eq!(lib2.alias_target("ui\\logo.png"), Some("ui/icon.png"));
+        assert!(!lib2.is_encrypted("ui\\icon.png"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn control_characters() -> Result<()> {
+        for path in ["test/bell\u{0007}.txt", "test/a\nb.txt", "test/\0.txt", "test/\t.txt", "test/del\u{7F}.txt"] {
+            let err = is_valid_resource_path(path).expect_err(path);
+            assert!(matches!(err, ResourceLibraryError::PathError(PathError::ControlCharacter(..))), "{:?}", err);
+
+            // The error never prints the character itself
+            assert!(!err.to_string().chars().any(|c| c.is_control()), "{}", err);
+        }
+        assert_eq!(is_valid_resource_path("test/bell\u{0007}.txt").unwrap_err().to_string(), "Control character \\u{7} not allowed in path: \"test/bell\\u{7}.txt\"");
+
+        assert!(matches!(is_valid_resource_path(""), Err(ResourceLibraryError::PathError(PathError::EmptyPath))));
+        for path in [" a.txt", "a.txt ", "test/a.txt\u{A0}"] {
+            assert!(matches!(is_valid_resource_path(path), Err(ResourceLibraryError::PathError(PathError::SurroundingWhitespace(_)))), "{:?}", path);
+        }
+        is_valid_resource_path("test/a b.txt")?;
+        is_valid_resource_path("test/a.txt")?;
+
+        // The writer goes through the same checks
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("test/a\nb.txt", "").expect_err("Path should be invalid!");
+        lib1.write_str("", "").expect_err("Path should be invalid!");
+        assert!(lib1.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resource_paths() -> Result<()> {
+        for path in ["", "/a", "a//b", "../a", "a/", "a\nb", "a?b"] {
+            assert!(ResourcePath::new(path).is_err(), "{:?}", path);
+            assert!(ResourcePath::try_from(path).is_err(), "{:?}", path);
+            assert!(ResourcePath::try_from(path.to_owned()).is_err(), "{:?}", path);
+        }
+
+        let path = ResourcePath::try_from("test/a.txt")?;
+        assert_eq!(path, "test/a.txt");
+        assert_eq!(path.len(), 10);
+        assert_eq!(String::from(path), "test/a.txt");
+
+        // Paths sort the same way as their bytes, so archives come out in the same order as before
+        let paths = ["b", "B", "a/b", "a-b", "a.b", "a0", "\u{e9}", "z", "Z/a"];
+        let mut sorted_strings = paths.map(str::to_owned).to_vec();
+        sorted_strings.sort();
+        let mut sorted_paths = paths.iter().map(|path| ResourcePath::new(*path)).collect::<Result<Vec<_>>>()?;
+        sorted_paths.sort();
+        assert!(sorted_paths.iter().map(|path| &path[..]).eq(sorted_strings.iter().map(|path| &path[..])));
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        for path in paths {
+            lib1.write_str(path, path)?;
+        }
+        lib1.write_str(ResourcePath::new("test/a.txt")?, "Test file A")?;
+        lib1.write_to_path("test/resource_paths.rcslib", CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/resource_paths.rcslib")?;
+        sorted_strings.push("test/a.txt".to_owned());
+        sorted_strings.sort();
+        assert_eq!(lib2.get_all_files().to_vec(), sorted_strings);
+        assert_eq!(&*lib2.read_file(ResourcePath::new("test/a.txt")?)?, b"Test file A");
+
+        // An index naming a path that doesn't validate can't be read back
+        let bytes = entries_to_bytes(&[IndexEntry::new(ResourcePath::new("test/a.txt")?)], false)?.into_vec();
+        let start = bytes.windows(10).position(|window| window == b"test/a.txt").unwrap();
+        let mut bad = bytes.clone();
+        bad[start..start + 10].copy_from_slice(b"../a/b.txt");
+        assert!(entries_from_bytes(&bytes, FORMAT_VERSION, false).is_ok());
+        assert!(matches!(entries_from_bytes(&bad, FORMAT_VERSION, false), Err(SerializationError::DeserializeError(_))));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn nfc_paths() -> Result<()> {
+        let decomposed = "assets/cafe\u{301}.png";
+        let composed = "assets/caf\u{e9}.png";
+
+        // Without normalization the two spellings are different paths
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str(decomposed, "decomposed")?;
+        lib1.write_str(composed, "composed")?;
+        assert_eq!(lib1.len(), 2);
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.set_nfc_paths(true);
+        lib1.write_str(decomposed, "image")?;
+        lib1.write_str("test/a.txt", "Test file A")?;
+
+        // Adding the same name again follows the duplicate policy, but a different name for the same path is an error
+        lib1.write_str(decomposed, "image")?;
+        match lib1.write_str(composed, "other") {
+            Err(ResourceLibraryError::PathError(PathError::NormalizationCollision(first, second, path))) => {
+                assert_eq!((&first[..], &second[..], &path[..]), (decomposed, composed, composed));
+            }
+            result => panic!("Expected a NormalizationCollision, got {:?}", result.map(|_| ()))
+        }
+        lib1.write_to_path("test/nfc_paths.rcslib", CompressionLevel::None)?;
+
+        let mut lib2 = ResourceLibraryReader::new("test/nfc_paths.rcslib")?;
+        assert_eq!(&*lib2.get_all_files(), [composed, "test/a.txt"]);
+        assert_eq!(&*lib2.read_file(composed)?, b"image");
+        assert!(lib2.read_file(decomposed).is_err());
+
+        lib2.set_nfc_paths(true);
+        assert_eq!(&*lib2.read_file(decomposed)?, b"image");
+        assert_eq!(&*lib2.read_file(composed)?, b"image");
+
+        Ok(())
+    }
+
+    #[test]
+    fn case_collisions() -> Result<()> {
+        let collisions = [("Textures/Foo.png", "textures/foo.png"), ("docs/Ärger.txt", "docs/äRGER.txt"), ("Maße.txt", "MASSE.txt"), ("\u{3A3}.txt", "\u{3C2}.txt")];
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.forbid_case_collisions(true);
+        for (first, second) in collisions {
+            lib1.write_str(first, "first")?;
+            // The same path again is up to the duplicate policy
+            lib1.write_str(first, "first")?;
+            match lib1.write_str(second, "second") {
+                Err(ResourceLibraryError::PathError(PathError::CaseCollision(a, b))) => assert_eq!((&a[..], &b[..]), (first, second)),
+                result => panic!("Expected a CaseCollision for {}, got {:?}", second, result.map(|_| ()))
+            }
+        }
+        assert!(lib1.add_alias("textures/FOO.png".to_owned(), "Textures/Foo.png").is_err());
+        assert!(lib1.rename_file("docs/Ärger.txt", "textures/FOO.png").is_err());
+
+        // Changing the case of a path on its own is fine, and removing a path frees up its other spellings
+        lib1.rename_file("Maße.txt", "MAẞE.txt")?;
+        lib1.remove_file("Textures/Foo.png")?;
+        lib1.write_str("textures/foo.png", "second")?;
+        lib1.write_to_path("test/case_collisions.rcslib", CompressionLevel::None)?;
+
+        // Reporting lists every collision but still writes the archive
+        let mut lib2 = ResourceLibraryWriter::new();
+        lib2.set_case_collisions(CaseCollisions::Report);
+        for (first, second) in collisions {
+            lib2.write_str(first, "first")?;
+            lib2.write_str(second, "second")?;
+        }
+        lib2.write_str("test/a.txt", "Test file A")?;
+        let report = lib2.write_to_path("test/case_collisions.rcslib", CompressionLevel::None)?;
+        let mut expected: Vec<_> = collisions.iter().map(|&(first, second)| if first < second { (first, second) } else { (second, first) }).collect();
+        expected.sort();
+        let mut reported: Vec<_> = report.case_collisions.iter().map(|(a, b)| (&a[..], &b[..])).collect();
+        reported.sort();
+        assert_eq!(reported, expected);
+        assert_eq!(ResourceLibraryReader::new("test/case_collisions.rcslib")?.len(), 9);
+
+        // Turning it on for a writer that already has collisions fails the write
+        lib2.forbid_case_collisions(true);
+        assert!(matches!(lib2.write_to_path("test/case_collisions.rcslib", CompressionLevel::None), Err(ResourceLibraryError::PathError(PathError::CaseCollision(..)))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_lengths() -> Result<()> {
+        let at_limit = "x".repeat(254) + "/";
+        let at_limit = at_limit.repeat(16) + &"y".repeat(16);
+        assert_eq!(at_limit.len(), 4096);
+        let component_at_limit = "test/".to_owned() + &"z".repeat(255);
+
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str(&at_limit[..], "at limit")?;
+        lib1.write_str(&component_at_limit[..], "component at limit")?;
+        match lib1.write_str(at_limit.clone() + "y", "") {
+            Err(err @ ResourceLibraryError::PathError(PathError::PathTooLong(4097, 4096))) => assert!(err.to_string().contains("4097") && err.to_string().contains("4096")),
+            result => panic!("Expected PathTooLong, got {:?}", result.map(|_| ()))
+        }
+        assert!(matches!(lib1.write_str(component_at_limit.clone() + "z", ""), Err(ResourceLibraryError::PathError(PathError::ComponentTooLong(256, 255, _)))));
+        assert!(matches!(lib1.rename_file(&component_at_limit, component_at_limit.clone() + "z"), Err(ResourceLibraryError::PathError(PathError::ComponentTooLong(256, 255, _)))));
+        lib1.write_to_path("test/path_lengths.rcslib", CompressionLevel::None)?;
+        assert_eq!(&*ResourceLibraryReader::new("test/path_lengths.rcslib")?.read_file(&at_limit)?, b"at limit");
+
+        // Both limits can be changed
+        lib1.set_path_policy(PathPolicy { max_len: 10, max_component_len: 5 });
+        lib1.write_str("test/a.txt", "")?;
+        assert!(matches!(lib1.write_str("test/ab.txt", ""), Err(ResourceLibraryError::PathError(PathError::PathTooLong(11, 10)))));
+        assert!(matches!(lib1.write_str("a/test.txt", ""), Err(ResourceLibraryError::PathError(PathError::ComponentTooLong(8, 5, _)))));
+
+        // An index with a path over the hard cap is rejected before it's rebuilt, however it was written
+        let huge = IndexEntry::new(ResourcePath::new("x".repeat(70000))?);
+        let index = entries_to_bytes(&[huge], false)?;
+        match entries_from_bytes(&index, FORMAT_VERSION, false) {
+            Err(SerializationError::DeserializeError(error)) => assert!(error.contains("70000"), "{}", error),
+            result => panic!("Expected a DeserializeError, got {:?}", result)
+        }
+
+        let mut file = File::create("test/path_lengths_crafted.rcslib")?;
+        resource_library::write_header(&mut file, &index, 0, 0, 0, &resource_library::HeaderExtras::default())?;
+        drop(file);
+        assert!(matches!(ResourceLibraryReader::new("test/path_lengths_crafted.rcslib"), Err(ResourceLibraryError::SerializationError(SerializationError::DeserializeError(_)))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn case_insensitive_reader() -> Result<()> {
+        let mut lib1 = ResourceLibraryWriter::new();
+        lib1.write_str("Textures/Foo.png", "upper")?;
+        lib1.write_str("textures/foo.png", "lower")?;
+        lib1.write_str("Scripts/Main.lua", "main")?;
+        lib1.write_str("docs/Ärger.txt", "ärger")?;
+        lib1.write_to_path("test/case_insensitive_reader.rcslib", CompressionLevel::None)?;
+
+        // Lookups are exact unless asked otherwise
+        let lib2 = ResourceLibraryReader::new("test/case_insensitive_reader.rcslib")?;
+        assert!(lib2.contains("Scripts/Main.lua"));
+        assert!(!lib2.contains("scripts/main.lua"));
+
+        let mut lib2 = ResourceLibraryReader::open_with_options("test/case_insensitive_reader.rcslib", ReaderOptions::new().case_insensitive(true))?;
+        assert!(lib2.contains("SCRIPTS/MAIN.LUA"));
+        assert!(!lib2.contains("scripts/other.lua"));
+        assert_eq!(&*lib2.read_file("scripts/main.lua")?, b"main");
+        assert_eq!(&*lib2.read_range("SCRIPTS/main.LUA", 1..3)?, b"ai");
+        assert_eq!(&*lib2.read_file("DOCS/ÄRGER.TXT")?, "ärger".as_bytes());
+        assert_eq!(lib2.uncompressed_len("scripts/MAIN.lua"), Some(4));
+
+        // Exact matches win, and anything else goes to the first of the entries in index order
+        assert_eq!(&*lib2.read_file("Textures/Foo.png")?, b"upper");
+        assert_eq!(&*lib2.read_file("textures/foo.png")?, b"lower");
+        assert_eq!(&*lib2.read_file("TEXTURES/FOO.PNG")?, b"upper");
+        assert!(matches!(lib2.read_file("textures/bar.png"), Err(ResourceLibraryError::PathError(PathError::InvalidPath(_)))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_components() -> Result<()> {
+        // Path, parent, file name, extension
+        let cases = [
+            ("a.txt", None, "a.txt", Some("txt")),
+            ("README", None, "README", None),
+            ("test/a.txt", Some("test"), "a.txt", Some("txt")),
+            ("a/b/c/d.png", Some("a/b/c"), "d.png", Some("png")),
+            ("backups/archive.tar.gz", Some("backups"), "archive.tar.gz", Some("gz")),
+            (".gitignore", None, ".gitignore", None),
+            ("config/.env.local", Some("config"), ".env.local", Some("local")),
+            ("dir.d/file", Some("dir.d"), "file", None),
+            ("file.", None, "file.", Some("")),
+            ("..hidden", None, "..hidden", Some("hidden"))
+        ];
+
+        for (path, parent, file_name, extension) in cases {
+            let path = ResourcePath::new(path)?;
+            assert_eq!(path.parent(), parent, "{}", path);
+            assert_eq!(path.file_name(), file_name, "{}", path);
+            assert_eq!(path.extension(), extension, "{}", path);
+            assert_eq!(path.components().next_back(), Some(file_name), "{}", path);
+            assert_eq!(path.components().collect::<Vec<_>>().join("/"), &path[..]);
+        }
+
+        let dir = ResourcePath::new("assets/textures")?;
+        assert_eq!(dir.components().collect::<Vec<_>>(), ["assets", "textures"]);
+        assert_eq!(dir.join("foo.png")?, "assets/textures/foo.png");
+        assert_eq!(dir.join("ui/icon.png")?, "assets/textures/ui/icon.png");
+        for component in ["", "..", "../secret.txt", "a//b", "/abs", "a/"] {
+            assert!(dir.join(component).is_err(), "{:?}", component);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_dir() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for path in ["readme.txt", "textures/a.png", "textures/ui.png", "textures/ui/button.png", "textures/ui/icons/close.png",
+                     "textures/ui/icons/open.png", "textures/z.png", "texturesque.txt", "sounds/click.ogg"] {
+            writer.write_str(path, path)?;
+        }
+
+        let file = |name: &str| DirEntry::File(name.to_string());
+        let dir = |name: &str| DirEntry::Dir(name.to_string());
+
+        assert_eq!(writer.list_dir(""), [file("readme.txt"), dir("sounds"), dir("textures"), file("texturesque.txt")]);
+        assert_eq!(writer.list_dir("textures"), [file("a.png"), file("ui.png"), dir("ui"), file("z.png")]);
+        assert_eq!(writer.list_dir("textures/"), writer.list_dir("textures"));
+        assert_eq!(writer.list_dir("textures/ui"), [file("button.png"), dir("icons")]);
+        assert_eq!(writer.list_dir("textures/ui/icons/"), [file("close.png"), file("open.png")]);
+
+        assert!(writer.list_dir("models").is_empty());
+        assert!(writer.list_dir("textures/ui/icons/close.png").is_empty());
+        assert!(writer.list_dir("textur").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for path in ["shaders/basic.glsl", "shaders/post/blur.glsl", "shaders/post/fx/bloom.glsl", "shaders/post/blur.hlsl",
+                     "shaders/README.md", "textures/a1.png", "textures/b2.png", "textures/ab.png", "Textures/C3.PNG", "main.glsl"] {
+            writer.write_str(path, path)?;
+        }
+
+        let cases: &[(&str, &[&str])] = &[
+            ("shaders/**/*.glsl", &["shaders/basic.glsl", "shaders/post/blur.glsl", "shaders/post/fx/bloom.glsl"]),
+            ("**/*.glsl", &["main.glsl", "shaders/basic.glsl", "shaders/post/blur.glsl", "shaders/post/fx/bloom.glsl"]),
+            ("shaders/*.glsl", &["shaders/basic.glsl"]),
+            ("shaders/**", &["shaders/README.md", "shaders/basic.glsl", "shaders/post/blur.glsl", "shaders/post/blur.hlsl", "shaders/post/fx/bloom.glsl"]),
+            ("shaders/post/blur.?lsl", &["shaders/post/blur.glsl", "shaders/post/blur.hlsl"]),
+            ("textures/[a-b][0-9].png", &["textures/a1.png", "textures/b2.png"]),
+            ("textures/[!a]*.png", &["textures/b2.png"]),
+            ("textures/*", &["textures/a1.png", "textures/ab.png", "textures/b2.png"]),
+            ("*", &["main.glsl"]),
+            ("**/*.wgsl", &[]),
+            ("shaders", &[]),
+            ("models/**", &[])
+        ];
+        for &(pattern, expected) in cases {
+            assert_eq!(writer.glob(pattern)?, expected, "{}", pattern);
+        }
+
+        let insensitive = Glob::new("textures/[a-c]?.png")?.case_insensitive(true);
+        assert_eq!(writer.glob_with(&insensitive), ["Textures/C3.PNG", "textures/a1.png", "textures/ab.png", "textures/b2.png"]);
+        assert_eq!(writer.glob("textures/[a-c]?.png")?, ["textures/a1.png", "textures/ab.png", "textures/b2.png"]);
+
+        assert!(Glob::new(r"\*.md")?.is_match("*.md"));
+        assert!(!Glob::new(r"\*.md")?.is_match("a.md"));
+
+        for pattern in ["", "a//b", "/a", "a/", "a**/b", "[abc", "[z-a]", "a\\"] {
+            assert!(matches!(writer.glob(pattern), Err(ResourceLibraryError::PathError(PathError::InvalidPattern(..)))), "{:?}", pattern);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_operations() -> Result<()> {
+        let mut lib = ResourceLibraryWriter::new();
+        for path in ["levels/act1", "levels/act1-old/map.bin", "levels/act1.txt", "levels/act1/map.bin", "levels/act1/sub/script.lua",
+                     "levels/act10/map.bin", "levels/act2/map.bin", "music/theme.ogg"] {
+            lib.write_str(path, path)?;
+        }
+
+        assert_eq!(lib.paths_with_prefix("levels/act1").collect::<Vec<_>>(), ["levels/act1", "levels/act1/map.bin", "levels/act1/sub/script.lua"]);
+        assert_eq!(lib.paths_with_prefix("levels/act1/").collect::<Vec<_>>(), ["levels/act1/map.bin", "levels/act1/sub/script.lua"]);
+        assert_eq!(lib.paths_with_prefix("levels/act").count(), 0);
+        assert_eq!(lib.paths_with_prefix("levels").count(), 7);
+        assert_eq!(lib.paths_with_prefix("").count(), 8);
+
+        // The range never reaches the neighbouring keys that share the prefix as a string
+        let (lower, upper) = resource_library::prefix_bounds("levels/act1");
+        assert_eq!(lower, Bound::Included("levels/act1/".to_string()));
+        assert_eq!(upper, Bound::Excluded("levels/act10".to_string()));
+        assert_eq!(resource_library::prefix_bounds("levels/act1/"), (lower.clone(), upper.clone()));
+        for unrelated in ["levels/act1-old/map.bin", "levels/act1.txt", "levels/act10/map.bin", "levels/act2/map.bin"] {
+            assert!(!(lower.as_ref(), upper.as_ref()).contains(&unrelated.to_string()), "{}", unrelated);
+        }
+
+        assert_eq!(lib.set_compression_for_prefix("levels/act1/", Codec::Stored)?, 2);
+        assert_eq!(lib.get_compression("levels/act1/sub/script.lua"), Some(Codec::Stored));
+        assert_eq!(lib.get_compression("levels/act1"), None);
+        assert_eq!(lib.get_compression("levels/act10/map.bin"), None);
+
+        assert_eq!(lib.rename_prefix("levels/act1", "levels/act3")?, 3);
+        assert_eq!(lib.paths_with_prefix("levels/act3").collect::<Vec<_>>(), ["levels/act3", "levels/act3/map.bin", "levels/act3/sub/script.lua"]);
+
+        assert_eq!(lib.remove_prefix("levels/act3"), 3);
+        assert_eq!(&*lib.get_all_files(), ["levels/act1-old/map.bin", "levels/act1.txt", "levels/act10/map.bin", "levels/act2/map.bin", "music/theme.ogg"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn paths_with_extension() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for path in ["shaders/basic.shader", "shaders/BLUR.SHADER", "textures/a.png", "textures/b.PNG", "textures/c.png.bak",
+                     "backups/archive.tar.gz", "backups/notes.gz", "backups/.gz", "bin/run", "config/.env", "dir.png/readme"] {
+            writer.write_str(path, path)?;
+        }
+        writer.write_to_file(File::create("test/extensions.rcslib")?, CompressionLevel::Fast)?;
+
+        let lib = ResourceLibraryReader::new("test/extensions.rcslib")?;
+        assert_eq!(lib.paths_with_extension("shader"), ["shaders/BLUR.SHADER", "shaders/basic.shader"]);
+        assert_eq!(lib.paths_with_extension(".png"), ["textures/a.png", "textures/b.PNG"]);
+        assert_eq!(lib.paths_with_extension("PNG"), lib.paths_with_extension("png"));
+        assert_eq!(lib.paths_with_extension("gz"), ["backups/archive.tar.gz", "backups/notes.gz"]);
+        assert_eq!(lib.paths_with_extension("tar.gz"), ["backups/archive.tar.gz"]);
+        assert_eq!(lib.paths_with_extension("tar"), Vec::<&str>::new());
+        assert_eq!(lib.paths_with_extension("env"), Vec::<&str>::new());
+        assert_eq!(lib.paths_with_extension(""), Vec::<&str>::new());
+        assert_eq!(lib.paths_with_extensions(&["png", "bak", "shader"]),
+                   ["shaders/BLUR.SHADER", "shaders/basic.shader", "textures/a.png", "textures/b.PNG", "textures/c.png.bak"]);
+        assert!(lib.paths_with_extensions(&[]).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_glob() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for i in 0..100 {
+            writer.write_str(format!("scripts/mod{}/main.lua", i), "")?;
+            writer.write_str(format!("textures/{}.png", i), "")?;
+        }
+        for i in 0..50 {
+            writer.write_str(format!("scripts/lib/util{}.lua", i), "")?;
+            writer.write_str(format!("scripts/lib/util{}.txt", i), "")?;
+        }
+        writer.write_str("Scripts/legacy.lua", "")?;
+        writer.write_to_file(File::create("test/glob.rcslib")?, CompressionLevel::Fast)?;
+
+        let lib = ResourceLibraryReader::new("test/glob.rcslib")?;
+        assert_eq!(lib.len(), 301);
+
+        for pattern in ["scripts/**/*.lua", "scripts/lib/util?.lua", "scripts/mod1[0-9]/*", "**/*.lua", "textures/[!0-8]*.png", "*/legacy.lua", "models/**"] {
+            assert_eq!(lib.glob(pattern)?, writer.glob(pattern)?, "{}", pattern);
+        }
+        assert_eq!(lib.glob("scripts/**/*.lua")?.len(), 150);
+        assert_eq!(lib.glob("scripts/lib/util?.lua")?.len(), 10);
+        assert!(lib.glob("models/**")?.is_empty());
+        lib.glob("scripts/[").expect_err("Pattern should be invalid!");
+
+        // Only the entries under the literal part of the pattern are visited
+        assert_eq!(Glob::new("scripts/lib/util?.lua")?.literal_prefix(), "scripts/lib/util");
+        assert_eq!(lib.glob_range(&Glob::new("scripts/lib/util?.lua")?).len(), 100);
+        assert_eq!(lib.glob_range(&Glob::new("scripts/**/*.lua")?).len(), 200);
+        assert_eq!(lib.glob_range(&Glob::new("textures/*.png")?).len(), 100);
+        assert!(lib.glob_range(&Glob::new("models/**")?).is_empty());
+        assert_eq!(lib.glob_range(&Glob::new("**/*.lua")?).len(), 301);
+
+        let insensitive = Glob::new("scripts/*.lua")?.case_insensitive(true);
+        assert_eq!(lib.glob_range(&insensitive).len(), 301);
+        assert_eq!(lib.glob_with(&insensitive), ["Scripts/legacy.lua"]);
+        assert_eq!(writer.glob_with(&insensitive), ["Scripts/legacy.lua"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_file_to() -> Result<()> {
+        // Takes the first few bytes, then fails like a closed socket would
+        struct FailingSink(usize);
+
+        impl Write for FailingSink {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                match self.0 {
+                    0 => Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+                    left => {
+                        let n = buf.len().min(left);
+                        self.0 -= n;
+                        Ok(n)
+                    }
+                }
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let png = std::fs::read("test/testfile.png")?;
+        for (name, block_size) in [("plain", None), ("chunked", Some(1000))] {
+            let mut writer = ResourceLibraryWriter::new();
+            writer.set_block_size(block_size);
+            writer.add_file("test/testfile.png", "testfile.png")?;
+            writer.write_str("empty.txt", "")?;
+            writer.write_stream_with_compression("stored.png", ByteStream::from(png.clone()), Codec::Stored)?;
+            let archive = format!("test/read_file_to_{}.rcslib", name);
+            writer.write_to_file(File::create(&archive)?, CompressionLevel::Fast)?;
+
+            let mut lib = ResourceLibraryReader::new(&archive)?;
+            for path in ["testfile.png", "stored.png", "empty.txt"] {
+                let mut sink = Vec::new();
+                assert_eq!(lib.read_file_to(path, &mut sink)?, sink.len() as u64);
+                assert_eq!(sink, &*lib.read_file(path)?, "{}", path);
+            }
+            assert_eq!(lib.read_file_to("testfile.png", &mut Vec::new())?, png.len() as u64);
+
+            match lib.read_file_to("testfile.png", &mut FailingSink(1500)) {
+                Err(ResourceLibraryError::SinkError(err)) => assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe),
+                result => panic!("Expected SinkError, got {:?}", result)
+            }
+            assert!(matches!(lib.read_file_to("missing.png", &mut Vec::new()), Err(ResourceLibraryError::PathError(PathError::InvalidPath(_)))));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_into() -> Result<()> {
+        let png = std::fs::read("test/testfile.png")?;
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "large.png")?;
+        writer.write_str("small.txt", "small")?;
+        writer.write_data("medium.bin", vec![7u8; 1000])?;
+        writer.write_str("empty.txt", "")?;
+        writer.write_to_file(File::create("test/read_into.rcslib")?, CompressionLevel::Fast)?;
+
+        let mut lib = ResourceLibraryReader::new("test/read_into.rcslib")?;
+        let mut buf = Vec::new();
+        assert_eq!(lib.read_into("large.png", &mut buf)?, png.len());
+        assert_eq!(buf, png);
+        // Sized exactly for the first entry, then never reallocated for the smaller ones
+        assert_eq!(buf.capacity(), png.len());
+        let (capacity, ptr) = (buf.capacity(), buf.as_ptr());
+
+        for (path, expected) in [("small.txt", &b"small"[..]), ("medium.bin", &[7u8; 1000][..]), ("empty.txt", b""), ("large.png", &png[..]), ("small.txt", b"small")] {
+            assert_eq!(lib.read_into(path, &mut buf)?, expected.len());
+            assert_eq!(buf, expected, "{}", path);
+            assert_eq!((buf.capacity(), buf.as_ptr()), (capacity, ptr), "{}", path);
+        }
+
+        let mut arena = [0u8; 1000];
+        assert_eq!(lib.read_into_slice("medium.bin", &mut arena)?, 1000);
+        assert_eq!(lib.read_into_slice("small.txt", &mut arena[..5])?, 5);
+        assert_eq!(&arena[..5], b"small");
+        assert!(matches!(lib.read_into_slice("large.png", &mut arena), Err(ResourceLibraryError::BufferTooSmall(path, 1000)) if path == "large.png"));
+        assert!(matches!(lib.read_into_slice("medium.bin", &mut arena[..999]), Err(ResourceLibraryError::BufferTooSmall(..))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_entry() -> Result<()> {
+        let png = std::fs::read("test/testfile.png")?;
+        for (name, block_size) in [("plain", None), ("chunked", Some(1000))] {
+            let mut writer = ResourceLibraryWriter::new();
+            writer.set_block_size(block_size);
+            writer.add_file("test/testfile.png", "a.png")?;
+            writer.write_str("b.txt", "first line\nsecond line\n")?;
+            // Stored entries sit right next to each other, so running past the end would read into c2
+            writer.write_stream_with_compression("c1.bin", ByteStream::from(vec![1u8; 3000]), Codec::Stored)?;
+            writer.write_stream_with_compression("c2.bin", ByteStream::from(vec![2u8; 3000]), Codec::Stored)?;
+            let archive = format!("test/open_entry_{}.rcslib", name);
+            writer.write_to_file(File::create(&archive)?, CompressionLevel::Fast)?;
+
+            let mut lib = ResourceLibraryReader::new(&archive)?;
+            for path in ["a.png", "b.txt", "c1.bin", "c2.bin"] {
+                let mut data = Vec::new();
+                lib.open_entry(path)?.read_to_end(&mut data)?;
+                assert_eq!(data, &*lib.read_file(path)?, "{}", path);
+            }
+
+            // Partial reads, then dropping the handle halfway through
+            let mut head = [0u8; 8];
+            lib.open_entry("a.png")?.read_exact(&mut head)?;
+            assert_eq!(head, png[..8]);
+
+            let mut entry = lib.open_entry("c1.bin")?;
+            let mut chunk = [0u8; 1200];
+            entry.read_exact(&mut chunk)?;
+            entry.read_exact(&mut chunk)?;
+            assert_eq!(entry.read(&mut chunk)?, 600);
+            assert_eq!(entry.read(&mut chunk)?, 0);
+            drop(entry);
+
+            let mut lines = lib.open_entry("b.txt")?.lines();
+            assert_eq!(lines.next().transpose()?.as_deref(), Some("first line"));
+            drop(lines);
+
+            // One handle after another, with the reader used in between
+            let mut first = Vec::new();
+            lib.open_entry("c2.bin")?.take(100).read_to_end(&mut first)?;
+            assert_eq!(&*lib.read_file("b.txt")?, b"first line\nsecond line\n");
+            let mut second = Vec::new();
+            lib.open_entry("a.png")?.read_to_end(&mut second)?;
+            assert_eq!(first, [2u8; 100]);
+            assert_eq!(second, png);
+
+            assert!(matches!(lib.open_entry("missing.bin"), Err(ResourceLibraryError::PathError(PathError::InvalidPath(_)))));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_lookups() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for path in ["lang/de/menu.txt", "lang/en/menu.txt", "lang/en/menu.txt.bak", "levels/act1/map.bin"] {
+            writer.write_str(path, path)?;
+        }
+        writer.write_to_file(File::create("test/reader_lookups.rcslib")?, CompressionLevel::Fast)?;
+
+        let mut lib = ResourceLibraryReader::new("test/reader_lookups.rcslib")?;
+        for (n, entry) in lib.entries().enumerate() {
+            assert_eq!(lib.entry_index(entry.path()), Some(n));
+            assert!(lib.contains(entry.path()));
+        }
+
+        // Near misses that share a prefix with an entry, or have one as their prefix
+        for path in ["lang/fr/menu.txt", "lang/en/menu", "lang/en/menu.tx", "lang/en", "lang/en/", "levels/act1/map.bin/x", "levels/act", ""] {
+            assert_eq!(lib.entry_index(path), None, "{}", path);
+            assert!(!lib.contains(path), "{}", path);
+        }
+
+        // Falling back without matching on error strings
+        let menu = match lib.read_file("lang/fr/menu.txt") {
+            Err(err) if err.is_not_found() => lib.read_file("lang/en/menu.txt")?,
+            result => result?
+        };
+        assert_eq!(&*menu, b"lang/en/menu.txt");
+        assert!(!lib.read_file("lang/en/menu?.txt").unwrap_err().is_not_found());
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_sizes() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "a.png")?;
+        writer.write_data("zeros.bin", vec![0u8; 10000])?;
+        writer.write_str("empty.txt", "")?;
+        writer.write_to_file(File::create("test/entry_sizes.rcslib")?, CompressionLevel::Fast)?;
+        std::fs::write("test/entry_sizes_v4.rcslib", include_bytes!("../tests/fixtures/v4.rcslib"))?;
+
+        for (archive, sizes_stored) in [("test/entry_sizes.rcslib", true), ("test/entry_sizes_v4.rcslib", false)] {
+            let mut lib = ResourceLibraryReader::new(archive)?;
+            let paths: Vec<String> = lib.get_all_files().iter().map(|path| path.to_string()).collect();
+
+            let mut compressed = 0;
+            for path in &paths {
+                let sizes = lib.entry_sizes(path).unwrap();
+                let data = lib.read_file(path)?;
+                assert_eq!(sizes.uncompressed, sizes_stored.then_some(data.len() as u64), "{}", path);
+                assert_eq!(sizes.compressed, lib.compressed_len(path).unwrap());
+                compressed += sizes.compressed;
+            }
+
+            let total = lib.total_sizes();
+            assert_eq!(total.compressed, compressed);
+            assert_eq!(total.uncompressed, lib.total_uncompressed_size());
+            assert_eq!(lib.entry_sizes("missing.txt"), None);
+        }
+
+        let lib = ResourceLibraryReader::new("test/entry_sizes.rcslib")?;
+        assert_eq!(lib.total_uncompressed_size(), Some(std::fs::read("test/testfile.png")?.len() as u64 + 10000));
+        assert!(lib.entry_sizes("zeros.bin").unwrap().compressed < 10000);
+        assert_eq!(ResourceLibraryReader::new("test/entry_sizes_v4.rcslib")?.total_uncompressed_size(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_refs() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "a.png")?;
+        writer.write_data("b.bin", vec![0u8; 10000])?;
+        writer.write_stream_with_compression("c.txt", ByteStream::from(b"stored".to_vec()), Codec::Stored)?;
+        writer.set_mtime("c.txt", 1_700_000_000)?;
+        writer.write_to_path("test/entry_refs.rcslib", CompressionLevel::Fast)?;
+
+        let lib = ResourceLibraryReader::new("test/entry_refs.rcslib")?;
+        let bytes = std::fs::read("test/entry_refs.rcslib")?;
+        let data_size = u64::from_be_bytes(bytes[20..28].try_into().unwrap());
+        assert_eq!(lib.entries().map(|entry| entry.compressed_len()).sum::<u64>(), data_size);
+
+        assert_eq!(lib.entries().len(), 3);
+        assert_eq!(lib.entries().map(|entry| entry.path()).collect::<Vec<_>>(), ["a.png", "b.bin", "c.txt"]);
+        let mut offset = 0;
+        for (entry, index_entry) in lib.entries().zip(lib.index_entries()) {
+            assert_eq!(entry.index_entry(), index_entry);
+            assert_eq!(entry.offset(), offset);
+            assert_eq!(entry.crc32(), Some(index_entry.crc32 as u32));
+            assert!(!entry.is_alias() && !entry.is_encrypted());
+            offset += entry.compressed_len();
+        }
+
+        let c = lib.entries().last().unwrap();
+        assert_eq!((c.codec(), c.uncompressed_len(), c.compressed_len(), c.mtime()), (Codec::Stored.id(), Some(6), 6, Some(1_700_000_000)));
+        let b = lib.entries().nth(1).unwrap();
+        assert_eq!((b.uncompressed_len(), b.mtime()), (Some(10000), None));
+        // Files added from disk keep their modification time
+        assert!(lib.entries().next().unwrap().mtime().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_summary() -> Result<()> {
+        std::fs::write("test/summary_v8.rcslib", include_bytes!("../tests/fixtures/v8.rcslib"))?;
+        let lib = ResourceLibraryReader::new("test/summary_v8.rcslib")?;
+        assert_eq!((lib.file_count(), lib.is_empty()), (3, false));
+        assert_eq!(lib.summary(), ArchiveSummary {
+            entries: 3,
+            sizes: EntrySizes { compressed: 33, uncompressed: Some(33) },
+            largest: Some(("test/a.txt".to_string(), EntrySizes { compressed: 11, uncompressed: Some(11) })),
+            index_size: 225
+        });
+
+        let mut writer = ResourceLibraryWriter::new();
+        writer.write_str("small.txt", "small")?;
+        writer.write_stream_with_compression("large.bin", ByteStream::from(vec![0u8; 5000]), Codec::Stored)?;
+        writer.write_stream_with_compression("medium.bin", ByteStream::from(vec![0u8; 1000]), Codec::Stored)?;
+        writer.write_to_path("test/summary.rcslib", CompressionLevel::Fast)?;
+        let summary = ResourceLibraryReader::new("test/summary.rcslib")?.summary();
+        assert_eq!(summary.largest, Some(("large.bin".to_string(), EntrySizes { compressed: 5000, uncompressed: Some(5000) })));
+        assert_eq!(summary.sizes.uncompressed, Some(6005));
+
+        ResourceLibraryWriter::new().write_to_path("test/summary_empty.rcslib", CompressionLevel::Fast)?;
+        let lib = ResourceLibraryReader::new("test/summary_empty.rcslib")?;
+        assert_eq!((lib.file_count(), lib.is_empty()), (0, true));
+        let summary = lib.summary();
+        assert_eq!((summary.entries, summary.sizes, summary.largest), (0, EntrySizes { compressed: 0, uncompressed: Some(0) }, None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn extract_all() -> Result<()> {
+        let png = std::fs::read("test/testfile.png")?;
+        let files: &[(&str, &[u8])] = &[
+            ("readme.txt", b"Read me"),
+            ("empty.txt", b""),
+            ("textures/testfile.png", &png),
+            ("textures/ui/icons/close.png", &png[..100]),
+            ("data/zeros.bin", &[0u8; 20000]),
+            ("text/日本.txt", "日本語".as_bytes())
+        ];
+
+        let mut writer = ResourceLibraryWriter::new();
+        for (path, data) in files {
+            writer.write_data(*path, data.to_vec())?;
+        }
+        writer.set_compression("data/zeros.bin", Codec::Stored)?;
+        writer.set_mtime("readme.txt", 1_700_000_000)?;
+        writer.add_alias("textures/copy.png".to_string(), "textures/testfile.png")?;
+        writer.write_to_path("test/extract_all.rcslib", CompressionLevel::Fast)?;
+
+        let dest = Path::new("test/extract_all");
+        let _ = std::fs::remove_dir_all(dest);
+        let mut lib = ResourceLibraryReader::new("test/extract_all.rcslib")?;
+        let report = lib.extract_all(dest, ConflictPolicy::Error)?;
+        assert_eq!(report.files, files.len() + 1);
+        assert_eq!(report.bytes, files.iter().map(|(_, data)| data.len() as u64).sum::<u64>() + png.len() as u64);
+        assert!(report.skipped.is_empty());
+
+        for (path, data) in files.iter().chain([&("textures/copy.png", &png[..])]) {
+            assert_eq!(std::fs::read(dest.join(path))?, *data, "{}", path);
+        }
+        let mtime = std::fs::metadata(dest.join("readme.txt"))?.modified()?;
+        assert_eq!(mtime.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(), 1_700_000_000);
+
+        // A second extraction runs into every file from the first
+        std::fs::write(dest.join("readme.txt"), "Changed")?;
+        assert!(matches!(lib.extract_all(dest, ConflictPolicy::Error), Err(ResourceLibraryError::PathError(PathError::PathExists(_)))));
+        let report = lib.extract_all(dest, ConflictPolicy::KeepExisting)?;
+        assert_eq!((report.files, report.skipped.len()), (0, files.len() + 1));
+        assert_eq!(std::fs::read(dest.join("readme.txt"))?, b"Changed");
+        let report = lib.extract_all(dest, ConflictPolicy::Overwrite)?;
+        assert_eq!((report.files, report.skipped.len()), (files.len() + 1, 0));
+        assert_eq!(std::fs::read(dest.join("readme.txt"))?, b"Read me");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::write("test/extract_script.sh", "#!/bin/sh\n")?;
+            std::fs::set_permissions("test/extract_script.sh", std::fs::Permissions::from_mode(0o750))?;
+            let mut writer = ResourceLibraryWriter::new();
+            writer.add_file("test/extract_script.sh", "bin/script.sh")?;
+            writer.write_str("escape/owned.txt", "Should stay inside")?;
+            writer.write_to_path("test/extract_unix.rcslib", CompressionLevel::Fast)?;
+
+            // A symlink that was already in dest can't be used to write outside of it
+            let dest = Path::new("test/extract_unix");
+            let _ = std::fs::remove_dir_all(dest);
+            let _ = std::fs::remove_dir_all("test/extract_outside");
+            std::fs::create_dir_all(dest)?;
+            std::fs::create_dir_all("test/extract_outside")?;
+            std::os::unix::fs::symlink("../extract_outside", dest.join("escape"))?;
+
+            let mut lib = ResourceLibraryReader::new("test/extract_unix.rcslib")?;
+            assert!(matches!(lib.extract_all(dest, ConflictPolicy::Overwrite), Err(ResourceLibraryError::ExtractThroughSymlink(path, _)) if path == "escape/owned.txt"));
+            assert!(!Path::new("test/extract_outside/owned.txt").exists());
+            assert_eq!(std::fs::metadata(dest.join("bin/script.sh"))?.permissions().mode() & 0o7777, 0o750);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn selective_extraction() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        for path in ["locale/de/menu.txt", "locale/de/help/intro.txt", "locale/del/menu.txt", "locale/en/menu.txt", "locale/de.txt", "readme.txt"] {
+            writer.write_str(path, &format!("contents of {}", path))?;
+        }
+        writer.write_to_path("test/selective.rcslib", CompressionLevel::Fast)?;
+        let mut lib = ResourceLibraryReader::new("test/selective.rcslib")?;
+
+        let extracted = |dest: &str| -> Result<Vec<String>> {
+            let mut files = Vec::new();
+            let mut dirs = vec![PathBuf::from(dest)];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    match path.is_dir() {
+                        true => dirs.push(path),
+                        false => {
+                            let name = path.strip_prefix(dest).unwrap().to_str().unwrap().replace('\\', "/");
+                            assert_eq!(std::fs::read_to_string(&path)?, format!("contents of {}", name));
+                            files.push(name);
+                        }
+                    }
+                }
+            }
+            files.sort();
+            Ok(files)
+        };
+
+        let _ = std::fs::remove_dir_all("test/extract_prefix");
+        let report = lib.extract_prefix("test/extract_prefix", "locale/de", ConflictPolicy::Error)?;
+        assert_eq!(report.files, 2);
+        assert_eq!(extracted("test/extract_prefix")?, ["locale/de/help/intro.txt", "locale/de/menu.txt"]);
+
+        let _ = std::fs::remove_dir_all("test/extract_nothing");
+        let report = lib.extract_prefix("test/extract_nothing", "locale/fr/", ConflictPolicy::Error)?;
+        assert_eq!((report.files, report.bytes), (0, 0));
+        assert!(extracted("test/extract_nothing")?.is_empty());
+
+        let _ = std::fs::remove_dir_all("test/extract_paths");
+        let paths = ["readme.txt", "locale/fr/menu.txt", "locale/en/menu.txt", "readme.txt"];
+        let report = lib.extract_paths("test/extract_paths", &paths, ConflictPolicy::Error, MissingPolicy::Report)?;
+        assert_eq!(report.files, 2);
+        assert_eq!(report.missing, ["locale/fr/menu.txt"]);
+        assert_eq!(extracted("test/extract_paths")?, ["locale/en/menu.txt", "readme.txt"]);
+
+        let _ = std::fs::remove_dir_all("test/extract_strict");
+        assert!(matches!(lib.extract_paths("test/extract_strict", &paths, ConflictPolicy::Error, MissingPolicy::Error),
+                         Err(ResourceLibraryError::PathError(PathError::InvalidPath(path))) if path == "locale/fr/menu.txt"));
+        assert!(!Path::new("test/extract_strict").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_from_memory() -> Result<()> {
+        let png = std::fs::read("test/testfile.png")?;
+        let mut writer = ResourceLibraryWriter::new();
+        writer.write_data("images/test.png", png.clone())?;
+        writer.write_str("readme.txt", "Read me")?;
+        writer.write_data("zeros.bin", vec![0u8; 5000])?;
+
+        let plain = writer.write_to_vec(CompressionLevel::Fast)?;
+        writer.set_block_size(Some(1000));
+        let chunked = writer.write_to_vec(CompressionLevel::Fast)?;
+        writer.set_block_size(None);
+
+        let mut libs = vec![
+            ResourceLibraryReader::from_reader(Cursor::new(plain))?,
+            ResourceLibraryReader::from_reader(Cursor::new(chunked))?
+        ];
+        #[cfg(feature = "encryption")]
+        {
+            writer.set_encryption(Some(ArchiveKey::password("hunter2")));
+            let encrypted = writer.write_to_vec(CompressionLevel::Fast)?;
+            libs.push(ResourceLibraryReader::from_reader_encrypted(Cursor::new(encrypted), &ArchiveKey::password("hunter2"))?);
+        }
+        for lib in &mut libs {
+            assert_eq!(&*lib.get_all_files(), ["images/test.png", "readme.txt", "zeros.bin"]);
+            assert_eq!(lib.glob("**/*.png")?, ["images/test.png"]);
+            assert!(lib.contains("readme.txt"));
+
+            assert_eq!(&*lib.read_file("images/test.png")?, &png[..]);
+            assert_eq!(&*lib.read_range("images/test.png", 1500..1600)?, &png[1500..1600]);
+            let mut sink = Vec::new();
+            assert_eq!(lib.read_file_to("zeros.bin", &mut sink)?, 5000);
+            assert_eq!(sink, [0u8; 5000]);
+            let mut buf = Vec::new();
+            lib.read_into("readme.txt", &mut buf)?;
+            assert_eq!(buf, b"Read me");
+
+            let mut entry = Vec::new();
+            lib.open_entry("images/test.png")?.read_to_end(&mut entry)?;
+            assert_eq!(entry, png);
+            assert!(lib.verify_all()?.is_ok());
+        }
+
+        let mut lib = ResourceLibraryReader::from_reader_with_options(libs.remove(0).into_inner(), ReaderOptions::new().case_insensitive(true))?;
+        assert_eq!(&*lib.read_file("README.TXT")?, b"Read me");
+        assert!(ResourceLibraryReader::from_reader(Cursor::new(b"not an archive".to_vec())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_from_bytes() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "images/test.png")?;
+        for i in 0..20 {
+            writer.write_str(format!("text/{}.txt", i), &format!("Test file {}", i).repeat(i))?;
+        }
+        writer.write_str("empty.txt", "")?;
+        let archive = writer.write_to_vec(CompressionLevel::Fast)?;
+
+        std::fs::write("test/from_bytes.rcslib", &archive)?;
+        let mut lib = ResourceLibraryReader::new("test/from_bytes.rcslib")?;
+        let mut expected = Vec::new();
+        for path in lib.get_all_files().iter().map(|path| path.to_string()).collect::<Vec<_>>() {
+            let data = lib.read_file(&path)?;
+            expected.push((path, data));
+        }
+        assert_eq!(expected.len(), 22);
+
+        let mut owned = ResourceLibraryReader::from_bytes(archive.clone())?;
+        let mut borrowed = ResourceLibraryReader::from_slice(&archive)?;
+        for (path, data) in &expected {
+            assert_eq!(&owned.read_file(path)?, data, "{}", path);
+            assert_eq!(&borrowed.read_file(path)?, data, "{}", path);
+        }
+
+        // A damaged magic number, then a header that promises more index than there is
+        let mut corrupted = archive.clone();
+        corrupted[0] ^= 0xFF;
+        assert!(matches!(ResourceLibraryReader::from_bytes(corrupted), Err(ResourceLibraryError::FileHeaderError)));
+        assert!(ResourceLibraryReader::from_slice(&archive[..HEADER_SIZE as usize + 4]).is_err());
+        assert!(ResourceLibraryReader::from_slice(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_reader() -> Result<()> {
+        let png = std::fs::read("test/testfile.png")?;
+        let mut writer = ResourceLibraryWriter::new();
+        writer.write_data("data/zeros.bin", vec![0u8; 5000])?;
+        writer.write_stream_with_compression("images/stored.png", ByteStream::from(png.clone()), Codec::Stored)?;
+        writer.write_str("readme.txt", "Read me")?;
+        writer.write_str("empty.txt", "")?;
+
+        for (name, block_size) in [("plain", None), ("chunked", Some(1000))] {
+            writer.set_block_size(block_size);
+            let path = format!("test/mmap_{}.rcslib", name);
+            writer.write_to_path(&path, CompressionLevel::Fast)?;
+
+            let mut file = ResourceLibraryReader::new(&path)?;
+            let mut mapped = ResourceLibraryReader::open_mmap(&path)?;
+            assert_eq!(mapped.get_all_files(), file.get_all_files());
+            for path in file.get_all_files().iter().map(|path| path.to_string()).collect::<Vec<_>>() {
+                assert_eq!(mapped.read_file(&path)?, file.read_file(&path)?, "{}", path);
+                assert_eq!(mapped.read_range(&path, 10..2000)?, file.read_range(&path, 10..2000)?, "{}", path);
+
+                let mut entry = Vec::new();
+                mapped.open_entry(&path)?.read_to_end(&mut entry)?;
+                assert_eq!(&entry[..], &*file.read_file(&path)?, "{}", path);
+            }
+            assert!(mapped.verify_all()?.is_ok());
+
+            // Only stored entries can be borrowed from the mapping, and those are never split into blocks
+            assert_eq!(mapped.mapped_bytes("images/stored.png"), Some(&png[..]));
+            assert_eq!(mapped.mapped_bytes("data/zeros.bin"), None);
+            assert_eq!(mapped.mapped_bytes("missing.png"), None);
+            assert_eq!(file.mapped_bytes("images/stored.png"), None);
+        }
+
+        std::fs::write("test/mmap_corrupt.rcslib", b"not an archive")?;
+        assert!(ResourceLibraryReader::open_mmap("test/mmap_corrupt.rcslib").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_from_static() -> Result<()> {
+        static FIXTURE: &[u8] = include_bytes!("../tests/fixtures/v8.rcslib");
+        let mut lib = ResourceLibraryReader::from_static(FIXTURE)?;
+        let expected: [(&str, &[u8]); 3] = [("test/a.txt", b"Test file A"), ("test/b.txt", b"Test file B"), ("test/c.txt", b"Test file C")];
+        assert_eq!(&*lib.get_all_files(), &expected.map(|(path, _)| path));
+        for (path, data) in expected {
+            assert_eq!(&*lib.read_file(path)?, data, "{}", path);
+
+            // Stored entries are borrowed out of the embedded bytes themselves
+            let borrowed = lib.mapped_bytes(path).unwrap();
+            assert_eq!(borrowed, data);
+            assert!(FIXTURE.as_ptr_range().contains(&borrowed.as_ptr()));
+        }
+        assert!(lib.verify_all()?.is_ok());
+
+        // Compressed and chunked entries are decompressed out of the buffer
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "images/test.png")?;
+        writer.write_str("text/repeated.txt", &"Test file ".repeat(1000))?;
+        writer.write_str("text/short.txt", &"Test file ".repeat(50))?;
+        writer.set_block_size(Some(1000));
+        let archive: &'static [u8] = writer.write_to_vec(CompressionLevel::Fast)?.leak();
+
+        let mut lib = ResourceLibraryReader::from_static(archive)?;
+        assert_eq!(&*lib.read_file("images/test.png")?, &std::fs::read("test/testfile.png")?[..]);
+        assert_eq!(&*lib.read_file("text/repeated.txt")?, "Test file ".repeat(1000).as_bytes());
+        assert_eq!(&*lib.read_file("text/short.txt")?, "Test file ".repeat(50).as_bytes());
+        assert_eq!(lib.mapped_bytes("text/repeated.txt"), None);
+        assert_eq!(lib.mapped_bytes("text/short.txt"), None);
+        assert!(lib.verify_all()?.is_ok());
+
+        assert!(ResourceLibraryReader::from_static(&FIXTURE[..HEADER_SIZE as usize]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reader_at_offset() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "images/test.png")?;
+        writer.write_str("text/a.txt", &"Test file A".repeat(100))?;
+        writer.write_str("empty.txt", "")?;
+        let png = std::fs::read("test/testfile.png")?;
+
+        for (name, layout) in [("first", Layout::IndexFirst), ("last", Layout::IndexLast)] {
+            writer.set_layout(layout);
+            let archive = writer.write_to_vec(CompressionLevel::Fast)?;
+
+            // Junk ahead of the archive, the way it is when the archive is appended to an executable
+            let junk: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+            let embedded = [&junk[..], &archive[..]].concat();
+            let path = format!("test/offset_{}.rcslib", name);
+            std::fs::write(&path, &embedded)?;
+
+            let mut lib = ResourceLibraryReader::new_at(&path, junk.len() as u64)?;
+            assert_eq!(&*lib.get_all_files(), &["empty.txt", "images/test.png", "text/a.txt"]);
+            assert_eq!(&*lib.read_file("images/test.png")?, &png[..]);
+            assert_eq!(&*lib.read_file("text/a.txt")?, "Test file A".repeat(100).as_bytes());
+            assert_eq!(&*lib.read_file("empty.txt")?, b"");
+            assert_eq!(&*lib.read_range("images/test.png", 100..200)?, &png[100..200]);
+            assert!(lib.verify_all()?.is_ok());
+
+            let mut cursor = Cursor::new(embedded);
+            cursor.seek(SeekFrom::End(0))?;
+            let mut lib = ResourceLibraryReader::from_reader_at(cursor, junk.len() as u64)?;
+            assert_eq!(&*lib.read_file("images/test.png")?, &png[..]);
+
+            for offset in [0, junk.len() as u64 - 1, junk.len() as u64 + 1, (junk.len() + archive.len()) as u64] {
+                assert!(matches!(ResourceLibraryReader::new_at(&path, offset), Err(ResourceLibraryError::FileHeaderError)), "{}", offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_embedded() -> Result<()> {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.add_file("test/testfile.png", "images/test.png")?;
+        writer.write_str("text/a.txt", &"Test file A".repeat(100))?;
+        writer.set_embed_footer(true);
+        let png = std::fs::read("test/testfile.png")?;
+
+        // Stands in for the executable the archive gets appended to
+        let executable: Vec<u8> = b"\x7fELF".iter().copied().chain((0..3000u32).map(|i| (i * 13) as u8)).collect();
+        std::fs::write("test/embedded.exe", &executable)?;
+
+        for (name, layout) in [("first", Layout::IndexFirst), ("last", Layout::IndexLast)] {
+            writer.set_layout(layout);
+            let path = format!("test/embedded_{}.exe", name);
+            std::fs::copy("test/embedded.exe", &path)?;
+
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            file.seek(SeekFrom::End(0))?;
+            writer.write_to_file(file, CompressionLevel::Fast)?;
+
+            let embedded = std::fs::read(&path)?;
+            assert_eq!(&embedded[..executable.len()], &executable[..]);
+            assert_eq!(embedded[embedded.len() - 8..], (executable.len() as u64).to_be_bytes());
+
+            let mut lib = ResourceLibraryReader::open_embedded(&path)?;
+            assert_eq!(&*lib.get_all_files(), &["images/test.png", "text/a.txt"]);
+            assert_eq!(&*lib.read_file("images/test.png")?, &png[..]);
+            assert_eq!(&*lib.read_file("text/a.txt")?, "Test file A".repeat(100).as_bytes());
+            assert!(lib.verify_all()?.is_ok());
+            if layout == Layout::IndexFirst {
+                assert!(lib.verify_fingerprint()?);
+            }
+        }
+
+        // An archive on its own with the footer starts at 0
+        let archive = writer.write_to_vec(CompressionLevel::Fast)?;
+        std::fs::write("test/embedded_alone.rcslib", &archive)?;
+        assert_eq!(&*ResourceLibraryReader::open_embedded("test/embedded_alone.rcslib")?.read_file("images/test.png")?, &png[..]);
+
+        // Files that don't end with a footer, including a plain archive, and a footer pointing past itself
+        writer.set_embed_footer(false);
+        writer.write_to_path("test/embedded_plain.rcslib", CompressionLevel::Fast)?;
+        std::fs::write("test/embedded_short.exe", b"\x7fELF")?;
+        let mut bad_offset = archive.clone();
+        let footer_start = bad_offset.len() - EMBED_FOOTER_SIZE as usize;
+        bad_offset[footer_start + 8..].copy_from_slice(&(footer_start as u64).to_be_bytes());
+        std::fs::write("test/embedded_bad_offset.exe", &bad_offset)?;
+        for path in ["test/embedded.exe", "test/embedded_plain.rcslib", "test/embedded_short.exe", "test/embedded_bad_offset.exe"] {
+            assert!(matches!(ResourceLibraryReader::open_embedded(path), Err(ResourceLibraryError::NoEmbeddedArchive(_))), "{}", path);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_file_stream() -> Result<()> {
         let _guard = FILE_LOCK.lock().unwrap();
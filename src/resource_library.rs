@@ -1,21 +1,85 @@
-use std::{collections::BTreeMap, fmt::Debug, fs::File, io::{Read, Seek, SeekFrom, Write}, path::Path};
+use std::{borrow::{Borrow, Cow}, cell::RefCell, collections::BTreeMap, ffi::OsString, fmt::{Debug, Display}, fs::{File, OpenOptions}, io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write}, ops::{Bound, Deref, Range}, path::{Path, PathBuf}, rc::Rc, sync::{atomic::AtomicBool, mpsc::sync_channel, Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "hashes")]
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
-use crate::index_serialization::{index_from_bytes, IndexSerializer, SerializationError};
+use crate::{glob::Glob, content_type::{detect_content_type, max_content_type_len, SNIFF_LEN}, encryption::{plain_len, ArchiveCipher, ArchiveKey, EntryCipher, EntryKeys, INDEX_OVERHEAD, SEALED_SEGMENT_SIZE, SEGMENT_SIZE}, codec::{check_cancelled, Codec, CodecRegistry, CompressionCodec, LzmaCodec}, index_serialization::{entries_from_bytes, entries_to_bytes, index_from_bytes, SerializationError, ENTRY_ALIAS, ENTRY_CHUNKED, ENTRY_ENCRYPTED, ENTRY_FLAGS_CRITICAL, KNOWN_ENTRY_FLAGS, MAX_PATH_LEN, NO_CRC, NO_MODE, NO_MTIME, UNKNOWN_LEN}};
+#[cfg(feature = "signing")]
+use crate::signing::{self, VerifyingKey};
+
+pub use crate::index_serialization::IndexEntry;
 
 const FORBIDDEN_CHARACTERS: &'static str = "\\?%*:|\"<>,;=";
-const HEADER_BYTES: [u8; 10] = [0x67, 0xD7, 0x70, 0x3A, 0x54, 0x3D, 0xDB, 0xF5, 0x17, 0x95]; // This is just a string of random numbers, it has no real signifigance
+pub(crate) const HEADER_BYTES: [u8; 10] = [0x67, 0xD7, 0x70, 0x3A, 0x54, 0x3D, 0xDB, 0xF5, 0x17, 0x95]; // This is just a string of random numbers, it has no real signifigance
+pub const FORMAT_VERSION: u16 = 8;
+// Magic, version, index size, data size, flags, and index padding
+pub(crate) const HEADER_SIZE: u64 = 42;
+// Archive-level flags, stored after the data size since version 2
+pub(crate) const FLAG_SOLID: u16 = 1;
+pub(crate) const FLAG_VOLUMES: u16 = 2;
+const FLAG_COMMENT: u16 = 4;
+const FLAG_INDEX_LAST: u16 = 8;
+const FLAG_INDEX_COMPRESSED: u16 = 16;
+pub(crate) const FLAG_COMPACT_OFFSETS: u16 = 32;
+const FLAG_ENCRYPTED: u16 = 64;
+pub(crate) const FLAG_FINGERPRINT: u16 = 128;
+const FLAG_CREATION_INFO: u16 = 256;
+const KNOWN_FLAGS: u16 = FLAG_SOLID | FLAG_VOLUMES | FLAG_COMMENT | FLAG_INDEX_LAST | FLAG_INDEX_COMPRESSED | FLAG_COMPACT_OFFSETS | FLAG_ENCRYPTED | FLAG_FINGERPRINT | FLAG_CREATION_INFO;
+
+// Archives with the index at the end close with its length, the data length, a checksum of both and the index, and these bytes
+const TRAILER_BYTES: [u8; 10] = [0x95, 0x17, 0xF5, 0xDB, 0x3D, 0x54, 0x3A, 0x70, 0xD7, 0x67];
+pub(crate) const TRAILER_SIZE: u64 = 30;
+
+// An archive written with an embed footer is followed by these bytes and the archive's offset in the file, so it can be
+// found at the end of whatever it was appended to
+const EMBED_FOOTER_BYTES: [u8; 8] = [0x3E, 0xA1, 0x5C, 0x09, 0xE2, 0x7B, 0x46, 0xD8];
+pub(crate) const EMBED_FOOTER_SIZE: u64 = 16;
+
+// The fingerprint sits right after the data section
+pub(crate) const FINGERPRINT_LEN: u64 = 32;
+// Entries larger than this are streamed through the encoder instead of being read into memory
+const STREAM_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Entries read into a sink go through a buffer of this size on the stack
+const SINK_CHUNK_SIZE: usize = 64 * 1024;
+// Metadata lives in the index, which is read into memory in full, so each entry only gets a little of it
+pub const MAX_ENTRY_METADATA: usize = 64 * 1024;
 
 pub type Result<T> = std::result::Result<T, ResourceLibraryError>;
 
 #[derive(Error, Debug)]
 pub enum PathError {
-    #[error("Character '{0}' not allowed in path.")]
-    DisallowedCharacter(char),
+    #[error("Character '{0}' not allowed in path: {1}")]
+    DisallowedCharacter(char, String),
     #[error("No resource exists at path: {0}")]
-    InvalidPath(String)
+    InvalidPath(String),
+    #[error("A resource already exists at path: {0}")]
+    PathExists(String),
+    #[error("Path is empty")]
+    EmptyPath,
+    #[error("Path must be relative: {0}")]
+    AbsolutePath(String),
+    #[error("Path has an empty component: {0}")]
+    EmptyComponent(String),
+    #[error("Component '{0}' not allowed in path: {1}")]
+    DisallowedComponent(String, String),
+    #[error("Path ends with a slash: {0}")]
+    TrailingSlash(String),
+    #[error("Control character {} not allowed in path: {1:?}", .0.escape_unicode())]
+    ControlCharacter(char, String),
+    #[error("Path starts or ends with whitespace: {0:?}")]
+    SurroundingWhitespace(String),
+    #[error("Paths {0} and {1} both normalize to {2}")]
+    NormalizationCollision(String, String, String),
+    #[error("Paths {0} and {1} only differ by case")]
+    CaseCollision(String, String),
+    #[error("Path is {0} bytes long, over the limit of {1} bytes")]
+    PathTooLong(usize, usize),
+    #[error("Component is {0} bytes long, over the limit of {1} bytes: {2}")]
+    ComponentTooLong(usize, usize, String),
+    #[error("Invalid glob pattern {0:?}: {1}")]
+    InvalidPattern(String, String)
 }
 
 #[derive(Error, Debug)]
@@ -25,41 +89,673 @@ pub enum ResourceLibraryError {
     PathError(#[from] PathError),
     #[error("File header does not match!")]
     FileHeaderError,
+    #[error("Unsupported format version {0}, this library supports up to version {1}")]
+    UnsupportedVersion(u16, u16),
+    #[error("Unsupported archive flags {0:#06x}")]
+    UnsupportedFlags(u16),
+    #[error("Entry {0} uses flags {1:#06x}, which this library doesn't support")]
+    UnsupportedEntryFlags(String, u16),
+    #[error("Archive header or index is corrupt")]
+    CorruptIndex,
+    #[error("The header gives an index of {0} bytes, but only {1} bytes follow it")]
+    IndexOutOfBounds(u64, u64),
+    #[error("The archive is encrypted, open it with a key")]
+    Encrypted,
+    #[error("Encryption is not enabled in this build")]
+    EncryptionNotEnabled,
+    #[error("The key doesn't match the one the archive was encrypted with")]
+    WrongKey,
+    #[error("Encrypted data failed authentication, the archive has been damaged or tampered with")]
+    AuthenticationFailed,
+    #[error("Multi-volume archives can't be encrypted")]
+    EncryptedVolumes,
+    #[error("Entry {0} is encrypted and no key was given")]
+    MissingKey(String),
+    #[error("Entry {0} can't be encrypted on its own in a solid archive")]
+    SolidEncryptedEntry(String),
+    #[error("The archive has no signature")]
+    Unsigned,
+    #[error("The archive's signature doesn't match its contents or the key")]
+    BadSignature,
+    #[error("Entry {0} lies outside of the archive data")]
+    EntryOutOfBounds(String),
+    #[error("Entry {0} doesn't decompress to its recorded size of {1} bytes")]
+    SizeMismatch(String, u64),
+    #[error("Checksum mismatch in {0}: expected {1:#010x}, got {2:#010x}")]
+    ChecksumMismatch(String, u32, u32),
+    #[error("SHA-256 of {0} doesn't match the one in the index")]
+    HashMismatch(String),
+    #[error("Solid archives can't be edited in place")]
+    SolidArchive,
+    #[error("Writing the archive was cancelled")]
+    Cancelled,
+    #[error("Multi-volume archives can't be edited or compacted")]
+    MultiVolumeArchive,
+    #[error("Volume {0} of the archive is missing")]
+    MissingVolume(u64),
+    #[error("{0} needs {1} bytes, which doesn't fit in a volume of at most {2} bytes")]
+    VolumeTooSmall(String, u64, u64),
+    #[error("Metadata for {0} would take {1} bytes, more than the limit of {MAX_ENTRY_METADATA}")]
+    MetadataTooLarge(String, usize),
+    #[error("Alias {0} points at {1}, which isn't in the archive")]
+    DanglingAlias(String, String),
+    #[error("Alias {0} is part of a cycle of aliases")]
+    AliasCycle(String),
+    #[error("Unknown compression codec {0}")]
+    UnknownCodec(u8),
+    #[error("Codec {0} is not enabled in this build")]
+    CodecNotEnabled(&'static str),
+    #[error("Codec id {0} is reserved for built-in codecs")]
+    ReservedCodecId(u8),
+    #[error("Compression level {0} is out of range, it must be between 0 and 9")]
+    InvalidCompressionLevel(u32),
+    #[error("{0}: {1}")]
+    DiskFileError(PathBuf, #[source] std::io::Error),
+    #[error("{0} is not a file")]
+    NotAFile(PathBuf),
+    #[error("Refusing to extract {0} through the symlink {1}")]
+    ExtractThroughSymlink(String, PathBuf),
+    #[error("Writing to the sink failed: {0}")]
+    SinkError(#[source] std::io::Error),
+    #[error("Entry {0} doesn't fit in a buffer of {1} bytes")]
+    BufferTooSmall(String, usize),
+    #[error("{0} is not valid UTF-8")]
+    NonUnicodePath(PathBuf),
+    #[error("{0} doesn't end with an embedded archive footer")]
+    NoEmbeddedArchive(PathBuf),
     IoError(#[from] std::io::Error),
     LZMAError(#[from] lzma::LzmaError)
 }
 
-#[derive(Clone, Copy)]
+impl ResourceLibraryError {
+    // Whether this is a lookup of a path with nothing at it, for falling back to another path
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ResourceLibraryError::PathError(PathError::InvalidPath(_)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionLevel {
-    Fastest = 1,
-    Fast = 3,
-    Normal = 5,
-    Maximum = 7,
-    Ultra = 9
+    None,
+    Fastest,
+    Fast,
+    Normal,
+    Maximum,
+    Ultra,
+    Custom(u32)
 }
 
-fn verify_str(str: &str) -> Result<&str> {
-    for c in str.chars() {
-        for forbidden in FORBIDDEN_CHARACTERS.chars() {
-            if c == forbidden {
-                return Err(PathError::DisallowedCharacter(c).into());
+impl CompressionLevel {
+    // None isn't compressed at all, so its preset is never actually used
+    pub fn preset(&self) -> u32 {
+        match self {
+            CompressionLevel::None => 0,
+            CompressionLevel::Fastest => 1,
+            CompressionLevel::Fast => 3,
+            CompressionLevel::Normal => 5,
+            CompressionLevel::Maximum => 7,
+            CompressionLevel::Ultra => 9,
+            CompressionLevel::Custom(level) => *level
+        }
+    }
+
+    pub(crate) fn validate(self) -> Result<CompressionLevel> {
+        match self {
+            CompressionLevel::Custom(level) if level > 9 => Err(ResourceLibraryError::InvalidCompressionLevel(level)),
+            level => Ok(level)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    KeepExisting,
+    Overwrite,
+    Error
+}
+
+// How far write_to_file and friends go to make sure the archive actually reached the disk.
+// write_to only has a generic sink, so it can flush but never fsync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SyncPolicy {
+    None,
+    Flush,
+    FsyncFile,
+    FsyncFileAndDir
+}
+
+// What gets rewritten in a path before it's validated. Paths are left alone by default, so a backslash is rejected
+// like any other forbidden character.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalize {
+    #[default]
+    None,
+    Backslashes
+}
+
+impl Normalize {
+    fn apply(self, path: &str) -> Cow<'_, str> {
+        match self {
+            Normalize::Backslashes if path.contains('\\') => Cow::Owned(path.replace('\\', "/")),
+            _ => Cow::Borrowed(path)
+        }
+    }
+}
+
+// Everything the writer and reader rewrite in a path before it's validated or looked up
+#[derive(Clone, Copy, Debug, Default)]
+struct PathRules {
+    separators: Normalize,
+    #[cfg(feature = "unicode")]
+    nfc: bool
+}
+
+impl PathRules {
+    fn apply<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let path = self.separators.apply(path);
+
+        #[cfg(feature = "unicode")]
+        if self.nfc && !unicode_normalization::is_nfc(&path) {
+            use unicode_normalization::UnicodeNormalization;
+            return Cow::Owned(path.nfc().collect());
+        }
+
+        path
+    }
+}
+
+// Limits on the paths the writer accepts. Indexes are never read with paths over MAX_PATH_LEN, so a longer max_len
+// still stops there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathPolicy {
+    pub max_len: usize,
+    pub max_component_len: usize
+}
+
+impl Default for PathPolicy {
+    fn default() -> PathPolicy {
+        PathPolicy { max_len: 4096, max_component_len: 255 }
+    }
+}
+
+impl PathPolicy {
+    fn check(&self, path: &str) -> Result<()> {
+        let max_len = self.max_len.min(MAX_PATH_LEN);
+        if path.len() > max_len {
+            return Err(PathError::PathTooLong(path.len(), max_len).into());
+        }
+
+        if let Some(component) = path.split('/').find(|component| component.len() > self.max_component_len) {
+            return Err(PathError::ComponentTooLong(component.len(), self.max_component_len, path.to_owned()).into());
+        }
+
+        Ok(())
+    }
+}
+
+// What the writer does about paths that only differ by case, which end up as one file on case-insensitive filesystems
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseCollisions {
+    Allow,
+    Report,
+    Forbid
+}
+
+fn fold_case(path: &str) -> String {
+    unicase::UniCase::new(path).to_folded_case()
+}
+
+// Each path that folds to the same thing as an earlier one, paired with the earlier one
+fn find_case_collisions<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    let mut folded: BTreeMap<String, &str> = BTreeMap::new();
+    let mut collisions = Vec::new();
+    for path in paths {
+        match folded.entry(fold_case(path)) {
+            std::collections::btree_map::Entry::Occupied(first) if *first.get() != path => collisions.push((first.get().to_string(), path.to_owned())),
+            std::collections::btree_map::Entry::Occupied(_) => (),
+            std::collections::btree_map::Entry::Vacant(slot) => {
+                slot.insert(path);
             }
         }
     }
 
+    collisions
+}
+
+// Where the index goes. With the index first it can be read without seeking to the end, but the header
+// has to be patched once the data is written. With it last, the archive is written front to back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    IndexFirst,
+    IndexLast
+}
+
+// A direct child of a directory in the writer. Directories aren't stored, so Dir is any name that has files under it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirEntry {
+    File(String),
+    Dir(String)
+}
+
+// How wide offsets and sizes are in the index. Compact indexes use 32 bits, which is plenty for archives under 4 GB.
+// Auto picks compact when the entries are small enough that the data section can't outgrow it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OffsetSize {
+    Auto,
+    Compact,
+    Full
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    pub files: usize,
+    pub bytes: u64,
+    // Entries left alone because a file was already there
+    pub skipped: Vec<String>,
+    // Paths that were asked for but aren't in the archive
+    pub missing: Vec<String>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingPolicy {
+    Report,
+    Error
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub replaced: Vec<String>,
+    pub skipped: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryReport {
+    pub path: String,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub codec: Codec
+}
+
+impl EntryReport {
+    pub fn ratio(&self) -> f64 {
+        ratio(self.compressed_size, self.original_size)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WriteReport {
+    pub entries: Vec<EntryReport>,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub index_size: u64,
+    pub fingerprint: Option<[u8; 32]>,
+    // Pairs of paths that only differ by case, when the writer was asked to report them
+    pub case_collisions: Vec<(String, String)>
+}
+
+impl WriteReport {
+    pub fn ratio(&self) -> f64 {
+        ratio(self.compressed_size, self.original_size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreationInfo {
+    // Version of this library that wrote the archive
+    pub library_version: String,
+    // Whatever the caller said made the archive
+    pub tool: Option<String>,
+    // Seconds since the unix epoch, left out of deterministic archives
+    pub created: Option<u64>
+}
+
+impl CreationInfo {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [&self.library_version[..], self.tool.as_deref().unwrap_or_default()] {
+            bytes.extend((field.len() as u64).to_be_bytes());
+            bytes.extend(field.as_bytes());
+        }
+        bytes.extend(self.created.unwrap_or(NO_MTIME).to_be_bytes());
+
+        bytes
+    }
+
+    // Fields added later go on the end, so anything past the ones known here is skipped
+    fn from_bytes(mut bytes: &[u8]) -> Result<CreationInfo> {
+        let library_version = String::from_utf8_lossy(take_field(&mut bytes)?).into_owned();
+        let tool = String::from_utf8_lossy(take_field(&mut bytes)?).into_owned();
+        let created = u64::from_be_bytes(take_bytes(&mut bytes, 8)?.try_into().unwrap());
+
+        Ok(CreationInfo { library_version, tool: (!tool.is_empty()).then_some(tool), created: (created != NO_MTIME).then_some(created) })
+    }
+}
+
+fn take_bytes<'a>(bytes: &mut &'a [u8], len: u64) -> Result<&'a [u8]> {
+    if (bytes.len() as u64) < len {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    let (taken, rest) = bytes.split_at(len as usize);
+    *bytes = rest;
+
+    Ok(taken)
+}
+
+// A length followed by that many bytes
+fn take_field<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = u64::from_be_bytes(take_bytes(bytes, 8)?.try_into().unwrap());
+    take_bytes(bytes, len)
+}
+
+// The optional parts of the header between its fixed part and the index, each stored as its length and then its bytes
+#[derive(Debug, Default, Clone)]
+pub(crate) struct HeaderExtras {
+    pub comment: Option<String>,
+    pub creation_info: Option<CreationInfo>
+}
+
+impl HeaderExtras {
+    fn flags(&self) -> u16 {
+        let comment = if self.comment.is_some() { FLAG_COMMENT } else { 0 };
+        let creation_info = if self.creation_info.is_some() { FLAG_CREATION_INFO } else { 0 };
+
+        comment | creation_info
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [self.comment.as_ref().map(|comment| comment.as_bytes().to_vec()), self.creation_info.as_ref().map(CreationInfo::to_bytes)].into_iter().flatten() {
+            bytes.extend((field.len() as u64).to_be_bytes());
+            bytes.extend(field);
+        }
+
+        bytes
+    }
+}
+
+// A view of one entry in a reader's index. Fields the index leaves unset for an entry come back as None.
+#[derive(Clone, Copy, Debug)]
+pub struct EntryRef<'a>(&'a IndexEntry);
+
+impl<'a> EntryRef<'a> {
+    pub fn path(&self) -> &'a str {
+        &self.0.path
+    }
+
+    // Where the entry's data starts within its volume's data section
+    pub fn offset(&self) -> u64 {
+        self.0.offset
+    }
+
+    pub fn compressed_len(&self) -> u64 {
+        self.0.compressed_len
+    }
+
+    pub fn uncompressed_len(&self) -> Option<u64> {
+        known_len(self.0.original_len)
+    }
+
+    pub fn codec(&self) -> u8 {
+        self.0.codec
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.0.flags
+    }
+
+    pub fn volume(&self) -> u64 {
+        self.0.volume
+    }
+
+    pub fn crc32(&self) -> Option<u32> {
+        (self.0.crc32 != NO_CRC).then_some(self.0.crc32 as u32)
+    }
+
+    pub fn mtime(&self) -> Option<u64> {
+        (self.0.mtime != NO_MTIME).then_some(self.0.mtime)
+    }
+
+    pub fn is_alias(&self) -> bool {
+        self.0.flags & ENTRY_ALIAS != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.0.flags & ENTRY_ENCRYPTED != 0
+    }
+
+    pub fn index_entry(&self) -> &'a IndexEntry {
+        self.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    pub entries: usize,
+    pub sizes: EntrySizes,
+    pub largest: Option<(String, EntrySizes)>,
+    // Bytes the index takes up in the file, as stored
+    pub index_size: u64
+}
+
+// Archives from before version 5 didn't record how large entries are once decompressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntrySizes {
+    pub compressed: u64,
+    pub uncompressed: Option<u64>
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub verified: usize,
+    pub failures: Vec<(String, ResourceLibraryError)>
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+fn ratio(compressed_size: u64, original_size: u64) -> f64 {
+    if original_size == 0 {
+        return 1.0;
+    }
+
+    compressed_size as f64 / original_size as f64
+}
+
+fn verify_str(str: &str) -> Result<&str> {
+    verify_characters(str)?;
+
     Ok(str)
 }
 
-fn verify_string(string: String) -> Result<String> {
-    for c in string.chars() {
-        for forbidden in FORBIDDEN_CHARACTERS.chars() {
-            if c == forbidden {
-                return Err(PathError::DisallowedCharacter(c).into());
-            }
+fn verify_characters(path: &str) -> std::result::Result<(), PathError> {
+    if let Some(c) = path.chars().find(|&c| FORBIDDEN_CHARACTERS.contains(c)) {
+        return Err(PathError::DisallowedCharacter(c, path.to_owned()));
+    }
+
+    if let Some(c) = path.chars().find(|c| c.is_ascii_control()) {
+        return Err(PathError::ControlCharacter(c, path.to_owned()));
+    }
+
+    Ok(())
+}
+
+// The key range holding everything under the directory named by prefix, with or without its trailing slash.
+// '0' is the character after '/', so the range stops right after the last path under dir/.
+pub(crate) fn prefix_bounds(prefix: &str) -> (Bound<String>, Bound<String>) {
+    if prefix.is_empty() {
+        return (Bound::Unbounded, Bound::Unbounded);
+    }
+
+    let dir = prefix.strip_suffix('/').unwrap_or(prefix);
+    (Bound::Included(format!("{}/", dir)), Bound::Excluded(format!("{}0", dir)))
+}
+
+// Prefixes match whole components, so "levels/act1" is the file levels/act1 and everything under levels/act1/,
+// but not levels/act10. With a trailing slash only what's under the directory matches.
+fn paths_under<'a, V>(map: &'a BTreeMap<ResourcePath, V>, prefix: &str) -> impl Iterator<Item = &'a ResourcePath> {
+    let exact = match prefix {
+        "" => None,
+        prefix if prefix.ends_with('/') => None,
+        prefix => map.get_key_value(prefix).map(|(path, _)| path)
+    };
+
+    let (lower, upper) = prefix_bounds(prefix);
+    let under = map.range::<str, _>((lower.as_ref().map(|s| &s[..]), upper.as_ref().map(|s| &s[..])));
+
+    exact.into_iter().chain(under.map(|(path, _)| path))
+}
+
+// The rules every path in an archive has to follow, for tools that want to check paths before handing them over.
+// Paths are checked as they are, nothing gets normalized. Archives are extracted relative to a directory, so
+// anything that could climb out of it is rejected along with paths that only differ by an extra slash.
+pub fn is_valid_resource_path(path: &str) -> Result<()> {
+    verify_characters(path)?;
+
+    if path.is_empty() {
+        return Err(PathError::EmptyPath.into());
+    }
+
+    if path.starts_with(char::is_whitespace) || path.ends_with(char::is_whitespace) {
+        return Err(PathError::SurroundingWhitespace(path.to_owned()).into());
+    }
+
+    if path.starts_with('/') {
+        return Err(PathError::AbsolutePath(path.to_owned()).into());
+    }
+
+    if path.ends_with('/') {
+        return Err(PathError::TrailingSlash(path.to_owned()).into());
+    }
+
+    for component in path.split('/') {
+        match component {
+            "" => return Err(PathError::EmptyComponent(path.to_owned()).into()),
+            "." | ".." => return Err(PathError::DisallowedComponent(component.to_owned(), path.to_owned()).into()),
+            _ => ()
+        }
+    }
+
+    Ok(())
+}
+
+// A path that has already been through is_valid_resource_path. Paths order the same way as the strings they hold,
+// which is the order entries are stored in.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResourcePath(String);
+
+impl ResourcePath {
+    pub fn new(path: impl Into<String>) -> Result<ResourcePath> {
+        let path = path.into();
+        is_valid_resource_path(&path)?;
+
+        Ok(ResourcePath(path))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // None for entries at the root of the archive
+    pub fn parent(&self) -> Option<&str> {
+        self.0.rsplit_once('/').map(|(parent, _)| parent)
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.0.rsplit_once('/').map_or(&self.0, |(_, name)| name)
+    }
+
+    // Whatever follows the last dot of the file name, like Path::extension. Dotfiles like ".gitignore" have none.
+    pub fn extension(&self) -> Option<&str> {
+        match self.file_name().rsplit_once('.') {
+            Some(("", _)) | None => None,
+            Some((_, extension)) => Some(extension)
+        }
+    }
+
+    // Ignores case, and takes a leading dot or not. ext can span dots, so "tar.gz" matches archive.tar.gz
+    // where extension() would only give "gz".
+    pub fn has_extension(&self, ext: &str) -> bool {
+        let ext = ext.strip_prefix('.').unwrap_or(ext);
+        let name = self.file_name();
+        match name.len().checked_sub(ext.len() + 1).and_then(|dot| Some((name.get(..dot)?, name.get(dot..)?))) {
+            Some((stem, dot_ext)) => !stem.is_empty() && !ext.is_empty() && dot_ext.starts_with('.') && fold_case(&dot_ext[1..]) == fold_case(ext),
+            None => false
         }
     }
 
-    Ok(string)
+    // The result goes through the same validation as any other path, so component can't climb out with ".."
+    pub fn join(&self, component: &str) -> Result<ResourcePath> {
+        ResourcePath::new(format!("{}/{}", self.0, component))
+    }
+
+    pub fn components(&self) -> std::str::Split<'_, char> {
+        self.0.split('/')
+    }
+}
+
+impl Deref for ResourcePath {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for ResourcePath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for ResourcePath {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ResourcePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for ResourcePath {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for ResourcePath {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl TryFrom<String> for ResourcePath {
+    type Error = ResourceLibraryError;
+
+    fn try_from(path: String) -> Result<ResourcePath> {
+        ResourcePath::new(path)
+    }
+}
+
+impl TryFrom<&str> for ResourcePath {
+    type Error = ResourceLibraryError;
+
+    fn try_from(path: &str) -> Result<ResourcePath> {
+        ResourcePath::new(path)
+    }
+}
+
+impl From<ResourcePath> for String {
+    fn from(path: ResourcePath) -> String {
+        path.0
+    }
 }
 
 pub struct ByteStream {
@@ -129,174 +825,3279 @@ impl From<Vec<u8>> for ByteStream {
     }
 }
 
-pub trait Resource: Read + Seek + Debug {} 
-impl<T: Read + Seek + Debug> Resource for T {}
+pub trait Resource: Read + Seek + Debug + Send {}
+impl<T: Read + Seek + Debug + Send> Resource for T {}
 
+// A file on disk that isn't opened until something reads from it, and is closed again once it has been read to the end,
+// or as far as the length it last reported. Its length comes from the file's metadata, so finding it doesn't open the file.
 #[derive(Debug)]
-pub struct ResourceLibraryWriter {
-    map: BTreeMap<String, Box<dyn Resource>>
+struct DiskFile {
+    path: PathBuf,
+    file: Option<File>,
+    position: u64,
+    len: Option<u64>
 }
 
-impl ResourceLibraryWriter {
-    pub fn new() -> ResourceLibraryWriter {
-        ResourceLibraryWriter { map: BTreeMap::new() }
+impl DiskFile {
+    fn new(path: PathBuf) -> DiskFile {
+        DiskFile { path, file: None, position: 0, len: None }
     }
 
-    pub fn write_stream<T: Read + Seek + Debug + 'static>(&mut self, path: String, stream: T) -> Result<()> {
-        self.map.insert(verify_string(path)?, Box::new(stream));
-
-        Ok(())
+    fn with_path<T>(&self, result: std::io::Result<T>) -> std::io::Result<T> {
+        result.map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", self.path.display(), err)))
     }
 
-    pub fn read_data<'a>(&'a mut self, path: &str) -> Result<Box<[u8]>> {
-        match self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()).into()) {
-            Ok(resource) => {
-                let mut bytes = Vec::new();
-                resource.rewind()?;
-                resource.read_to_end(&mut bytes)?;
-    
-                Ok(bytes.into_boxed_slice())
-            },
-            Err(err) => Err(err)
+    fn file(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            let mut file = self.with_path(File::open(&self.path))?;
+            file.seek(SeekFrom::Start(self.position))?;
+            self.file = Some(file);
         }
-    }
 
-    pub fn take_data(&mut self, path: &str) -> Result<Box<[u8]>> {
-        match self.map.remove(path).ok_or(PathError::InvalidPath(path.to_owned()).into()) {
-            Ok(mut resource) => {
-                let mut bytes = Vec::new();
-                resource.rewind()?;
-                resource.read_to_end(&mut bytes)?;
-    
-                Ok(bytes.into_boxed_slice())
-            },
-            Err(err) => Err(err)
-        }
+        Ok(self.file.as_mut().unwrap())
     }
+}
 
-    pub fn write_to_file<'a>(&mut self, mut file: File, compression_level: CompressionLevel) -> Result<()> {
-        // Create index template
+impl Read for DiskFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.file()?.read(buf)?;
+        self.position += bytes_read as u64;
 
-        // Create index buffer
-        let mut index = Vec::new();
-        // Since map is a tree map, iterator will be in order, sorted by filename
-        for (filename, _) in self.map.iter_mut() {
-            // Write zeroes to be replaced later
-            let slice_tuple = (filename.clone(), u64::MAX, u64::MAX);
-            index.push(slice_tuple);
+        if (bytes_read == 0 && !buf.is_empty()) || self.len.is_some_and(|len| self.position >= len) {
+            self.file = None;
         }
 
-        let mut serializer = IndexSerializer::new();
-        index.serialize(&mut serializer)?;
-        let index_data = serializer.take();
+        Ok(bytes_read)
+    }
+}
 
-        // Write header
-        file.write(&HEADER_BYTES)?;
+impl Seek for DiskFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let len = self.with_path(std::fs::metadata(&self.path))?.len();
+                self.len = Some(len);
+                len.checked_add_signed(offset)
+            }
+        };
+        self.position = position.ok_or(std::io::ErrorKind::InvalidInput)?;
 
-        // Write metadata
-        file.write(&index_data.len().to_be_bytes())?;
+        if let Some(file) = &mut self.file {
+            file.seek(SeekFrom::Start(self.position))?;
+        }
 
-        let data_len_offset = file.stream_position()?;
-        file.write(&0u64.to_be_bytes())?;
+        Ok(self.position)
+    }
+}
 
-        // Write index data
-        file.write(&index_data)?;
+#[derive(Debug)]
+struct Entry {
+    resource: Box<dyn Resource>,
+    compression: Option<Codec>,
+    // Seconds since the unix epoch, recorded in the index when known
+    mtime: Option<u64>,
+    // Unix permission bits of files added from disk
+    mode: Option<u32>,
+    metadata: BTreeMap<String, Box<[u8]>>,
+    content_type: Option<String>,
+    encrypted: bool,
+    // Files added from disk can be memory-mapped instead of read
+    #[cfg(feature = "mmap")]
+    disk_path: Option<PathBuf>
+}
 
-        let mut data_len = 0;
+impl Entry {
+    fn new(resource: Box<dyn Resource>) -> Entry {
+        Entry { resource, compression: None, mtime: None, mode: None, metadata: BTreeMap::new(), content_type: None, encrypted: false, #[cfg(feature = "mmap")] disk_path: None }
+    }
 
-        // Since map is a tree map, iterator will be in order, sorted by filename
-        for (i, (_, resource)) in self.map.iter_mut().enumerate() {
-            let mut data = Vec::new();
-            resource.rewind()?;
-            resource.read_to_end(&mut data)?;
-            let data = data.into_boxed_slice();
+    #[cfg(feature = "mmap")]
+    fn from_disk(path: PathBuf) -> Entry {
+        Entry { disk_path: Some(path.clone()), ..Entry::new(Box::new(DiskFile::new(path))) }
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn from_disk(path: PathBuf) -> Entry {
+        Entry::new(Box::new(DiskFile::new(path)))
+    }
+}
 
-            // Compress data
-            let f_data = lzma::compress(&data, compression_level as u32)?;
+// An entry's bytes when it's compressed in one piece
+enum EntryData {
+    Buffered(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap)
+}
 
-            // Write the current number of bytes in the buffer to our index
-            index[i].1 = data_len;
-            index[i].2 = f_data.len() as u64;
+impl AsRef<[u8]> for EntryData {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            EntryData::Buffered(data) => data,
+            #[cfg(feature = "mmap")]
+            EntryData::Mapped(data) => data
+        }
+    }
+}
+
+impl From<Vec<u8>> for EntryData {
+    fn from(data: Vec<u8>) -> Self {
+        EntryData::Buffered(data)
+    }
+}
+
+// The checksums recorded in an entry's index entry, computed as its bytes go by
+struct EntryDigest {
+    crc32: crc32fast::Hasher,
+    #[cfg(feature = "hashes")]
+    sha256: Option<Sha256>
+}
+
+impl EntryDigest {
+    #[cfg(feature = "hashes")]
+    fn new(sha256: bool) -> EntryDigest {
+        EntryDigest { crc32: crc32fast::Hasher::new(), sha256: sha256.then(Sha256::new) }
+    }
+
+    // store_hashes can't be turned on without the hashes feature
+    #[cfg(not(feature = "hashes"))]
+    fn new(_sha256: bool) -> EntryDigest {
+        EntryDigest { crc32: crc32fast::Hasher::new() }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        #[cfg(feature = "hashes")]
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(data);
+        }
+        self.crc32.update(data);
+    }
+
+    fn finish(self, entry: &mut IndexEntry) {
+        #[cfg(feature = "hashes")]
+        let sha256 = self.sha256.map_or_else(Vec::new, |sha256| sha256.finalize().to_vec());
+        #[cfg(not(feature = "hashes"))]
+        let sha256 = Vec::new();
+
+        entry.crc32 = self.crc32.finalize() as u64;
+        entry.sha256 = sha256;
+    }
+}
+
+// Checksums everything that's read through it, and keeps the first few bytes for sniffing the content type
+struct ChecksumReader<R> {
+    inner: R,
+    digest: EntryDigest,
+    head: Vec<u8>
+}
+
+impl<R> ChecksumReader<R> {
+    fn new(inner: R, sha256: bool) -> ChecksumReader<R> {
+        ChecksumReader { inner, digest: EntryDigest::new(sha256), head: Vec::new() }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.digest.update(&buf[..bytes_read]);
+
+        let missing = (SNIFF_LEN as usize).saturating_sub(self.head.len());
+        self.head.extend_from_slice(&buf[..bytes_read.min(missing)]);
+
+        Ok(bytes_read)
+    }
+}
+
+// Where write_to gets an entry's bytes from
+enum EntrySource<'a> {
+    Resource(&'a mut Box<dyn Resource>),
+    // Read ahead of time by a reader thread
+    Prefetched,
+    #[cfg(feature = "mmap")]
+    Mapped(Cursor<memmap2::Mmap>)
+}
 
-            // Write to the file
-            file.write(&f_data[..])?;
-            data_len += f_data.len() as u64;
+impl EntrySource<'_> {
+    fn reader(&mut self) -> Option<&mut dyn Read> {
+        match self {
+            EntrySource::Resource(resource) => Some(&mut **resource),
+            EntrySource::Prefetched => None,
+            #[cfg(feature = "mmap")]
+            EntrySource::Mapped(mapped) => Some(mapped)
         }
+    }
+}
+
+// Mapping is only an optimization, so anything that goes wrong falls back to reading the file
+#[cfg(feature = "mmap")]
+fn map_file(path: &Path, len: u64) -> Option<memmap2::Mmap> {
+    let file = File::open(path).ok()?;
+
+    // The mapping is only valid as long as nothing else modifies the file while the archive is written
+    let mapped = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    (mapped.len() as u64 == len).then_some(mapped)
+}
+
+// Directories can only be opened (and synced) like files on Unix
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+
+    File::open(parent).and_then(|dir| dir.sync_all()).map_err(|err| ResourceLibraryError::DiskFileError(parent.to_owned(), err))
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// The temp file sits next to the destination so a rename never crosses filesystems
+fn temp_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| ResourceLibraryError::NotAFile(path.to_owned()))?;
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+
+    Ok(path.with_file_name(temp_name))
+}
+
+fn volume_path(path: &Path, volume: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{:03}", volume + 1));
+
+    PathBuf::from(name)
+}
+
+// The checksum covers every header field before it as well as the extras and index
+fn header_crc(version: u16, index_data: &[u8], index_padding: u64, data_size: u64, flags: u16, extras_data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&HEADER_BYTES);
+    hasher.update(&version.to_be_bytes());
+    hasher.update(&(index_data.len() as u64).to_be_bytes());
+    hasher.update(&data_size.to_be_bytes());
+    hasher.update(&flags.to_be_bytes());
+    hasher.update(&index_padding.to_be_bytes());
+    hasher.update(extras_data);
+    hasher.update(index_data);
+
+    hasher.finalize()
+}
+
+// Where the index starts, since the extras sit between it and the fixed part of the header
+pub(crate) fn index_start(extras: &HeaderExtras) -> u64 {
+    HEADER_SIZE + extras.to_bytes().len() as u64
+}
+
+// Writes an index-first header, along with the index and the space reserved after it
+pub(crate) fn write_header<W: Write>(sink: &mut W, index_data: &[u8], index_padding: u64, data_size: u64, flags: u16, extras: &HeaderExtras) -> Result<()> {
+    write_fixed_header(sink, index_data, index_padding, data_size, flags & !FLAG_INDEX_LAST, extras)?;
+    sink.write_all(index_data)?;
+    std::io::copy(&mut std::io::repeat(0).take(index_padding), sink)?;
+
+    Ok(())
+}
+
+// Writes the header up to where the index would go
+fn write_fixed_header<W: Write>(sink: &mut W, index_data: &[u8], index_padding: u64, data_size: u64, flags: u16, extras: &HeaderExtras) -> Result<()> {
+    let flags = flags & !(FLAG_COMMENT | FLAG_CREATION_INFO) | extras.flags();
+    let extras_data = extras.to_bytes();
+
+    sink.write_all(&HEADER_BYTES)?;
+    sink.write_all(&FORMAT_VERSION.to_be_bytes())?;
+    sink.write_all(&(index_data.len() as u64).to_be_bytes())?;
+    sink.write_all(&data_size.to_be_bytes())?;
+    sink.write_all(&flags.to_be_bytes())?;
+    sink.write_all(&index_padding.to_be_bytes())?;
+    sink.write_all(&header_crc(FORMAT_VERSION, index_data, index_padding, data_size, flags, &extras_data).to_be_bytes())?;
+    sink.write_all(&extras_data)?;
+
+    Ok(())
+}
+
+// A compressed index is stored as its uncompressed length followed by the LZMA stream. Where the index has to fit
+// in a fixed amount of space and the compressed form doesn't, the plain index is stored instead.
+pub(crate) fn encode_index(index_data: &[u8], flags: u16, space: Option<u64>) -> Result<(Box<[u8]>, u16)> {
+    if flags & FLAG_INDEX_COMPRESSED == 0 {
+        return Ok((index_data.into(), flags));
+    }
+
+    let mut packed = (index_data.len() as u64).to_be_bytes().to_vec();
+    packed.extend(LzmaCodec::default().compress(index_data)?);
+
+    match space {
+        Some(space) if packed.len() as u64 > space => Ok((index_data.into(), flags & !FLAG_INDEX_COMPRESSED)),
+        _ => Ok((packed.into_boxed_slice(), flags))
+    }
+}
+
+fn decode_index(stored: Vec<u8>, flags: u16) -> Result<Vec<u8>> {
+    if flags & FLAG_INDEX_COMPRESSED == 0 {
+        return Ok(stored);
+    }
+
+    let (len, packed) = stored.split_first_chunk::<8>().ok_or(ResourceLibraryError::CorruptIndex)?;
+    let len = u64::from_be_bytes(*len);
+    let index_data = LzmaCodec::default().decompress(packed, Some(len))?;
+    if index_data.len() as u64 != len {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    Ok(index_data)
+}
+
+// The index is compressed before it's sealed, so sealing happens last when writing and is undone first when reading
+fn seal_index(index_data: Box<[u8]>, cipher: Option<&ArchiveCipher>) -> Result<Box<[u8]>> {
+    match cipher {
+        Some(cipher) => cipher.seal_index(&index_data),
+        None => Ok(index_data)
+    }
+}
+
+fn open_index(stored: Vec<u8>, flags: u16, key: Option<&ArchiveKey>) -> Result<(Vec<u8>, Option<ArchiveCipher>)> {
+    match (flags & FLAG_ENCRYPTED, key) {
+        (0, _) => Ok((stored, None)),
+        (_, Some(key)) => ArchiveCipher::open_index(key, &stored).map(|(cipher, index_data)| (index_data, Some(cipher))),
+        (_, None) => Err(ResourceLibraryError::Encrypted)
+    }
+}
+
+// Identifies an archive's contents. Hashing the data section and stored index separately lets the writer hash the data
+// as it goes by, before the final index is known, and lets the reader check it without reading the index again.
+pub(crate) fn archive_fingerprint(data_digest: &blake3::Hash, index_digest: &blake3::Hash) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(data_digest.as_bytes());
+    hasher.update(index_digest.as_bytes());
+
+    hasher.finalize().into()
+}
+
+// Aliases are copies of their target's index entry, merged in by path
+fn with_aliases(index: &[IndexEntry], aliases: &[(ResourcePath, usize)]) -> Vec<IndexEntry> {
+    let mut entries = index.to_vec();
+    entries.extend(aliases.iter().map(|(alias, n)| IndexEntry { path: alias.clone(), flags: index[*n].flags | ENTRY_ALIAS, target: index[*n].path.to_string(), ..index[*n].clone() }));
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    entries
+}
+
+fn trailer_crc(index_data: &[u8], data_size: u64) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&(index_data.len() as u64).to_be_bytes());
+    hasher.update(&data_size.to_be_bytes());
+    hasher.update(index_data);
+
+    hasher.finalize()
+}
 
-        // Update data length
-        file.seek(SeekFrom::Start(data_len_offset))?;
-        file.write(&data_len.to_be_bytes())?;
+fn write_trailer<W: Write>(sink: &mut W, index_data: &[u8], data_size: u64) -> Result<()> {
+    sink.write_all(index_data)?;
+    sink.write_all(&(index_data.len() as u64).to_be_bytes())?;
+    sink.write_all(&data_size.to_be_bytes())?;
+    sink.write_all(&trailer_crc(index_data, data_size).to_be_bytes())?;
+    sink.write_all(&TRAILER_BYTES)?;
 
-        // Update index
-        let mut serializer = IndexSerializer::new();
-        index.serialize(&mut serializer)?;
-        let index_data = serializer.take();
-        file.write(&index_data)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Only the permission bits, the file type is always a regular file
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_file_mode(file: &File, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_file: &File, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Tracks the data section as it's written, padding entries out to the configured alignment
+struct DataSection<'a, W> {
+    sink: &'a mut W,
+    // Where the data section begins, relative to the start of the archive
+    start: u64,
+    len: u64,
+    alignment: u64,
+    // Encrypted data is held back until a whole segment can be sealed
+    cipher: Option<&'a ArchiveCipher>,
+    segment: Vec<u8>,
+    sealed_segments: u64,
+    entry_cipher: Option<&'a EntryCipher>,
+    // Everything that reaches the sink, for the archive's fingerprint
+    hasher: blake3::Hasher
+}
+
+// Anything written through the section counts towards its length
+impl<W: Write> Write for DataSection<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = match self.cipher {
+            Some(_) => {
+                let written = buf.len().min(SEGMENT_SIZE as usize - self.segment.len());
+                self.segment.extend_from_slice(&buf[..written]);
+                if self.segment.len() == SEGMENT_SIZE as usize {
+                    self.seal_segment()?;
+                }
+
+                written
+            },
+            None => {
+                let written = self.sink.write(buf)?;
+                self.hasher.update(&buf[..written]);
+
+                written
+            }
+        };
+        self.len += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+impl<'a, W: Write> DataSection<'a, W> {
+    fn new(sink: &'a mut W, start: u64, alignment: u64, cipher: Option<&'a ArchiveCipher>, entry_cipher: Option<&'a EntryCipher>) -> DataSection<'a, W> {
+        DataSection { sink, start, len: 0, alignment, cipher, segment: Vec::new(), sealed_segments: 0, entry_cipher, hasher: blake3::Hasher::new() }
+    }
+
+    fn align(&mut self) -> Result<()> {
+        if self.alignment > 1 {
+            let padding = (self.alignment - (self.start + self.len) % self.alignment) % self.alignment;
+            self.write_all(&vec![0u8; padding as usize])?;
+        }
 
         Ok(())
     }
 
-    pub fn get_all_files(&self) -> Box<[&str]> {
-        self.map.keys().map(|path| &path[..]).collect()
+    fn seal_segment(&mut self) -> std::io::Result<()> {
+        let sealed = self.cipher.unwrap().seal_segment(self.sealed_segments, &self.segment).map_err(std::io::Error::other)?;
+        self.sink.write_all(&sealed)?;
+        self.hasher.update(&sealed);
+        self.sealed_segments += 1;
+        self.segment.clear();
+
+        Ok(())
+    }
+
+    // Seals whatever is left of the last segment, returning how long the data section ended up on disk and its digest
+    fn finish(mut self) -> Result<(u64, blake3::Hash)> {
+        if self.cipher.is_none() {
+            return Ok((self.len, self.hasher.finalize()));
+        }
+
+        if !self.segment.is_empty() {
+            self.seal_segment()?;
+        }
+
+        Ok((self.len + self.sealed_segments * (SEALED_SEGMENT_SIZE - SEGMENT_SIZE), self.hasher.finalize()))
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        self.write_all(data)?;
+
+        Ok(())
+    }
+
+    fn write_compressed(&mut self, codecs: &CodecRegistry, jobs: Vec<(Codec, EntryData)>, min_savings: Option<f64>, index: &mut [IndexEntry], next: &mut usize) -> Result<()> {
+        // Results come back in the same order as the jobs, so the layout doesn't depend on threading
+        for (codec, f_data) in codecs.compress_all(jobs, min_savings)? {
+            self.align()?;
+
+            // Encrypted entries are sealed once they're compressed
+            let sealed = match self.entry_cipher {
+                Some(cipher) if index[*next].flags & ENTRY_ENCRYPTED != 0 => Some(cipher.seal(f_data.as_ref())?),
+                _ => None
+            };
+            let f_data = sealed.as_deref().unwrap_or(f_data.as_ref());
+
+            // Write the current number of bytes in the buffer to our index
+            index[*next].offset = self.len;
+            index[*next].compressed_len = f_data.len() as u64;
+            index[*next].codec = codec.id();
+
+            // Write to the sink
+            self.write_data(f_data)?;
+            *next += 1;
+        }
+
+        Ok(())
     }
 }
 
-pub struct ResourceLibraryReader {
-    file: File,
-    index: Box<[(String, u64, u64)]>,
-    data_pointer: u64
+#[derive(Debug)]
+pub struct ResourceLibraryWriter {
+    map: BTreeMap<ResourcePath, Entry>,
+    // Alias paths mapped to the path they were given as a target
+    aliases: BTreeMap<ResourcePath, ResourcePath>,
+    duplicate_policy: ConflictPolicy,
+    path_rules: PathRules,
+    path_policy: PathPolicy,
+    // Each path's name as it was given, once separators were normalized, for catching names that only
+    // end up the same after unicode normalization
+    #[cfg(feature = "unicode")]
+    source_names: BTreeMap<ResourcePath, String>,
+    case_collisions: CaseCollisions,
+    // Case folded paths, kept while case collisions are forbidden so single inserts don't have to check every path
+    case_folds: BTreeMap<String, ResourcePath>,
+    follow_symlinks: bool,
+    include_hidden: bool,
+    codecs: CodecRegistry,
+    max_in_flight: usize,
+    min_savings: Option<f64>,
+    no_compress_extensions: Vec<String>,
+    solid: bool,
+    block_size: Option<u64>,
+    alignment: u64,
+    sync_policy: SyncPolicy,
+    index_padding: u64,
+    cancel: Option<Arc<AtomicBool>>,
+    read_ahead: usize,
+    store_hashes: bool,
+    detect_content_types: bool,
+    comment: Option<String>,
+    record_creation: bool,
+    tool: Option<String>,
+    deterministic: bool,
+    layout: Layout,
+    compress_index: bool,
+    offset_size: OffsetSize,
+    encryption: Option<ArchiveKey>,
+    entry_key: Option<ArchiveKey>,
+    embed_footer: bool,
+    #[cfg(feature = "mmap")]
+    mmap_threshold: u64
 }
 
-impl ResourceLibraryReader {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<ResourceLibraryReader> {
-        let mut file = File::open(path)?;
+impl ResourceLibraryWriter {
+    pub fn new() -> ResourceLibraryWriter {
+        ResourceLibraryWriter { map: BTreeMap::new(), aliases: BTreeMap::new(), duplicate_policy: ConflictPolicy::Overwrite, path_rules: PathRules::default(), path_policy: PathPolicy::default(), #[cfg(feature = "unicode")] source_names: BTreeMap::new(), case_collisions: CaseCollisions::Allow, case_folds: BTreeMap::new(), follow_symlinks: true, include_hidden: true, codecs: CodecRegistry::default(), max_in_flight: 64, min_savings: Some(0.0), no_compress_extensions: Vec::new(), solid: false, block_size: None, alignment: 1, sync_policy: SyncPolicy::Flush, index_padding: 0, cancel: None, read_ahead: 0, store_hashes: false, detect_content_types: false, comment: None, record_creation: false, tool: None, deterministic: false, layout: Layout::IndexFirst, compress_index: false, offset_size: OffsetSize::Auto, encryption: None, entry_key: None, embed_footer: false, #[cfg(feature = "mmap")] mmap_threshold: 16 * 1024 * 1024 }
+    }
+
+    pub fn register_codec(&mut self, codec: impl CompressionCodec + 'static) -> Result<()> {
+        self.codecs.register(codec)
+    }
+
+    pub fn set_duplicate_policy(&mut self, policy: ConflictPolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    // Applies to paths as they're added, and to the paths given to lookups of entries that are already in the writer
+    pub fn set_path_normalization(&mut self, normalization: Normalize) {
+        self.path_rules.separators = normalization;
+    }
+
+    pub fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.path_policy = policy;
+    }
+
+    // Rewrites paths into NFC as they're added, so names that were decomposed by the filesystem they came from
+    // match the composed ones used in code. Two different names that normalize to the same path are an error.
+    #[cfg(feature = "unicode")]
+    pub fn set_nfc_paths(&mut self, nfc: bool) {
+        self.path_rules.nfc = nfc;
+    }
+
+    pub fn set_case_collisions(&mut self, policy: CaseCollisions) {
+        self.case_collisions = policy;
+        self.rebuild_case_folds();
+    }
+
+    pub fn forbid_case_collisions(&mut self, forbid: bool) {
+        self.set_case_collisions(if forbid { CaseCollisions::Forbid } else { CaseCollisions::Allow });
+    }
+
+    fn rebuild_case_folds(&mut self) {
+        self.case_folds.clear();
+        if self.case_collisions == CaseCollisions::Forbid {
+            for path in self.map.keys().chain(self.aliases.keys()) {
+                self.case_folds.entry(fold_case(path)).or_insert_with(|| path.clone());
+            }
+        }
+    }
+
+    // Records a path that's about to be added, unless it only differs by case from one that's already there
+    fn claim_case(&mut self, path: &ResourcePath) -> Result<()> {
+        if self.case_collisions != CaseCollisions::Forbid {
+            return Ok(());
+        }
 
-        let mut first_10 = [0u8; 10];
-        file.read(&mut first_10)?;
+        match self.case_folds.entry(fold_case(path)) {
+            std::collections::btree_map::Entry::Occupied(existing) if existing.get() != path => Err(PathError::CaseCollision(existing.get().to_string(), path.to_string()).into()),
+            std::collections::btree_map::Entry::Occupied(_) => Ok(()),
+            std::collections::btree_map::Entry::Vacant(slot) => {
+                slot.insert(path.clone());
+                Ok(())
+            }
+        }
+    }
 
-        if first_10 != HEADER_BYTES {
-            return Err(ResourceLibraryError::FileHeaderError.into());
+    // For changes that touch many paths at once, which are checked as a whole before anything is changed
+    fn check_case_collisions<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        match self.case_collisions {
+            CaseCollisions::Forbid => match find_case_collisions(paths).into_iter().next() {
+                Some((first, second)) => Err(PathError::CaseCollision(first, second).into()),
+                None => Ok(())
+            },
+            _ => Ok(())
         }
+    }
 
-        // Read metadata
-        let mut index_size = [0u8; 8];
-        let mut data_size = [0u8; 8];
+    pub fn set_follow_symlinks(&mut self, follow_symlinks: bool) {
+        self.follow_symlinks = follow_symlinks;
+    }
 
-        file.read(&mut index_size)?;
-        file.read(&mut data_size)?;
+    pub fn set_include_hidden(&mut self, include_hidden: bool) {
+        self.include_hidden = include_hidden;
+    }
 
-        let index_size = u64::from_be_bytes(index_size);
-        let _data_size = u64::from_be_bytes(data_size);
+    pub fn set_max_in_flight(&mut self, entries: usize) {
+        self.max_in_flight = entries;
+    }
 
-        let mut index_data = vec![0u8; index_size as usize];
+    pub fn set_min_savings(&mut self, fraction: Option<f64>) {
+        self.min_savings = fraction;
+    }
 
-        file.read(&mut index_data)?;
+    pub fn set_no_compress_extensions(&mut self, exts: &[&str]) {
+        self.no_compress_extensions = exts.iter().map(|ext| format!(".{}", ext.trim_start_matches('.').to_lowercase())).collect();
+    }
 
-        let index = index_from_bytes(&index_data)?;
+    pub fn set_solid(&mut self, solid: bool) {
+        self.solid = solid;
+    }
 
-        let data_pointer = file.stream_position()?;
+    pub fn set_block_size(&mut self, block_size: Option<u64>) {
+        self.block_size = block_size.filter(|&block_size| block_size > 0);
+    }
 
-        Ok(ResourceLibraryReader { file, index, data_pointer })
+    // 0 and 1 both mean tight packing, everything past this point can count on at least 1
+    pub fn set_alignment(&mut self, bytes: u64) {
+        self.alignment = bytes.max(1);
     }
 
-    pub fn read_file<'a>(&'a mut self, path: &str) -> Result<Box<[u8]>> {
-        let index = self.index.binary_search_by(|(file_path, _, _)| {
-            file_path[..].cmp(path)
-        }).map_err(|_| PathError::InvalidPath(path.to_owned()))?;
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.sync_policy = policy;
+    }
 
-        let index = &self.index[index];
-        
-        self.file.seek(std::io::SeekFrom::Start(self.data_pointer + index.1))?;
+    // Leaves room after the index so entries can be appended later without moving the data section
+    pub fn reserve_index_bytes(&mut self, bytes: u64) {
+        self.index_padding = bytes;
+    }
 
-        let mut buffer = vec![0u8; index.2 as usize];
-        self.file.read(&mut buffer)?;
+    // Number of threads reading upcoming entries while earlier ones are compressed, 0 reads everything on the calling thread
+    pub fn set_read_ahead(&mut self, threads: usize) {
+        self.read_ahead = threads;
+    }
 
-        let decompressed = lzma::decompress(&buffer)?;
-        
-        Ok(decompressed.into_boxed_slice())
+    // Adds a SHA-256 of every entry to the index, at 32 bytes per entry
+    #[cfg(feature = "hashes")]
+    pub fn set_store_hashes(&mut self, store_hashes: bool) {
+        self.store_hashes = store_hashes;
     }
 
-    pub fn get_all_files(&self) -> Box<[&str]> {
-        self.index.iter().map(|(path, _, _)| &path[..]).collect()
+    // Worth it for archives with many entries, where the index is large enough to slow down opening
+    pub fn set_compress_index(&mut self, compress_index: bool) {
+        self.compress_index = compress_index;
+    }
+
+    // Writing fails with CompactOverflow if a forced compact index can't hold the archive's offsets and sizes
+    pub fn set_offset_size(&mut self, offset_size: OffsetSize) {
+        self.offset_size = offset_size;
+    }
+
+    // Encrypts the index and data section, leaving only the header and comment readable without the key
+    #[cfg(feature = "encryption")]
+    pub fn set_encryption(&mut self, key: Option<ArchiveKey>) {
+        self.encryption = key;
+    }
+
+    // The key for entries that are encrypted on their own, see write_stream_encrypted and set_encrypted
+    #[cfg(feature = "encryption")]
+    pub fn set_entry_key(&mut self, key: Option<ArchiveKey>) {
+        self.entry_key = key;
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    // Ends the archive with a footer recording where it starts, for ResourceLibraryReader::open_embedded. Only sinks
+    // that can seek know where that is, so write_to_stream and write_to_volumes never add one. To append to a file,
+    // open it for writing and seek to the end, append mode can't seek back to rewrite the header.
+    pub fn set_embed_footer(&mut self, embed_footer: bool) {
+        self.embed_footer = embed_footer;
+    }
+
+    // Entries without an explicit content type get one guessed from their contents and extension
+    pub fn set_detect_content_types(&mut self, detect: bool) {
+        self.detect_content_types = detect;
+    }
+
+    // Stored in the header, so readers get it without looking at any entries
+    pub fn set_comment(&mut self, comment: &str) {
+        self.comment = Some(comment.to_owned());
+    }
+
+    pub fn clear_comment(&mut self) {
+        self.comment = None;
+    }
+
+    // Records which version of this library wrote the archive, along with the tool and when, next to the comment
+    pub fn set_creation_info(&mut self, record: bool) {
+        self.record_creation = record;
+    }
+
+    pub fn set_tool(&mut self, tool: Option<&str>) {
+        self.tool = tool.map(str::to_owned);
+    }
+
+    // Leaves out anything that would differ between two packs of the same inputs, which for now is the creation time
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    fn header_extras(&self) -> HeaderExtras {
+        let created = || SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|time| time.as_secs());
+        let creation_info = self.record_creation.then(|| CreationInfo {
+            library_version: env!("CARGO_PKG_VERSION").to_owned(),
+            tool: self.tool.clone(),
+            created: if self.deterministic { None } else { created() }
+        });
+
+        HeaderExtras { comment: self.comment.clone(), creation_info }
+    }
+
+    // Archives are always written in the newest format
+    pub fn format_version(&self) -> u16 {
+        FORMAT_VERSION
+    }
+
+    // Files added from disk that are at least this large are memory-mapped instead of read into memory
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap_threshold(&mut self, bytes: u64) {
+        self.mmap_threshold = bytes;
+    }
+
+    // Setting the token makes the current write stop with a Cancelled error between entries or chunks
+    pub fn set_cancel_token(&mut self, token: Option<Arc<AtomicBool>>) {
+        self.cancel = token;
+    }
+
+    fn is_no_compress(&self, path: &str) -> bool {
+        let path = path.to_lowercase();
+        self.no_compress_extensions.iter().any(|ext| path.ends_with(&ext[..]))
+    }
+
+    pub(crate) fn normalize_path(&self, path: String) -> Result<ResourcePath> {
+        match self.path_rules.apply(&path) {
+            Cow::Owned(normalized) => ResourcePath::new(normalized),
+            Cow::Borrowed(_) => ResourcePath::new(path)
+        }
+    }
+
+    // Same as normalize_path, for paths that are about to be added
+    fn new_path(&mut self, path: String) -> Result<ResourcePath> {
+        let normalized = self.normalize_path(path.clone())?;
+        self.path_policy.check(&normalized)?;
+
+        #[cfg(feature = "unicode")]
+        if self.path_rules.nfc {
+            let source = self.path_rules.separators.apply(&path).into_owned();
+            match self.source_names.get(&normalized) {
+                Some(existing) if *existing != source && (self.map.contains_key(&normalized) || self.aliases.contains_key(&normalized)) => {
+                    return Err(PathError::NormalizationCollision(existing.clone(), source, normalized.into()).into());
+                }
+                _ => self.source_names.insert(normalized.clone(), source)
+            };
+        }
+
+        Ok(normalized)
+    }
+
+    pub fn write_stream<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T) -> Result<()> {
+        self.insert_entry(path.into(), Entry::new(Box::new(stream)))
+    }
+
+    fn insert_entry(&mut self, path: String, entry: Entry) -> Result<()> {
+        let path = self.new_path(path)?;
+
+        if self.map.contains_key(&path) {
+            match self.duplicate_policy {
+                ConflictPolicy::KeepExisting => return Ok(()),
+                ConflictPolicy::Error => return Err(PathError::PathExists(path.into()).into()),
+                ConflictPolicy::Overwrite => ()
+            }
+        }
+
+        self.claim_case(&path)?;
+        self.map.insert(path, entry);
+
+        Ok(())
+    }
+
+    // The alias shares its target's data instead of storing it again. Targets are looked up when the archive is written,
+    // and an alias of another alias ends up pointing at the entry at the end of the chain.
+    pub fn add_alias(&mut self, alias: String, target: &str) -> Result<()> {
+        let alias = self.new_path(alias)?;
+        let target = self.normalize_path(target.to_owned())?;
+
+        if self.map.contains_key(&alias) || self.aliases.contains_key(&alias) {
+            return Err(PathError::PathExists(alias.into()).into());
+        }
+
+        self.claim_case(&alias)?;
+        self.aliases.insert(alias, target);
+
+        Ok(())
+    }
+
+    // Pairs each alias with the position of the entry it ends up pointing at
+    fn resolve_aliases(&self) -> Result<Vec<(ResourcePath, usize)>> {
+        let mut resolved = Vec::with_capacity(self.aliases.len());
+        for (alias, target) in &self.aliases {
+            if self.map.contains_key(alias) {
+                return Err(PathError::PathExists(alias.to_string()).into());
+            }
+
+            // A chain longer than the number of aliases has to come back around on itself
+            let mut target = target;
+            for _ in 0..self.aliases.len() {
+                match self.aliases.get(target) {
+                    Some(next) => target = next,
+                    None => break
+                }
+            }
+            if self.aliases.contains_key(target) {
+                return Err(ResourceLibraryError::AliasCycle(alias.to_string()));
+            }
+
+            let position = self.map.keys().position(|path| path == target).ok_or_else(|| ResourceLibraryError::DanglingAlias(alias.to_string(), target.to_string()))?;
+            resolved.push((alias.clone(), position));
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn write_stream_with_compression<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T, codec: impl Into<Codec>) -> Result<()> {
+        // The duplicate policy may keep an existing entry, which keeps its own settings
+        let codec = codec.into().validate()?;
+        self.insert_entry(path.into(), Entry { compression: Some(codec), ..Entry::new(Box::new(stream)) })
+    }
+
+    // Only the entry's data is encrypted, its path and other details in the index stay readable
+    #[cfg(feature = "encryption")]
+    pub fn write_stream_encrypted<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T) -> Result<()> {
+        self.insert_entry(path.into(), Entry { encrypted: true, ..Entry::new(Box::new(stream)) })
+    }
+
+    pub fn write_stream_new<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T) -> Result<()> {
+        let path = self.new_path(path.into())?;
+
+        if self.map.contains_key(&path) {
+            return Err(PathError::PathExists(path.into()).into());
+        }
+
+        self.claim_case(&path)?;
+        self.map.insert(path, Entry::new(Box::new(stream)));
+
+        Ok(())
+    }
+
+    pub fn replace_stream<T: Read + Seek + Debug + Send + 'static>(&mut self, path: impl Into<String>, stream: T) -> Result<Option<Box<dyn Resource>>> {
+        let path = self.new_path(path.into())?;
+        self.claim_case(&path)?;
+        Ok(self.map.insert(path, Entry::new(Box::new(stream))).map(|entry| entry.resource))
+    }
+
+    pub fn try_extend<I, S, T>(&mut self, iter: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+        T: Read + Seek + Debug + Send + 'static {
+        for (path, stream) in iter {
+            self.write_stream(path, stream)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn try_from_iter<I, S, T>(iter: I) -> Result<ResourceLibraryWriter>
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+        T: Read + Seek + Debug + Send + 'static {
+        let mut writer = ResourceLibraryWriter::new();
+        writer.try_extend(iter)?;
+
+        Ok(writer)
+    }
+
+    pub fn write_data(&mut self, path: impl Into<String>, data: impl Into<Box<[u8]>>) -> Result<()> {
+        self.write_stream(path, ByteStream::from(data.into()))
+    }
+
+    pub fn write_str(&mut self, path: impl Into<String>, data: &str) -> Result<()> {
+        self.write_data(path, data.as_bytes())
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, disk_path: P, archive_path: impl Into<String>) -> Result<()> {
+        let disk_path = disk_path.as_ref();
+
+        // fs::metadata follows symlinks, so a symlink loop shows up as an error here
+        let metadata = std::fs::metadata(disk_path).map_err(|err| ResourceLibraryError::DiskFileError(disk_path.to_owned(), err))?;
+        if !metadata.is_file() {
+            return Err(ResourceLibraryError::NotAFile(disk_path.to_owned()));
+        }
+
+        // Platforms that can't report a modification time, or times before the epoch, just leave it out
+        let mtime = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_secs());
+
+        self.insert_entry(archive_path.into(), Entry { mtime, mode: file_mode(&metadata), ..Entry::from_disk(disk_path.to_owned()) })
+    }
+
+    pub fn add_dir<P: AsRef<Path>>(&mut self, root: P, archive_prefix: impl AsRef<str>) -> Result<usize> {
+        let root = root.as_ref();
+        let archive_prefix = archive_prefix.as_ref();
+        let mut files = Vec::new();
+        let mut ancestors = Vec::new();
+        self.walk_dir(root, &mut ancestors, &mut files)?;
+
+        let mut entries = Vec::with_capacity(files.len());
+        for disk_path in files {
+            let relative = disk_path.strip_prefix(root).unwrap();
+
+            let mut archive_path = archive_prefix.to_owned();
+            for component in relative.components() {
+                let component = component.as_os_str().to_str().ok_or_else(|| ResourceLibraryError::NonUnicodePath(disk_path.clone()))?;
+                if !archive_path.is_empty() && !archive_path.ends_with('/') {
+                    archive_path.push('/');
+                }
+                archive_path.push_str(component);
+            }
+
+            // Validate everything before adding anything so a bad name doesn't leave a partial directory behind
+            let archive_path = self.new_path(archive_path)?;
+            if self.duplicate_policy == ConflictPolicy::Error && self.map.contains_key(&archive_path) {
+                return Err(PathError::PathExists(archive_path.into()).into());
+            }
+
+            entries.push((disk_path, archive_path));
+        }
+
+        self.check_case_collisions(self.map.keys().chain(self.aliases.keys()).chain(entries.iter().map(|(_, path)| path)).map(|path| &path[..]))?;
+
+        // Only the files that were actually added are counted, paths kept under KeepExisting aren't
+        let mut count = 0;
+        for (disk_path, archive_path) in entries {
+            if self.duplicate_policy == ConflictPolicy::KeepExisting && self.map.contains_key(&archive_path) {
+                continue;
+            }
+
+            self.add_file(disk_path, archive_path)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn walk_dir(&self, dir: &Path, ancestors: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) -> Result<()> {
+        let disk_error = |path: &Path| { let path = path.to_owned(); move |err| ResourceLibraryError::DiskFileError(path, err) };
+
+        // Directory symlinks that lead back to one of their own ancestors would recurse forever
+        let canonical = dir.canonicalize().map_err(disk_error(dir))?;
+        if ancestors.contains(&canonical) {
+            return Ok(());
+        }
+        ancestors.push(canonical);
+
+        for entry in std::fs::read_dir(dir).map_err(disk_error(dir))? {
+            let entry = entry.map_err(disk_error(dir))?;
+            let path = entry.path();
+
+            if !self.include_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let mut file_type = entry.file_type().map_err(disk_error(&path))?;
+            if file_type.is_symlink() {
+                if !self.follow_symlinks {
+                    continue;
+                }
+
+                file_type = std::fs::metadata(&path).map_err(disk_error(&path))?.file_type();
+            }
+
+            if file_type.is_dir() {
+                self.walk_dir(&path, ancestors, files)?;
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+
+        ancestors.pop();
+
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: ResourceLibraryWriter, on_conflict: ConflictPolicy) -> Result<MergeReport> {
+        if on_conflict == ConflictPolicy::Error {
+            if let Some(path) = other.map.keys().find(|path| self.map.contains_key(*path)).or_else(|| other.aliases.keys().find(|alias| self.aliases.contains_key(*alias))) {
+                return Err(PathError::PathExists(path.to_string()).into());
+            }
+        }
+
+        self.check_case_collisions(self.map.keys().chain(self.aliases.keys()).chain(other.map.keys()).chain(other.aliases.keys()).map(|path| &path[..]))?;
+
+        // Aliases follow the same policy, but only entries show up in the report
+        for (alias, target) in other.aliases {
+            if on_conflict == ConflictPolicy::Overwrite || !self.aliases.contains_key(&alias) {
+                self.aliases.insert(alias, target);
+            }
+        }
+
+        let mut report = MergeReport::default();
+        for (path, entry) in other.map {
+            if !self.map.contains_key(&path) {
+                self.map.insert(path, entry);
+            } else if on_conflict == ConflictPolicy::Overwrite {
+                self.map.insert(path.clone(), entry);
+                report.replaced.push(path.into());
+            } else {
+                report.skipped.push(path.into());
+            }
+        }
+        self.rebuild_case_folds();
+
+        Ok(report)
+    }
+
+    pub fn set_compression(&mut self, path: impl AsRef<str>, codec: impl Into<Codec>) -> Result<()> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let entry = self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+        entry.compression = Some(codec.into().validate()?);
+
+        Ok(())
+    }
+
+    pub fn set_compression_for_prefix(&mut self, prefix: impl AsRef<str>, codec: impl Into<Codec>) -> Result<usize> {
+        let codec = codec.into().validate()?;
+        let paths = self.keys_with_prefix(&self.path_rules.apply(prefix.as_ref()));
+
+        for path in &paths {
+            self.map.get_mut(path).unwrap().compression = Some(codec);
+        }
+
+        Ok(paths.len())
+    }
+
+    pub fn get_compression(&self, path: impl AsRef<str>) -> Option<Codec> {
+        self.map.get(&*self.path_rules.apply(path.as_ref())).and_then(|entry| entry.compression)
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn set_encrypted(&mut self, path: impl AsRef<str>, encrypted: bool) -> Result<()> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let entry = self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+        entry.encrypted = encrypted;
+
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self, path: impl AsRef<str>) -> bool {
+        self.map.get(&*self.path_rules.apply(path.as_ref())).is_some_and(|entry| entry.encrypted)
+    }
+
+    // Time is in seconds since the unix epoch
+    pub fn set_mtime(&mut self, path: impl AsRef<str>, time: u64) -> Result<()> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let entry = self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+        entry.mtime = Some(time);
+
+        Ok(())
+    }
+
+    pub fn get_mtime(&self, path: impl AsRef<str>) -> Option<u64> {
+        self.map.get(&*self.path_rules.apply(path.as_ref())).and_then(|entry| entry.mtime)
+    }
+
+    pub fn set_content_type(&mut self, path: impl AsRef<str>, content_type: &str) -> Result<()> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let entry = self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+        entry.content_type = Some(content_type.to_owned());
+
+        Ok(())
+    }
+
+    pub fn get_content_type(&self, path: impl AsRef<str>) -> Option<&str> {
+        self.map.get(&*self.path_rules.apply(path.as_ref())).and_then(|entry| entry.content_type.as_deref())
+    }
+
+    // Keys follow the same rules as paths. Setting a key again replaces its value.
+    pub fn set_metadata(&mut self, path: impl AsRef<str>, key: &str, value: &[u8]) -> Result<()> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let key = verify_str(key)?;
+        let entry = self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+
+        let size = entry.metadata.iter().filter(|(existing, _)| *existing != key).map(|(key, value)| key.len() + value.len()).sum::<usize>() + key.len() + value.len();
+        if size > MAX_ENTRY_METADATA {
+            return Err(ResourceLibraryError::MetadataTooLarge(path.to_owned(), size));
+        }
+
+        entry.metadata.insert(key.to_owned(), value.into());
+
+        Ok(())
+    }
+
+    pub fn get_metadata(&self, path: impl AsRef<str>) -> Option<&BTreeMap<String, Box<[u8]>>> {
+        self.map.get(&*self.path_rules.apply(path.as_ref())).map(|entry| &entry.metadata).filter(|metadata| !metadata.is_empty())
+    }
+
+    // Borrows the stream stored at `path` without reading it. The stream is left wherever the caller
+    // seeks it to; read_data and write_to_file always rewind before reading, so there's no need to restore it.
+    pub fn get_stream(&mut self, path: impl AsRef<str>) -> Result<&mut (dyn Resource + 'static)> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        match self.map.get_mut(verify_str(path)?) {
+            Some(entry) => Ok(&mut *entry.resource),
+            None => Err(PathError::InvalidPath(path.to_owned()).into())
+        }
+    }
+
+    pub fn read_data(&mut self, path: impl AsRef<str>) -> Result<Box<[u8]>> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        match self.map.get_mut(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()).into()) {
+            Ok(Entry { resource, .. }) => {
+                let mut bytes = Vec::new();
+                resource.rewind()?;
+                resource.read_to_end(&mut bytes)?;
+    
+                Ok(bytes.into_boxed_slice())
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    pub fn take_data(&mut self, path: impl AsRef<str>) -> Result<Box<[u8]>> {
+        let path = path.as_ref();
+        let mut resource = self.take_stream(path)?;
+
+        let mut bytes = Vec::new();
+        resource.rewind()?;
+        resource.read_to_end(&mut bytes)?;
+
+        Ok(bytes.into_boxed_slice())
+    }
+
+    pub fn take_stream(&mut self, path: impl AsRef<str>) -> Result<Box<dyn Resource>> {
+        let path = path.as_ref();
+        self.remove_file(path)
+    }
+
+    // Aliases that lead to the removed entry are removed along with it
+    pub fn remove_file(&mut self, path: impl AsRef<str>) -> Result<Box<dyn Resource>> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        let (path, entry) = self.map.remove_entry(verify_str(path)?).ok_or(PathError::InvalidPath(path.to_owned()))?;
+        self.remove_aliases_to(vec![path]);
+        self.rebuild_case_folds();
+
+        Ok(entry.resource)
+    }
+
+    // Aliases under the prefix are removed too, but only the entries are counted
+    pub fn remove_prefix(&mut self, prefix: impl AsRef<str>) -> usize {
+        let prefix = &*self.path_rules.apply(prefix.as_ref());
+        let mut paths = self.keys_with_prefix(prefix);
+        let count = paths.len();
+
+        for path in &paths {
+            self.map.remove(path);
+        }
+        let aliases: Vec<_> = paths_under(&self.aliases, prefix).cloned().collect();
+        for alias in &aliases {
+            self.aliases.remove(alias);
+        }
+
+        paths.extend(aliases);
+        paths.sort();
+        self.remove_aliases_to(paths);
+        self.rebuild_case_folds();
+
+        count
+    }
+
+    // Takes sorted paths, and keeps going through aliases of the aliases it removes until the whole chain is gone
+    fn remove_aliases_to(&mut self, mut removed: Vec<ResourcePath>) {
+        while !removed.is_empty() {
+            let dangling: Vec<_> = self.aliases.iter().filter(|(_, target)| removed.binary_search(*target).is_ok()).map(|(alias, _)| alias.clone()).collect();
+            for alias in &dangling {
+                self.aliases.remove(alias);
+            }
+
+            removed = dangling;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.aliases.clear();
+        self.case_folds.clear();
+    }
+
+    pub fn clear_prefix(&mut self, prefix: impl AsRef<str>) -> usize {
+        self.remove_prefix(prefix)
+    }
+
+    pub fn rename_file(&mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Result<()> {
+        let to = self.normalize_path(to.as_ref().to_owned())?;
+        self.path_policy.check(&to)?;
+        if self.map.contains_key(&to) || self.aliases.contains_key(&to) {
+            return Err(PathError::PathExists(to.into()).into());
+        }
+
+        let from = &*self.path_rules.apply(from.as_ref());
+        let from = verify_str(from)?;
+        if !self.map.contains_key(from) {
+            return Err(PathError::InvalidPath(from.to_owned()).into());
+        }
+        self.check_case_collisions(self.map.keys().chain(self.aliases.keys()).filter(|path| *path != from).map(|path| &path[..]).chain([&to[..]]))?;
+
+        // Aliases follow the entry to its new path
+        for target in self.aliases.values_mut().filter(|target| **target == from) {
+            *target = to.clone();
+        }
+
+        let entry = self.map.remove(from).unwrap();
+        self.map.insert(to, entry);
+        self.rebuild_case_folds();
+
+        Ok(())
+    }
+
+    pub fn rename_prefix(&mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Result<usize> {
+        let from = &*self.path_rules.apply(from.as_ref());
+        let to = &*self.path_rules.apply(to.as_ref());
+        let to = verify_str(to)?;
+        let paths = self.keys_with_prefix(from);
+
+        // Prefixes match on components, so the part after the prefix is re-joined to the new one with a single slash
+        let from_dir = from.strip_suffix('/').unwrap_or(from);
+        let to_dir = to.strip_suffix('/').unwrap_or(to);
+        let renamed = |path: &ResourcePath| {
+            let rest = &path[from_dir.len()..];
+            let rest = rest.strip_prefix('/').unwrap_or(rest);
+            match (to_dir, rest) {
+                ("", rest) => ResourcePath::new(rest),
+                (to_dir, "") => ResourcePath::new(to_dir),
+                (to_dir, rest) => ResourcePath::new(format!("{}/{}", to_dir, rest))
+            }
+        };
+
+        // Check every destination up front so a bad path or a collision doesn't leave the map half renamed
+        let new_paths = paths.iter().map(renamed).collect::<Result<Vec<_>>>()?;
+        for new_path in &new_paths {
+            self.path_policy.check(new_path)?;
+            if (self.map.contains_key(new_path) && paths.binary_search(new_path).is_err()) || self.aliases.contains_key(new_path) {
+                return Err(PathError::PathExists(new_path.to_string()).into());
+            }
+        }
+        self.check_case_collisions(self.map.keys().filter(|path| paths.binary_search(path).is_err()).chain(self.aliases.keys()).chain(&new_paths).map(|path| &path[..]))?;
+
+        for target in self.aliases.values_mut() {
+            if let Ok(n) = paths.binary_search(target) {
+                *target = new_paths[n].clone();
+            }
+        }
+
+        let entries: Vec<_> = paths.iter().map(|path| self.map.remove(path).unwrap()).collect();
+        for (new_path, entry) in new_paths.into_iter().zip(entries) {
+            self.map.insert(new_path, entry);
+        }
+        self.rebuild_case_folds();
+
+        Ok(paths.len())
+    }
+
+    // The root is "". Each subdirectory is listed once, then its keys are skipped over with a new range
+    // instead of walking everything under it.
+    pub fn list_dir(&self, dir: &str) -> Vec<DirEntry> {
+        let dir = &*self.path_rules.apply(dir);
+        let dir = dir.strip_suffix('/').unwrap_or(dir);
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+
+        let mut entries = Vec::new();
+        let mut lower = Bound::Included(prefix.clone());
+        while let Some((path, _)) = self.map.range::<str, _>((lower.as_ref().map(|s| &s[..]), Bound::Unbounded)).next() {
+            let Some(rest) = path.strip_prefix(&prefix[..]) else {
+                break;
+            };
+
+            match rest.split_once('/') {
+                Some((name, _)) => {
+                    entries.push(DirEntry::Dir(name.to_string()));
+                    // '0' sorts right after '/', so this is the first key past name/
+                    lower = Bound::Included(format!("{}{}0", prefix, name));
+                },
+                None => {
+                    entries.push(DirEntry::File(rest.to_string()));
+                    lower = Bound::Excluded(path.to_string());
+                }
+            }
+        }
+
+        entries
+    }
+
+    pub fn glob(&self, pattern: &str) -> Result<Vec<&str>> {
+        Ok(self.glob_with(&Glob::new(pattern)?))
+    }
+
+    pub fn glob_with(&self, glob: &Glob) -> Vec<&str> {
+        let prefix = glob.literal_prefix();
+        self.map.range::<str, _>((Bound::Included(&prefix[..]), Bound::Unbounded))
+            .map(|(path, _)| &path[..])
+            .take_while(|path| path.starts_with(&prefix[..]))
+            .filter(|path| glob.is_match(path))
+            .collect()
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<ResourcePath> {
+        self.prefix_range(prefix).cloned().collect()
+    }
+
+    fn prefix_range(&self, prefix: &str) -> impl Iterator<Item = &ResourcePath> {
+        paths_under(&self.map, prefix)
+    }
+
+    // There's no path to go with the file, so FsyncFileAndDir can only sync the file itself
+    pub fn write_to_file(&mut self, mut file: File, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let report = self.write_to(&mut file, codec)?;
+
+        if self.sync_policy >= SyncPolicy::FsyncFile {
+            file.sync_all()?;
+        }
+
+        Ok(report)
+    }
+
+    pub fn write_to_path<P: AsRef<Path>>(&mut self, path: P, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let path = path.as_ref();
+        let report = self.write_to_disk(path, codec.into(), self.sync_policy)?;
+
+        if self.sync_policy == SyncPolicy::FsyncFileAndDir {
+            sync_parent_dir(path)?;
+        }
+
+        Ok(report)
+    }
+
+    pub fn write_to_path_atomic<P: AsRef<Path>>(&mut self, path: P, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let path = path.as_ref();
+        let temp_path = temp_path(path)?;
+
+        // The temp file always has to be on disk before it replaces the destination.
+        // rename replaces an existing destination on both Unix and Windows.
+        let result = self.write_to_disk(&temp_path, codec.into(), SyncPolicy::FsyncFile).and_then(|report| {
+            std::fs::rename(&temp_path, path).map_err(|err| ResourceLibraryError::DiskFileError(path.to_owned(), err))?;
+            Ok(report)
+        });
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+
+        // Makes the rename itself durable
+        if result.is_ok() && self.sync_policy == SyncPolicy::FsyncFileAndDir {
+            sync_parent_dir(path)?;
+        }
+
+        result
+    }
+
+    // Emits {path}.001, {path}.002, ... with no volume larger than max_volume_size. The first volume holds
+    // the header and index, and every entry's data is kept whole inside a single volume.
+    pub fn write_to_volumes<P: AsRef<Path>>(&mut self, path: P, codec: impl Into<Codec>, max_volume_size: u64) -> Result<WriteReport> {
+        let path = path.as_ref();
+        if self.encryption.is_some() {
+            return Err(ResourceLibraryError::EncryptedVolumes);
+        }
+
+        // Compressed sizes aren't known up front, so the archive is packed as usual and then split.
+        // The first volume keeps the packed archive's data offsets, which only holds with the index first.
+        let temp_path = temp_path(path)?;
+        let layout = std::mem::replace(&mut self.layout, Layout::IndexFirst);
+        let result = self.write_to_disk(&temp_path, codec.into(), SyncPolicy::Flush).and_then(|report| {
+            self.split_volumes(&temp_path, path, max_volume_size)?;
+            Ok(report)
+        });
+        self.layout = layout;
+
+        let _ = std::fs::remove_file(&temp_path);
+        result
+    }
+
+    fn split_volumes(&self, source_path: &Path, path: &Path, max_volume_size: u64) -> Result<()> {
+        let mut source = File::open(source_path)?;
+        let header = read_header(&mut source)?;
+        let mut index = header.index.into_vec();
+
+        if header.data_pointer > max_volume_size {
+            return Err(ResourceLibraryError::VolumeTooSmall("The index".to_owned(), header.data_pointer, max_volume_size));
+        }
+
+        // Maps each blob's offset in the packed archive to its length, volume, and offset within that volume.
+        // A solid archive is one big blob.
+        let mut blobs = BTreeMap::new();
+        match header.flags & FLAG_SOLID {
+            0 => index.iter().for_each(|entry| { blobs.insert(entry.offset, (entry.compressed_len, 0, 0)); }),
+            _ => { blobs.insert(0, (header.data_size, 0, 0)); }
+        }
+
+        // Offsets count from the start of the data section in the first volume, and from the start of the file in the rest
+        let mut volume = 0;
+        let mut used = header.data_pointer;
+        for (offset, (len, blob_volume, blob_offset)) in &mut blobs {
+            if *len > max_volume_size {
+                let path = index.iter().find(|entry| entry.offset == *offset).map_or_else(String::new, |entry| entry.path.to_string());
+                return Err(ResourceLibraryError::VolumeTooSmall(format!("Entry {}", path), *len, max_volume_size));
+            }
+
+            let mut start = match header.flags & FLAG_SOLID {
+                0 => used.next_multiple_of(self.alignment.max(1)),
+                _ => used
+            };
+            if start + *len > max_volume_size {
+                volume += 1;
+                start = 0;
+            }
+
+            *blob_volume = volume;
+            *blob_offset = if volume == 0 { start - header.data_pointer } else { start };
+            used = start + *len;
+        }
+
+        for entry in &mut index {
+            let (_, blob_volume, blob_offset) = match header.flags & FLAG_SOLID {
+                0 => blobs[&entry.offset],
+                _ => blobs[&0]
+            };
+
+            entry.volume = blob_volume;
+            if header.flags & FLAG_SOLID == 0 {
+                entry.offset = blob_offset;
+            }
+        }
+
+        let index_data = entries_to_bytes(&index, header.flags & FLAG_COMPACT_OFFSETS != 0)?;
+
+        let mut sinks = Vec::new();
+        for volume in 0..=volume {
+            let path = volume_path(path, volume);
+            let file = File::create(&path).map_err(|err| ResourceLibraryError::DiskFileError(path.clone(), err))?;
+            sinks.push((path, BufWriter::new(file), 0));
+        }
+
+        // The index has to fit in the same space as before, since the first volume keeps the data where it was
+        let space = header.data_pointer - index_start(&header.extras);
+        let (index_data, flags) = encode_index(&index_data, header.flags, Some(space))?;
+        // Only the data is copied over, so the volumes have no fingerprint
+        write_header(&mut sinks[0].1, &index_data, space - index_data.len() as u64, header.data_size, (flags | FLAG_VOLUMES) & !FLAG_FINGERPRINT, &header.extras)?;
+        sinks[0].2 = header.data_pointer;
+
+        for (offset, (len, volume, volume_offset)) in blobs {
+            let (path, sink, position) = &mut sinks[volume as usize];
+            let start = if volume == 0 { header.data_pointer + volume_offset } else { volume_offset };
+            let disk_error = |err| ResourceLibraryError::DiskFileError(path.clone(), err);
+
+            // Padding for alignment
+            sink.write_all(&vec![0u8; (start - *position) as usize]).map_err(disk_error)?;
+
+            source.seek(SeekFrom::Start(header.data_pointer + offset))?;
+            if std::io::copy(&mut (&mut source).take(len), sink).map_err(disk_error)? != len {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+
+            *position = start + len;
+        }
+
+        for (path, mut sink, _) in sinks {
+            let disk_error = |err| ResourceLibraryError::DiskFileError(path.clone(), err);
+            sink.flush().map_err(disk_error)?;
+
+            if self.sync_policy >= SyncPolicy::FsyncFile {
+                sink.get_ref().sync_all().map_err(disk_error)?;
+            }
+        }
+
+        if self.sync_policy == SyncPolicy::FsyncFileAndDir {
+            sync_parent_dir(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_to_disk(&mut self, path: &Path, codec: Codec, policy: SyncPolicy) -> Result<WriteReport> {
+        let disk_error = |err| ResourceLibraryError::DiskFileError(path.to_owned(), err);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(disk_error)?;
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(disk_error)?;
+
+        let mut writer = BufWriter::new(file);
+        let report = self.write_to(&mut writer, codec).map_err(|err| match err {
+            ResourceLibraryError::IoError(err) => disk_error(err),
+            err => err
+        })?;
+
+        // The buffer has to be emptied regardless of the policy, or errors would be lost when it's dropped
+        writer.flush().map_err(disk_error)?;
+
+        if policy >= SyncPolicy::FsyncFile {
+            writer.get_ref().sync_all().map_err(disk_error)?;
+        }
+
+        Ok(report)
+    }
+
+    pub fn write_to_vec(&mut self, codec: impl Into<Codec>) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.write_to(&mut cursor, codec)?;
+
+        Ok(cursor.into_inner())
+    }
+
+    pub fn write_to<W: Write + Seek>(&mut self, sink: &mut W, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let archive_start = sink.stream_position()?;
+        let cipher = self.encryption.as_ref().map(ArchiveCipher::new);
+        let extras = self.header_extras();
+        let (mut report, index_data, index_space, flags, data_digest) = self.write_archive(sink, codec.into(), self.layout, cipher.as_ref(), &extras)?;
+
+        if self.layout == Layout::IndexFirst {
+            let mut end = sink.stream_position()?;
+
+            // Rewrite the header with the final data length and index. A compressed index leaves the rest of its space as padding.
+            let overhead = if cipher.is_some() { INDEX_OVERHEAD } else { 0 };
+            let space = index_space + self.index_padding + overhead;
+            let (index_data, flags) = encode_index(&index_data, flags, Some(space - overhead))?;
+            let index_data = seal_index(index_data, cipher.as_ref())?;
+            report.index_size = index_data.len() as u64;
+
+            let fingerprint = archive_fingerprint(&data_digest, &blake3::hash(&index_data));
+            sink.write_all(&fingerprint)?;
+            report.fingerprint = Some(fingerprint);
+            end += FINGERPRINT_LEN;
+
+            sink.seek(SeekFrom::Start(archive_start))?;
+            write_header(sink, &index_data, space - index_data.len() as u64, report.compressed_size, flags, &extras)?;
+
+            // Leave the sink positioned after the archive
+            sink.seek(SeekFrom::Start(end))?;
+        }
+
+        if self.embed_footer {
+            sink.write_all(&EMBED_FOOTER_BYTES)?;
+            sink.write_all(&archive_start.to_be_bytes())?;
+        }
+
+        if self.sync_policy >= SyncPolicy::Flush {
+            sink.flush()?;
+        }
+
+        Ok(report)
+    }
+
+    // Sinks that can't seek, like pipes and sockets, always get the index at the end
+    pub fn write_to_stream<W: Write>(&mut self, sink: &mut W, codec: impl Into<Codec>) -> Result<WriteReport> {
+        let cipher = self.encryption.as_ref().map(ArchiveCipher::new);
+        let (report, _, _, _, _) = self.write_archive(sink, codec.into(), Layout::IndexLast, cipher.as_ref(), &self.header_extras())?;
+
+        if self.sync_policy >= SyncPolicy::Flush {
+            sink.flush()?;
+        }
+
+        Ok(report)
+    }
+
+    // Writes everything but the final index-first header and fingerprint, returning the finished index, the space the
+    // placeholder index took up, the header flags and the digest of the data section
+    fn write_archive<W: Write>(&mut self, sink: &mut W, codec: Codec, layout: Layout, cipher: Option<&ArchiveCipher>, extras: &HeaderExtras) -> Result<(WriteReport, Box<[u8]>, u64, u16, blake3::Hash)> {
+        // Create index template
+
+        // Create index buffer
+        let mut index = Vec::new();
+        // Since map is a tree map, iterator will be in order, sorted by filename
+        // Catch bad levels before anything is written
+        let default_codec = codec.validate()?;
+        let mut codecs = Vec::with_capacity(self.map.len());
+        let aliases = self.resolve_aliases()?;
+        let case_collisions = match self.case_collisions {
+            CaseCollisions::Allow => Vec::new(),
+            _ => find_case_collisions(self.map.keys().chain(self.aliases.keys()).map(|path| &path[..]))
+        };
+        if let (CaseCollisions::Forbid, Some((first, second))) = (self.case_collisions, case_collisions.first()) {
+            return Err(PathError::CaseCollision(first.clone(), second.clone()).into());
+        }
+
+        // Files added from disk take their size from metadata, so none of them are opened before they're written
+        let sizes = self.map.values_mut().map(|entry| entry.resource.seek(SeekFrom::End(0))).collect::<std::io::Result<Vec<_>>>()?;
+
+        // Solid archives compress everything as one block, so there's no entry data to seal on its own
+        let entry_cipher = match self.map.iter().find(|(_, entry)| entry.encrypted) {
+            Some((path, _)) if self.solid => return Err(ResourceLibraryError::SolidEncryptedEntry(path.to_string())),
+            Some((path, _)) => Some(EntryCipher::new(self.entry_key.as_ref().ok_or_else(|| ResourceLibraryError::MissingKey(path.to_string()))?)),
+            None => None
+        };
+
+        // Entries without a content type of their own are sniffed from their first bytes as they're written. Until then
+        // they hold space in the index for the longest type that could be detected.
+        let unsniffed = "\0".repeat(max_content_type_len());
+        let sniff = self.map.values().map(|entry| entry.content_type.is_none() && self.detect_content_types).collect::<Vec<_>>();
+
+        // Hashes are filled in later, but they have to take up their space in the index from the start
+        let sha256 = if self.store_hashes { vec![0u8; 32] } else { Vec::new() };
+        for (((filename, entry), &size), &sniff) in self.map.iter().zip(&sizes).zip(&sniff) {
+            // Prefer the entry's own codec if it has one, unless everything shares one solid block
+            let codec = match entry.compression {
+                _ if self.solid => default_codec,
+                Some(codec) => codec,
+                None if self.is_no_compress(filename) => Codec::Stored,
+                None => default_codec
+            };
+
+            if !self.codecs.contains(codec) {
+                return Err(ResourceLibraryError::UnknownCodec(codec.id()));
+            }
+
+            // Only entries that span more than one block are worth chunking. Encrypted entries are always sealed in one piece.
+            let block_size = match self.block_size {
+                Some(block_size) if !self.solid && !entry.encrypted && codec.id() != Codec::Stored.id() && size > block_size => block_size,
+                _ => 0
+            };
+            let (blocks, mut flags) = match block_size {
+                0 => (Vec::new(), 0),
+                block_size => (vec![u64::MAX; size.div_ceil(block_size) as usize], ENTRY_CHUNKED)
+            };
+            if entry.encrypted {
+                flags |= ENTRY_ENCRYPTED;
+            }
+
+            let content_type = match &entry.content_type {
+                Some(content_type) => content_type.clone(),
+                None if sniff => unsniffed.clone(),
+                None => String::new()
+            };
+
+            // Write placeholders to be replaced later
+            index.push(IndexEntry { path: filename.clone(), offset: u64::MAX, compressed_len: u64::MAX, codec: codec.id(), block_size, blocks, volume: 0, original_len: size, crc32: NO_CRC, sha256: sha256.clone(), mtime: entry.mtime.unwrap_or(NO_MTIME), mode: entry.mode.map_or(NO_MODE, u64::from), metadata: entry.metadata.clone(), content_type, flags, target: String::new() });
+            codecs.push(codec);
+        }
+
+        // Leave plenty of room in a compact index for compression making entries larger
+        let total = sizes.iter().sum::<u64>() + sizes.len() as u64 * self.alignment;
+        let compact = match self.offset_size {
+            OffsetSize::Auto => total <= u32::MAX as u64 / 2,
+            OffsetSize::Compact => true,
+            OffsetSize::Full => false
+        };
+
+        // Anything that doesn't fit, like a timestamp past 2106, already shows up in the placeholder index
+        let entries = with_aliases(&index, &aliases);
+        let (index_data, compact) = match entries_to_bytes(&entries, compact) {
+            Err(SerializationError::CompactOverflow(_)) if self.offset_size == OffsetSize::Auto => (entries_to_bytes(&entries, false)?, false),
+            result => (result?, compact)
+        };
+        // Sniffed content types can only make the final index smaller, and the rest of the space becomes padding
+        let index_space = index_data.len() as u64;
+
+        // With the index first, a placeholder header, index and the space reserved for the index to grow into are written.
+        // The data length, index and checksum are filled in once everything has been written.
+        let mut flags = FLAG_FINGERPRINT | if self.solid { FLAG_SOLID } else { 0 };
+        if self.compress_index {
+            flags |= FLAG_INDEX_COMPRESSED;
+        }
+        if compact {
+            flags |= FLAG_COMPACT_OFFSETS;
+        }
+        if cipher.is_some() {
+            flags |= FLAG_ENCRYPTED;
+        }
+
+        // An encrypted archive's placeholder is blank so that nothing in the index is ever on disk unencrypted
+        let start = match (layout, cipher) {
+            (Layout::IndexFirst, None) => {
+                write_header(sink, &index_data, self.index_padding, 0, flags & !FLAG_INDEX_COMPRESSED, extras)?;
+                index_start(extras) + index_data.len() as u64 + self.index_padding
+            },
+            (Layout::IndexFirst, Some(_)) => {
+                let placeholder = vec![0u8; index_data.len() + INDEX_OVERHEAD as usize];
+                write_header(sink, &placeholder, self.index_padding, 0, flags & !FLAG_INDEX_COMPRESSED, extras)?;
+                index_start(extras) + placeholder.len() as u64 + self.index_padding
+            },
+            (Layout::IndexLast, _) => {
+                write_fixed_header(sink, &[], 0, 0, flags | FLAG_INDEX_LAST, extras)?;
+                index_start(extras)
+            }
+        };
+
+        let mut data = DataSection::new(&mut *sink, start, self.alignment, cipher, entry_cipher.as_ref());
+        let mut i = 0;
+        let mut jobs = Vec::new();
+        let max_in_flight = self.max_in_flight.max(1);
+        let mut report = WriteReport { index_size: index_data.len() as u64, case_collisions, ..Default::default() };
+        let mut solid_block = Vec::new();
+        let cancel = self.cancel.clone();
+
+        // Entries that get read into memory in one go anyway can be fetched ahead of time by reader threads.
+        // Chunked and streamed entries are read as they're written to keep memory bounded.
+        let mut sources = Vec::with_capacity(self.map.len());
+        let mut prefetch = Vec::new();
+        for (n, entry) in self.map.values_mut().enumerate() {
+            #[cfg(feature = "mmap")]
+            if let Some(mapped) = entry.disk_path.as_deref().filter(|_| sizes[n] >= self.mmap_threshold).and_then(|path| map_file(path, sizes[n])) {
+                sources.push(EntrySource::Mapped(Cursor::new(mapped)));
+                continue;
+            }
+
+            let streamed = sizes[n] > STREAM_CHUNK_SIZE as u64 && codecs[n].is_streamable() && index[n].flags & ENTRY_ENCRYPTED == 0;
+            if self.read_ahead > 0 && (self.solid || (index[n].block_size == 0 && !streamed)) {
+                prefetch.push((n, &mut entry.resource));
+                sources.push(EntrySource::Prefetched);
+            } else {
+                sources.push(EntrySource::Resource(&mut entry.resource));
+            }
+        }
+
+        // Each reader takes a ticket before fetching an entry and the ticket is handed back once the entry is written,
+        // which caps how many fetched entries can be waiting in memory
+        let depth = self.read_ahead * 2;
+        let (ticket_sender, tickets) = sync_channel(depth);
+        let (result_sender, results) = sync_channel(depth);
+        let tickets = Mutex::new(tickets);
+        let queue = Mutex::new(prefetch.into_iter());
+
+        std::thread::scope(|scope| -> Result<()> {
+            // The channels are moved in so that returning early disconnects them and lets the readers exit
+            let (ticket_sender, result_sender, results) = (ticket_sender, result_sender, results);
+            for _ in 0..depth {
+                ticket_sender.send(()).unwrap();
+            }
+
+            for _ in 0..self.read_ahead {
+                let (tickets, queue, result_sender) = (&tickets, &queue, result_sender.clone());
+                scope.spawn(move || {
+                    while tickets.lock().unwrap().recv().is_ok() {
+                        let Some((n, resource)) = queue.lock().unwrap().next() else { break };
+
+                        let mut buffer = Vec::new();
+                        let result = resource.rewind().and_then(|_| resource.read_to_end(&mut buffer)).map(|_| buffer);
+                        if result_sender.send((n, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(result_sender);
+
+            // Readers can finish out of order, so results wait here until their turn
+            let mut fetched = BTreeMap::new();
+            let mut read_entry = |n: usize, source: EntrySource| -> Result<EntryData> {
+                match source {
+                    EntrySource::Resource(resource) => {
+                        let mut buffer = Vec::new();
+                        resource.read_to_end(&mut buffer)?;
+                        return Ok(EntryData::Buffered(buffer));
+                    },
+                    #[cfg(feature = "mmap")]
+                    EntrySource::Mapped(mapped) => return Ok(EntryData::Mapped(mapped.into_inner())),
+                    EntrySource::Prefetched => ()
+                }
+
+                loop {
+                    if let Some(result) = fetched.remove(&n) {
+                        let _ = ticket_sender.send(());
+                        return Ok(EntryData::Buffered(result?));
+                    }
+
+                    let (k, result) = results.recv().map_err(|_| std::io::Error::other("reader thread stopped"))?;
+                    fetched.insert(k, result);
+                }
+            };
+
+            let sniffed = |path: &str, head: &[u8]| detect_content_type(path, head).unwrap_or_default().to_owned();
+
+            // Since map is a tree map, entries are in order, sorted by filename
+            for (n, (mut source, (&codec, &size))) in sources.into_iter().zip(codecs.iter().zip(&sizes)).enumerate() {
+                check_cancelled(cancel.as_deref())?;
+
+                // Compressed sizes are filled in from the index once everything is written
+                report.entries.push(EntryReport { path: index[n].path.to_string(), original_size: size, compressed_size: 0, codec });
+                report.original_size += size;
+
+                if let EntrySource::Resource(resource) = &mut source {
+                    resource.rewind()?;
+                }
+
+                // Solid archives collect everything into one block, so offsets and lengths refer to the decompressed block
+                if self.solid {
+                    let data = read_entry(n, source)?;
+                    let mut digest = EntryDigest::new(self.store_hashes);
+                    digest.update(data.as_ref());
+                    digest.finish(&mut index[i]);
+                    if sniff[n] {
+                        index[i].content_type = sniffed(&index[i].path, data.as_ref());
+                    }
+                    index[i].offset = solid_block.len() as u64;
+                    index[i].compressed_len = data.as_ref().len() as u64;
+                    solid_block.extend_from_slice(data.as_ref());
+                    i += 1;
+                    continue;
+                }
+
+                // Queued entries haven't been written yet, so i can lag behind the current entry
+                let block_size = index[n].block_size;
+                if let (Some(reader), true) = (source.reader(), block_size != 0) {
+                    // Queued entries have to be written first to keep the index order
+                    data.write_compressed(&self.codecs, std::mem::take(&mut jobs), self.min_savings, &mut index, &mut i)?;
+                    data.align()?;
+
+                    let mut block = Vec::with_capacity(block_size as usize);
+                    let mut reader = ChecksumReader::new(reader, self.store_hashes);
+                    index[i].offset = data.len;
+                    for b in 0..index[i].blocks.len() {
+                        check_cancelled(cancel.as_deref())?;
+                        block.clear();
+                        Read::by_ref(&mut reader).take(block_size).read_to_end(&mut block)?;
+
+                        let f_data = self.codecs.compress(codec, &block)?;
+                        data.write_data(&f_data)?;
+                        index[i].blocks[b] = f_data.len() as u64;
+                    }
+
+                    index[i].compressed_len = data.len - index[i].offset;
+                    reader.digest.finish(&mut index[i]);
+                    if sniff[n] {
+                        index[i].content_type = sniffed(&index[i].path, &reader.head);
+                    }
+                    i += 1;
+                    continue;
+                }
+
+                // Streamed entries go straight to the sink, so they always keep their codec
+                if let (Some(reader), true) = (source.reader(), size > STREAM_CHUNK_SIZE as u64 && codec.is_streamable() && index[n].flags & ENTRY_ENCRYPTED == 0) {
+                    // Queued entries have to be written first to keep the index order
+                    data.write_compressed(&self.codecs, std::mem::take(&mut jobs), self.min_savings, &mut index, &mut i)?;
+                    data.align()?;
+
+                    let start = data.len;
+                    let mut reader = ChecksumReader::new(reader, self.store_hashes);
+                    codec.compress_stream(&mut reader, &mut data, STREAM_CHUNK_SIZE, cancel.as_deref())?;
+
+                    reader.digest.finish(&mut index[i]);
+                    if sniff[n] {
+                        index[i].content_type = sniffed(&index[i].path, &reader.head);
+                    }
+                    index[i].offset = start;
+                    index[i].compressed_len = data.len - start;
+                    i += 1;
+                    continue;
+                }
+
+                // Queued entries are written later, but their index entry is already known
+                let entry_data = read_entry(n, source)?;
+                let mut digest = EntryDigest::new(self.store_hashes);
+                digest.update(entry_data.as_ref());
+                digest.finish(&mut index[n]);
+                if sniff[n] {
+                    index[n].content_type = sniffed(&index[n].path, entry_data.as_ref());
+                }
+                jobs.push((codec, entry_data));
+
+                if jobs.len() >= max_in_flight {
+                    data.write_compressed(&self.codecs, std::mem::take(&mut jobs), self.min_savings, &mut index, &mut i)?;
+                }
+            }
+
+            check_cancelled(cancel.as_deref())?;
+            data.write_compressed(&self.codecs, std::mem::take(&mut jobs), self.min_savings, &mut index, &mut i)
+        })?;
+
+        if self.solid {
+            let f_data = self.codecs.compress(default_codec, &solid_block)?;
+            data.align()?;
+            data.write_data(&f_data)?;
+        }
+
+        let (data_len, data_digest) = data.finish()?;
+
+        let index_data = entries_to_bytes(&with_aliases(&index, &aliases), compact)?;
+        if layout == Layout::IndexLast {
+            let (stored, _) = encode_index(&index_data, flags, None)?;
+            let stored = seal_index(stored, cipher)?;
+            report.index_size = stored.len() as u64;
+
+            let fingerprint = archive_fingerprint(&data_digest, &blake3::hash(&stored));
+            sink.write_all(&fingerprint)?;
+            report.fingerprint = Some(fingerprint);
+            write_trailer(sink, &stored, data_len)?;
+        }
+
+        for (entry, index_entry) in report.entries.iter_mut().zip(index.iter()) {
+            entry.compressed_size = index_entry.compressed_len;
+            if index_entry.codec == Codec::Stored.id() {
+                entry.codec = Codec::Stored;
+            }
+        }
+        report.compressed_size = data_len;
+
+        Ok((report, index_data, index_space, flags, data_digest))
+    }
+
+    pub fn get_all_files(&self) -> Box<[&str]> {
+        self.map.keys().map(|path| &path[..]).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &dyn Resource)> {
+        self.map.iter().map(|(path, entry)| (&path[..], &*entry.resource))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut (dyn Resource + 'static))> {
+        self.map.iter_mut().map(|(path, entry)| (&path[..], &mut *entry.resource))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains_path(&self, path: impl AsRef<str>) -> bool {
+        // Invalid paths can never be inserted, so a plain lookup is enough
+        self.map.contains_key(&*self.path_rules.apply(path.as_ref()))
+    }
+
+    pub fn get_paths_with_prefix(&self, prefix: impl AsRef<str>) -> Box<[&str]> {
+        self.paths_with_prefix(prefix.as_ref()).collect()
+    }
+
+    pub fn paths_with_prefix(&self, prefix: &str) -> impl Iterator<Item = &str> {
+        self.prefix_range(&self.path_rules.apply(prefix)).map(|path| &path[..])
+    }
+}
+
+pub(crate) struct ArchiveHeader {
+    pub version: u16,
+    pub flags: u16,
+    pub data_pointer: u64,
+    pub data_size: u64,
+    // Length of the index as stored, before it's decrypted or decompressed
+    pub index_size: u64,
+    pub index: Box<[IndexEntry]>,
+    pub extras: HeaderExtras,
+    pub cipher: Option<ArchiveCipher>,
+    pub index_digest: blake3::Hash
+}
+
+pub(crate) fn read_header<R: Read + Seek>(source: &mut R) -> Result<ArchiveHeader> {
+    read_header_with_key(source, None)
+}
+
+fn read_header_with_key<R: Read + Seek>(source: &mut R, key: Option<&ArchiveKey>) -> Result<ArchiveHeader> {
+    let mut first_10 = [0u8; 10];
+    source.read(&mut first_10)?;
+
+    if first_10 != HEADER_BYTES {
+        return Err(ResourceLibraryError::FileHeaderError.into());
+    }
+
+    let mut version = [0u8; 2];
+    source.read_exact(&mut version)?;
+    let version = u16::from_be_bytes(version);
+
+    if version > FORMAT_VERSION {
+        return Err(ResourceLibraryError::UnsupportedVersion(version, FORMAT_VERSION));
+    }
+
+    // Read metadata
+    let mut index_size = [0u8; 8];
+    let mut data_size = [0u8; 8];
+
+    // Version 0 archives have no version field, but since the index is never anywhere near 2^48 bytes
+    // the first two bytes of their index size are always zero, which is what was just read as the version
+    if version == 0 {
+        source.read_exact(&mut index_size[2..])?;
+    } else {
+        source.read_exact(&mut index_size)?;
+    }
+    source.read_exact(&mut data_size)?;
+
+    let index_size = u64::from_be_bytes(index_size);
+    let data_size = u64::from_be_bytes(data_size);
+
+    let mut flags = [0u8; 2];
+    if version >= 2 {
+        source.read_exact(&mut flags)?;
+    }
+
+    let flags = u16::from_be_bytes(flags);
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(ResourceLibraryError::UnsupportedFlags(flags));
+    }
+
+    if flags & FLAG_ENCRYPTED != 0 && key.is_none() {
+        return Err(if cfg!(feature = "encryption") { ResourceLibraryError::Encrypted } else { ResourceLibraryError::EncryptionNotEnabled });
+    }
+
+    let mut index_padding = [0u8; 8];
+    if version >= 4 {
+        source.read_exact(&mut index_padding)?;
+    }
+
+    let index_padding = u64::from_be_bytes(index_padding);
+
+    let mut crc = [0u8; 4];
+    if version >= 6 {
+        source.read_exact(&mut crc)?;
+    }
+
+    // Lengths are checked against what's left of the file before anything is allocated for them, so a damaged header
+    // or one with narrower fields fails cleanly
+    let header_end = source.stream_position()?;
+    let mut remaining = source.seek(SeekFrom::End(0))?.saturating_sub(header_end);
+    source.seek(SeekFrom::Start(header_end))?;
+
+    let mut extras = HeaderExtras::default();
+    let mut extras_data = Vec::new();
+    if flags & FLAG_COMMENT != 0 {
+        extras.comment = Some(String::from_utf8_lossy(&read_extra(source, &mut extras_data, &mut remaining)?).into_owned());
+    }
+
+    if flags & FLAG_CREATION_INFO != 0 {
+        extras.creation_info = Some(CreationInfo::from_bytes(&read_extra(source, &mut extras_data, &mut remaining)?)?);
+    }
+
+    if index_size > remaining {
+        return Err(ResourceLibraryError::IndexOutOfBounds(index_size, remaining));
+    }
+
+    let mut index_data = vec![0u8; index_size as usize];
+
+    source.read_exact(&mut index_data)?;
+
+    // Nothing in the index can be trusted if the header or index bytes were damaged
+    if version >= 6 && u32::from_be_bytes(crc) != header_crc(version, &index_data, index_padding, data_size, flags, &extras_data) {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    if flags & FLAG_INDEX_LAST != 0 {
+        let data_pointer = source.stream_position()?;
+        let fingerprint_len = if flags & FLAG_FINGERPRINT != 0 { FINGERPRINT_LEN } else { 0 };
+        let (index_data, data_size) = read_trailer(source, data_pointer, fingerprint_len)?;
+        let index_size = index_data.len() as u64;
+        let index_digest = blake3::hash(&index_data);
+        let (index_data, cipher) = open_index(index_data, flags, key)?;
+        let index = entries_from_bytes(&decode_index(index_data, flags)?, version, flags & FLAG_COMPACT_OFFSETS != 0)?;
+
+        return finish_header(ArchiveHeader { version, flags, data_pointer, data_size, index_size, index, extras, cipher, index_digest });
+    }
+
+    let index_digest = blake3::hash(&index_data);
+    let (index_data, cipher) = open_index(index_data, flags, key)?;
+    let index_data = decode_index(index_data, flags)?;
+    let index = match version {
+        0 => index_from_bytes(&index_data)?.into_vec().into_iter().map(IndexEntry::try_from).collect::<std::result::Result<_, _>>()?,
+        _ => entries_from_bytes(&index_data, version, flags & FLAG_COMPACT_OFFSETS != 0)?
+    };
+
+    let data_pointer = source.seek(SeekFrom::Current(index_padding as i64))?;
+
+    finish_header(ArchiveHeader { version, flags, data_pointer, data_size, index_size, index, extras, cipher, index_digest })
+}
+
+// Reads one of the header extras, keeping its raw bytes for the header checksum
+fn read_extra<R: Read>(source: &mut R, extras_data: &mut Vec<u8>, remaining: &mut u64) -> Result<Vec<u8>> {
+    let mut len = [0u8; 8];
+    source.read_exact(&mut len)?;
+
+    let extra_len = u64::from_be_bytes(len);
+    *remaining = remaining.saturating_sub(8).checked_sub(extra_len).ok_or(ResourceLibraryError::CorruptIndex)?;
+
+    let mut data = vec![0u8; extra_len as usize];
+    source.read_exact(&mut data)?;
+    extras_data.extend(len);
+    extras_data.extend(&data);
+
+    Ok(data)
+}
+
+fn finish_header(header: ArchiveHeader) -> Result<ArchiveHeader> {
+    if let Some(entry) = header.index.iter().find(|entry| entry.flags & ENTRY_FLAGS_CRITICAL & !KNOWN_ENTRY_FLAGS != 0) {
+        return Err(ResourceLibraryError::UnsupportedEntryFlags(entry.path.to_string(), entry.flags));
+    }
+
+    Ok(header)
+}
+
+// Reads the index and data length from the end of an index-last archive whose data starts at data_pointer.
+// gap is how much comes between the data and the index.
+fn read_trailer<R: Read + Seek>(source: &mut R, data_pointer: u64, gap: u64) -> Result<(Vec<u8>, u64)> {
+    let end = source.seek(SeekFrom::End(0))?;
+    if end < data_pointer + gap + TRAILER_SIZE {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    let mut trailer = [0u8; TRAILER_SIZE as usize];
+    source.seek(SeekFrom::Start(end - TRAILER_SIZE))?;
+    source.read_exact(&mut trailer)?;
+
+    let index_size = u64::from_be_bytes(trailer[0..8].try_into().unwrap());
+    let data_size = u64::from_be_bytes(trailer[8..16].try_into().unwrap());
+    let crc = u32::from_be_bytes(trailer[16..20].try_into().unwrap());
+
+    // A truncated archive won't end with the trailer, and a damaged one won't add up to the file's length
+    if trailer[20..] != TRAILER_BYTES || data_size.checked_add(index_size).and_then(|len| len.checked_add(data_pointer + gap + TRAILER_SIZE)) != Some(end) {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    let mut index_data = vec![0u8; index_size as usize];
+    source.seek(SeekFrom::Start(data_pointer + data_size + gap))?;
+    source.read_exact(&mut index_data)?;
+
+    if crc != trailer_crc(&index_data, data_size) {
+        return Err(ResourceLibraryError::CorruptIndex);
+    }
+
+    source.seek(SeekFrom::Start(data_pointer))?;
+
+    Ok((index_data, data_size))
+}
+
+fn known_len(len: u64) -> Option<u64> {
+    (len != UNKNOWN_LEN).then_some(len)
+}
+
+fn check_len(path: &str, data: &[u8], expected_len: Option<u64>) -> Result<()> {
+    match expected_len {
+        Some(len) if data.len() as u64 != len => Err(ResourceLibraryError::SizeMismatch(path.to_owned(), len)),
+        _ => Ok(())
+    }
+}
+
+#[cfg(feature = "hashes")]
+fn check_hash(path: &str, data: &[u8], hash: [u8; 32]) -> Result<()> {
+    match Sha256::digest(data)[..] == hash {
+        true => Ok(()),
+        false => Err(ResourceLibraryError::HashMismatch(path.to_owned()))
+    }
+}
+
+// Stored hashes are skipped without the hashes feature, the CRC32 is still checked
+#[cfg(not(feature = "hashes"))]
+fn check_hash(_path: &str, _data: &[u8], _hash: [u8; 32]) -> Result<()> {
+    Ok(())
+}
+
+// Offsets are relative to the data section in the first volume, and to the start of the file in the others.
+// Takes the reader's fields rather than the reader, so the codecs can be used while the volume is borrowed.
+fn seek_volume<'a, R: Seek>(file: &'a mut R, volumes: &'a mut [R], data_pointer: u64, volume: u64, offset: u64) -> Result<&'a mut R> {
+    let (file, start) = match volume {
+        0 => (file, data_pointer),
+        volume => (volumes.get_mut(volume as usize - 1).ok_or(ResourceLibraryError::MissingVolume(volume))?, 0)
+    };
+
+    file.seek(SeekFrom::Start(start + offset))?;
+    Ok(file)
+}
+
+// Where the archive an embed footer points at starts and ends, or None if the source doesn't end with one
+fn read_embed_footer<R: Read + Seek>(source: &mut R) -> Result<Option<(u64, u64)>> {
+    let Some(end) = source.seek(SeekFrom::End(0))?.checked_sub(EMBED_FOOTER_SIZE) else {
+        return Ok(None);
+    };
+
+    let mut footer = [0u8; EMBED_FOOTER_SIZE as usize];
+    source.seek(SeekFrom::Start(end))?;
+    source.read_exact(&mut footer)?;
+
+    let start = u64::from_be_bytes(footer[8..].try_into().unwrap());
+    Ok((footer[..8] == EMBED_FOOTER_BYTES && start < end).then_some((start, end)))
+}
+
+fn open_volume(path: &Path, volume: u64) -> Result<File> {
+    File::open(path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => ResourceLibraryError::MissingVolume(volume),
+        _ => ResourceLibraryError::DiskFileError(path.to_owned(), err)
+    })
+}
+
+// Same as ResourceLibraryReader::dump_index without the header values, for index bytes that have already been
+// decrypted and decompressed
+pub fn dump_index_bytes(index: &[u8], version: u16, compact_offsets: bool, w: &mut impl Write) -> Result<()> {
+    let entries: Box<[IndexEntry]> = match version {
+        0 => index_from_bytes(index)?.into_vec().into_iter().map(IndexEntry::try_from).collect::<std::result::Result<_, _>>()?,
+        _ => entries_from_bytes(index, version, compact_offsets)?
+    };
+
+    dump_entries(&entries, w)
+}
+
+fn dump_entries(entries: &[IndexEntry], w: &mut impl Write) -> Result<()> {
+    let unknown = |value: u64| if value == u64::MAX { "-".to_owned() } else { value.to_string() };
+
+    writeln!(w, "{:>12} {:>12} {:>12} {:>5} {:>6} {:>6} {:>8}  path", "offset", "compressed", "original", "codec", "flags", "volume", "crc32")?;
+    for entry in entries {
+        let crc32 = if entry.crc32 == NO_CRC { "-".to_owned() } else { format!("{:08x}", entry.crc32) };
+        write!(w, "{:>12} {:>12} {:>12} {:>5} {:>#6x} {:>6} {:>8}  {}", entry.offset, entry.compressed_len, unknown(entry.original_len), entry.codec, entry.flags, entry.volume, crc32, entry.path)?;
+
+        // Extended fields only show up when they're set, after the path
+        if entry.flags & ENTRY_ALIAS != 0 {
+            write!(w, " -> {}", entry.target)?;
+        }
+        if entry.block_size != 0 {
+            write!(w, " block_size={} blocks={}", entry.block_size, entry.blocks.len())?;
+        }
+        if entry.mtime != NO_MTIME {
+            write!(w, " mtime={}", entry.mtime)?;
+        }
+        if entry.mode != NO_MODE {
+            write!(w, " mode={:o}", entry.mode)?;
+        }
+        if !entry.content_type.is_empty() {
+            write!(w, " type={}", entry.content_type)?;
+        }
+        if !entry.sha256.is_empty() {
+            write!(w, " sha256={}", entry.sha256.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())?;
+        }
+        for key in entry.metadata.keys() {
+            write!(w, " meta:{}", key)?;
+        }
+        writeln!(w)?;
+    }
+
+    Ok(())
+}
+
+// Settings that have to be known while an archive is being opened
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReaderOptions {
+    case_insensitive: bool
+}
+
+impl ReaderOptions {
+    pub fn new() -> ReaderOptions {
+        ReaderOptions::default()
+    }
+
+    // Lets lookups that don't match any entry exactly fall back to comparing paths without case. When several entries
+    // only differ by case, an exact match still finds each of them, and anything else finds the first one in the index.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> ReaderOptions {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+}
+
+// Reads one entry, decompressing it as it's read. The length and checksum are checked on reaching the end,
+// and a mismatch comes back as an InvalidData error wrapping the ResourceLibraryError.
+pub struct EntryReader<'a> {
+    inner: BufReader<EntryStream<'a>>
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl BufRead for EntryReader<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+// Lets the decoders for each block of a chunked entry take turns reading from the same source
+struct SharedReader<'a, R>(Rc<RefCell<&'a mut R>>);
+
+impl<R> Clone for SharedReader<'_, R> {
+    fn clone(&self) -> Self {
+        SharedReader(self.0.clone())
+    }
+}
+
+impl<R: Read> Read for SharedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+// Cuts a source off at end, so an archive with something after it is read as if it ended the file
+struct BoundedReader<'a, R> {
+    inner: &'a mut R,
+    end: u64
+}
+
+impl<R: Read + Seek> Read for BoundedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.end.saturating_sub(self.inner.stream_position()?);
+        let len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl<R: Seek> Seek for BoundedReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::End(offset) => {
+                let position = self.end.checked_add_signed(offset).ok_or(std::io::ErrorKind::InvalidInput)?;
+                self.inner.seek(SeekFrom::Start(position))
+            },
+            pos => self.inner.seek(pos)
+        }
+    }
+}
+
+struct EntryStream<'a> {
+    path: String,
+    decoder: std::io::Take<Box<dyn Read + 'a>>,
+    expected_len: Option<u64>,
+    expected_crc32: Option<u32>,
+    crc: crc32fast::Hasher,
+    len: u64
+}
+
+impl Read for EntryStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.decoder.read(buf)?;
+        self.crc.update(&buf[..bytes_read]);
+        self.len += bytes_read as u64;
+
+        if bytes_read == 0 && !buf.is_empty() {
+            let invalid = |err| std::io::Error::new(std::io::ErrorKind::InvalidData, err);
+            if let Some(len) = self.expected_len.filter(|&len| len != self.len) {
+                return Err(invalid(ResourceLibraryError::SizeMismatch(self.path.clone(), len)));
+            }
+
+            let actual = self.crc.clone().finalize();
+            if let Some(crc32) = self.expected_crc32.filter(|&crc32| crc32 != actual) {
+                return Err(invalid(ResourceLibraryError::ChecksumMismatch(self.path.clone(), crc32, actual)));
+            }
+        }
+
+        Ok(bytes_read)
+    }
+}
+
+// A first volume whose bytes can be borrowed directly rather than read into a buffer
+enum MappedData {
+    #[cfg(feature = "mmap")]
+    File(memmap2::Mmap),
+    Static(&'static [u8])
+}
+
+impl AsRef<[u8]> for MappedData {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            MappedData::File(map) => map,
+            MappedData::Static(data) => data
+        }
+    }
+}
+
+// Reads archives from files by default, or from anything else that can be read and seeked through from_reader
+pub struct ResourceLibraryReader<R = File> {
+    file: R,
+    version: u16,
+    // The data files of a multi-volume archive after the first one
+    volumes: Vec<R>,
+    index: Box<[IndexEntry]>,
+    data_pointer: u64,
+    data_size: u64,
+    index_size: u64,
+    compressed_size: u64,
+    flags: u16,
+    solid_block: Option<Box<[u8]>>,
+    codecs: CodecRegistry,
+    verify_checksums: bool,
+    extras: HeaderExtras,
+    cipher: Option<ArchiveCipher>,
+    key_provider: Option<Box<dyn FnMut(&str) -> Option<ArchiveKey> + Send>>,
+    entry_keys: EntryKeys,
+    index_digest: blake3::Hash,
+    path_rules: PathRules,
+    // Case folded paths mapped to their entries, when the reader was opened case insensitive
+    case_folds: Option<BTreeMap<String, usize>>,
+    // The whole first volume, when the reader was opened with open_mmap or from_static
+    map: Option<MappedData>
+}
+
+impl ResourceLibraryReader<File> {
+    // The other volumes of a multi-volume archive are found next to the first one
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<ResourceLibraryReader> {
+        let path = path.as_ref();
+        Self::new_at(path, 0)
+    }
+
+    // For an archive that starts partway into the file, like one appended to an executable. Archives with the index
+    // last are found through the trailer at the end, so those have to run to the end of the file.
+    pub fn new_at<P: AsRef<Path>>(path: P, offset: u64) -> Result<ResourceLibraryReader> {
+        let path = path.as_ref();
+        Self::open_volumes(File::open(path)?, offset, None, |volume| open_volume(&path.with_extension(format!("{:03}", volume + 1)), volume))
+    }
+
+    // Finds the archive through the footer a writer with set_embed_footer leaves at the end of the file
+    pub fn open_embedded<P: AsRef<Path>>(path: P) -> Result<ResourceLibraryReader> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let (start, end) = read_embed_footer(&mut file)?.ok_or_else(|| ResourceLibraryError::NoEmbeddedArchive(path.to_owned()))?;
+
+        // Index-last archives are read from their end, which is just before the footer
+        file.seek(SeekFrom::Start(start))?;
+        let header = read_header_with_key(&mut BoundedReader { inner: &mut file, end }, None)?;
+
+        Self::from_header(file, header, |volume| open_volume(&path.with_extension(format!("{:03}", volume + 1)), volume))
+    }
+
+    // Refuses to open the archive unless the signature next to it was made by the given key
+    #[cfg(feature = "signing")]
+    pub fn open_verified<P: AsRef<Path>>(path: P, public_key: &VerifyingKey) -> Result<ResourceLibraryReader> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        signing::verify(path, &mut file, public_key)?;
+
+        Self::open_volumes(file, 0, None, |volume| Err(ResourceLibraryError::MissingVolume(volume)))
+    }
+
+    // Archives that aren't encrypted open as usual, ignoring the key
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, key: &ArchiveKey) -> Result<ResourceLibraryReader> {
+        Self::open_volumes(File::open(path)?, 0, Some(key), |volume| Err(ResourceLibraryError::MissingVolume(volume)))
+    }
+
+    pub fn open_parts<P: AsRef<Path>>(paths: &[P]) -> Result<ResourceLibraryReader> {
+        let first = paths.first().ok_or(ResourceLibraryError::MissingVolume(0))?;
+        Self::open_volumes(File::open(first)?, 0, None, |volume| match paths.get(volume as usize) {
+            Some(path) => open_volume(path.as_ref(), volume),
+            None => Err(ResourceLibraryError::MissingVolume(volume))
+        })
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: ReaderOptions) -> Result<ResourceLibraryReader> {
+        Ok(Self::new(path)?.with_options(options))
+    }
+
+    // Reads the index and entries straight out of a mapping of the first volume instead of through read calls.
+    // The mapping is only valid as long as nothing else modifies the file while the reader is open. If it's
+    // truncated, reading an entry can crash the process rather than return an error.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<ResourceLibraryReader> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|err| ResourceLibraryError::DiskFileError(path.to_owned(), err))?;
+
+        let header = read_header_with_key(&mut Cursor::new(&map[..]), None)?;
+        let mut reader = Self::from_header(file, header, |volume| open_volume(&path.with_extension(format!("{:03}", volume + 1)), volume))?;
+        reader.map = Some(MappedData::File(map));
+
+        Ok(reader)
+    }
+}
+
+// Archives already in memory are read straight out of the buffer. Entries are only copied out as they're read,
+// the same as they would be from a file, so the data is never held twice.
+impl ResourceLibraryReader<Cursor<Vec<u8>>> {
+    pub fn from_bytes(data: impl Into<Vec<u8>>) -> Result<ResourceLibraryReader<Cursor<Vec<u8>>>> {
+        Self::from_reader(Cursor::new(data.into()))
+    }
+}
+
+impl<'a> ResourceLibraryReader<Cursor<&'a [u8]>> {
+    pub fn from_slice(data: &'a [u8]) -> Result<ResourceLibraryReader<Cursor<&'a [u8]>>> {
+        Self::from_reader(Cursor::new(data))
+    }
+}
+
+pub type StaticReader = ResourceLibraryReader<Cursor<&'static [u8]>>;
+
+// For archives embedded with include_bytes!. Entries are decompressed straight out of the buffer the same way
+// open_mmap does it, so only what read_file returns is allocated.
+impl ResourceLibraryReader<Cursor<&'static [u8]>> {
+    pub fn from_static(data: &'static [u8]) -> Result<StaticReader> {
+        let mut reader = Self::from_reader(Cursor::new(data))?;
+        reader.map = Some(MappedData::Static(data));
+
+        Ok(reader)
+    }
+}
+
+impl<R: Read + Seek> ResourceLibraryReader<R> {
+    // Archives read this way have to fit in a single volume
+    pub fn from_reader(reader: R) -> Result<ResourceLibraryReader<R>> {
+        Self::from_reader_at(reader, 0)
+    }
+
+    pub fn from_reader_at(reader: R, offset: u64) -> Result<ResourceLibraryReader<R>> {
+        Self::open_volumes(reader, offset, None, |volume| Err(ResourceLibraryError::MissingVolume(volume)))
+    }
+
+    pub fn from_reader_with_options(reader: R, options: ReaderOptions) -> Result<ResourceLibraryReader<R>> {
+        Ok(Self::from_reader(reader)?.with_options(options))
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn from_reader_encrypted(reader: R, key: &ArchiveKey) -> Result<ResourceLibraryReader<R>> {
+        Self::open_volumes(reader, 0, Some(key), |volume| Err(ResourceLibraryError::MissingVolume(volume)))
+    }
+
+    // The data pointer ends up as a position in the whole source, so every read after the header already lands
+    // inside the archive wherever it starts
+    fn open_volumes(mut file: R, base: u64, key: Option<&ArchiveKey>, open_volume: impl FnMut(u64) -> Result<R>) -> Result<ResourceLibraryReader<R>> {
+        // The header is at the start of the archive, wherever the source was left
+        file.seek(SeekFrom::Start(base))?;
+        let header = read_header_with_key(&mut file, key)?;
+
+        Self::from_header(file, header, open_volume)
+    }
+
+    fn from_header(file: R, header: ArchiveHeader, open_volume: impl FnMut(u64) -> Result<R>) -> Result<ResourceLibraryReader<R>> {
+        let ArchiveHeader { version, flags, data_pointer, data_size, index_size, index, extras, cipher, index_digest } = header;
+
+        let last_volume = index.iter().map(|entry| entry.volume).max().unwrap_or(0);
+        let volumes = (1..=last_volume).map(open_volume).collect::<Result<Vec<_>>>()?;
+
+        let compressed_size = match flags & FLAG_SOLID {
+            0 => index.iter().filter(|entry| entry.flags & ENTRY_ALIAS == 0).map(|entry| entry.compressed_len).sum(),
+            _ => data_size
+        };
+
+        Ok(ResourceLibraryReader { file, version, volumes, index, data_pointer, data_size, index_size, compressed_size, flags, solid_block: None, codecs: CodecRegistry::default(), verify_checksums: true, extras, cipher, key_provider: None, entry_keys: EntryKeys::default(), index_digest, path_rules: PathRules::default(), case_folds: None, map: None })
+    }
+
+    // The source of the first volume, for reusing it once the archive has been read
+    pub fn into_inner(self) -> R {
+        self.file
+    }
+
+    fn with_options(mut self, options: ReaderOptions) -> ResourceLibraryReader<R> {
+        if options.case_insensitive {
+            let mut case_folds = BTreeMap::new();
+            for (n, entry) in self.index.iter().enumerate() {
+                case_folds.entry(fold_case(&entry.path)).or_insert(n);
+            }
+            self.case_folds = Some(case_folds);
+        }
+
+        self
+    }
+
+    // An exact match always wins, and only then are paths compared without case
+    fn find_entry(&self, path: &str) -> Option<usize> {
+        match self.index.binary_search_by(|entry| entry.path[..].cmp(path)) {
+            Ok(index) => Some(index),
+            Err(_) => self.case_folds.as_ref()?.get(&fold_case(path)).copied()
+        }
+    }
+
+    pub fn contains(&self, path: impl AsRef<str>) -> bool {
+        self.entry_index(path).is_some()
+    }
+
+    // Where the entry is in entries(), found from the index alone
+    pub fn entry_index(&self, path: impl AsRef<str>) -> Option<usize> {
+        self.find_entry(&self.path_rules.apply(path.as_ref()))
+    }
+
+    pub fn read_file(&mut self, path: impl AsRef<str>) -> Result<Box<[u8]>> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        is_valid_resource_path(path)?;
+        let index = self.find_entry(path).ok_or_else(|| PathError::InvalidPath(path.to_owned()))?;
+
+        // Everything past the lookup goes by the entry's own path, which keys and errors are tied to
+        if self.index[index].path != path {
+            return self.read_file(self.index[index].path.clone());
+        }
+
+        let entry = &self.index[index];
+        let crc32 = entry.crc32;
+
+        if entry.block_size != 0 {
+            let blocks = 0..entry.blocks.len();
+            let data = self.read_blocks(index, blocks)?;
+            self.check_crc(path, &data, crc32)?;
+
+            return Ok(data.into_boxed_slice());
+        }
+
+        if self.flags & FLAG_SOLID != 0 {
+            let (start, end, volume, codec) = (entry.offset as usize, (entry.offset + entry.compressed_len) as usize, entry.volume, entry.codec);
+            let block = self.solid_block(volume, codec)?;
+
+            let data: Box<[u8]> = block.get(start..end).ok_or_else(|| ResourceLibraryError::EntryOutOfBounds(path.to_owned()))?.into();
+            self.check_crc(path, &data, crc32)?;
+
+            return Ok(data);
+        }
+
+        let (volume, offset, codec, original_len, flags) = (entry.volume, entry.offset, entry.codec, known_len(entry.original_len), entry.flags);
+        if let Some(compressed) = self.mapped_data(volume, offset, entry.compressed_len).filter(|_| flags & ENTRY_ENCRYPTED == 0) {
+            let decompressed = self.codecs.decompress_slice(codec, compressed, original_len)?;
+            check_len(path, &decompressed, original_len)?;
+            self.check_crc(path, &decompressed, crc32)?;
+
+            return Ok(decompressed.into_boxed_slice());
+        }
+
+        let mut buffer = vec![0u8; entry.compressed_len as usize];
+        self.read_data(volume, offset, &mut buffer)?;
+        if flags & ENTRY_ENCRYPTED != 0 {
+            buffer = self.unseal_entry(path, &buffer)?;
+        }
+
+        let decompressed = self.codecs.decompress(codec, buffer, original_len)?;
+        check_len(path, &decompressed, original_len)?;
+        self.check_crc(path, &decompressed, crc32)?;
+
+        Ok(decompressed.into_boxed_slice())
+    }
+
+    // Decompresses into sink a chunk at a time, so memory stays bounded by the chunk size, or the block size of
+    // chunked entries, however large the entry is. Solid archives and encrypted data are only ever decrypted or
+    // decompressed whole, so those are read in full first. The length and checksum can only be checked once
+    // everything has gone through, so on a mismatch sink has already been given the bad data.
+    pub fn read_file_to(&mut self, path: impl AsRef<str>, sink: &mut impl Write) -> Result<u64> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        is_valid_resource_path(path)?;
+        let index = self.find_entry(path).ok_or_else(|| PathError::InvalidPath(path.to_owned()))?;
+
+        if self.index[index].path != path {
+            return self.read_file_to(self.index[index].path.clone(), sink);
+        }
+
+        let entry = &self.index[index];
+        let (volume, offset, compressed_len, codec, original_len, crc32) = (entry.volume, entry.offset, entry.compressed_len, entry.codec, known_len(entry.original_len), entry.crc32);
+        let mut written = 0;
+        let mut crc = crc32fast::Hasher::new();
+        let mut write = |data: &[u8]| {
+            crc.update(data);
+            written += data.len() as u64;
+            sink.write_all(data).map_err(ResourceLibraryError::SinkError)
+        };
+
+        if entry.block_size != 0 {
+            for block in 0..entry.blocks.len() {
+                write(&self.read_blocks(index, block..block + 1)?)?;
+            }
+        } else if self.flags & FLAG_SOLID != 0 || entry.flags & ENTRY_ENCRYPTED != 0 || self.cipher.is_some() {
+            // read_file has already checked the data
+            let data = self.read_file(path)?;
+            write(&data)?;
+            return Ok(written);
+        } else {
+            let source = seek_volume(&mut self.file, &mut self.volumes, self.data_pointer, volume, offset)?.take(compressed_len);
+            // One byte past the expected length is enough to tell that an entry is larger than it claims
+            let mut decoder = self.codecs.decoder(codec, source, original_len)?.take(original_len.map_or(u64::MAX, |len| len.saturating_add(1)));
+
+            let mut chunk = [0u8; SINK_CHUNK_SIZE];
+            loop {
+                let read = decoder.read(&mut chunk)?;
+                if read == 0 {
+                    break;
+                }
+
+                write(&chunk[..read])?;
+            }
+        }
+
+        if original_len.is_some_and(|len| len != written) {
+            return Err(ResourceLibraryError::SizeMismatch(path.to_owned(), original_len.unwrap()));
+        }
+        let actual = crc.finalize();
+        if self.verify_checksums && crc32 != NO_CRC && actual as u64 != crc32 {
+            return Err(ResourceLibraryError::ChecksumMismatch(path.to_owned(), crc32 as u32, actual));
+        }
+
+        Ok(written)
+    }
+
+    // Compressed bytes are pulled from the entry's region of the file as the handle is read, never past its end.
+    // Every other read seeks before it starts, so the reader is usable again as soon as the handle is dropped.
+    // Solid archives and encrypted data are only ever decrypted or decompressed whole, so those entries are read in full up front.
+    pub fn open_entry(&mut self, path: impl AsRef<str>) -> Result<EntryReader<'_>> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        is_valid_resource_path(path)?;
+        let index = self.find_entry(path).ok_or_else(|| PathError::InvalidPath(path.to_owned()))?;
+
+        if self.index[index].path != path {
+            return self.open_entry(self.index[index].path.clone());
+        }
+
+        let entry = &self.index[index];
+        let (volume, offset, compressed_len, codec, original_len) = (entry.volume, entry.offset, entry.compressed_len, entry.codec, known_len(entry.original_len));
+        let expected_crc32 = (self.verify_checksums && entry.crc32 != NO_CRC).then_some(entry.crc32 as u32);
+
+        let decoder: Box<dyn Read> = if self.flags & FLAG_SOLID != 0 || entry.flags & ENTRY_ENCRYPTED != 0 || self.cipher.is_some() {
+            Box::new(Cursor::new(self.read_file(path)?))
+        } else if entry.block_size != 0 {
+            // The blocks sit one after the other, so each one's decoder picks up where the last left the file
+            let (block_size, blocks) = (entry.block_size, entry.blocks.clone());
+            let file = SharedReader(Rc::new(RefCell::new(seek_volume(&mut self.file, &mut self.volumes, self.data_pointer, volume, offset)?)));
+            let mut decoders = Vec::with_capacity(blocks.len());
+            for (b, compressed_len) in (0..).zip(blocks) {
+                let expected_len = original_len.map(|len| len.saturating_sub(b * block_size).min(block_size));
+                decoders.push(self.codecs.decoder(codec, file.clone().take(compressed_len), expected_len)?);
+            }
+
+            decoders.into_iter().fold(Box::new(std::io::empty()), |chain: Box<dyn Read>, decoder| Box::new(chain.chain(decoder)))
+        } else {
+            let source = seek_volume(&mut self.file, &mut self.volumes, self.data_pointer, volume, offset)?.take(compressed_len);
+            self.codecs.decoder(codec, source, original_len)?
+        };
+
+        // One byte past the expected length is enough to tell that an entry is larger than it claims
+        let decoder = decoder.take(original_len.map_or(u64::MAX, |len| len.saturating_add(1)));
+        let stream = EntryStream { path: path.to_owned(), decoder, expected_len: original_len, expected_crc32, crc: crc32fast::Hasher::new(), len: 0 };
+
+        Ok(EntryReader { inner: BufReader::new(stream) })
+    }
+
+    // Writes every entry to a file under dest, creating directories along the way. Entry paths can't climb out of
+    // dest, and symlinks already inside it are never followed, so a hostile archive can't write anywhere else.
+    // Modification times and permissions are restored where the archive has them.
+    pub fn extract_all(&mut self, dest: impl AsRef<Path>, on_conflict: ConflictPolicy) -> Result<ExtractReport> {
+        self.extract_entries(dest.as_ref(), (0..self.index.len()).collect(), on_conflict, ExtractReport::default())
+    }
+
+    // Requested paths that aren't in the archive are listed in the report, or with MissingPolicy::Error fail the
+    // extraction before anything is written
+    pub fn extract_paths(&mut self, dest: impl AsRef<Path>, paths: &[&str], on_conflict: ConflictPolicy, on_missing: MissingPolicy) -> Result<ExtractReport> {
+        let mut report = ExtractReport::default();
+        let mut indices = Vec::with_capacity(paths.len());
+        for &path in paths {
+            match self.entry_index(path) {
+                Some(index) => indices.push(index),
+                None if on_missing == MissingPolicy::Error => return Err(PathError::InvalidPath(path.to_owned()).into()),
+                None => report.missing.push(path.to_owned())
+            }
+        }
+
+        self.extract_entries(dest.as_ref(), indices, on_conflict, report)
+    }
+
+    // Matches whole components like the writer's prefix operations, so "locale/de" doesn't take in locale/del/
+    pub fn extract_prefix(&mut self, dest: impl AsRef<Path>, prefix: &str, on_conflict: ConflictPolicy) -> Result<ExtractReport> {
+        let prefix = &*self.path_rules.apply(prefix);
+        let exact = match prefix {
+            "" => None,
+            prefix if prefix.ends_with('/') => None,
+            prefix => self.index.binary_search_by(|entry| entry.path[..].cmp(prefix)).ok()
+        };
+
+        let (lower, upper) = prefix_bounds(prefix);
+        let start = self.index.partition_point(|entry| match &lower {
+            Bound::Included(lower) => entry.path[..] < lower[..],
+            _ => false
+        });
+        let end = self.index.partition_point(|entry| match &upper {
+            Bound::Excluded(upper) => entry.path[..] < upper[..],
+            _ => true
+        });
+
+        let indices = exact.into_iter().chain(start..end.max(start)).collect();
+        self.extract_entries(dest.as_ref(), indices, on_conflict, ExtractReport::default())
+    }
+
+    // Entries are read in the order their data sits in the archive, so the reads only ever seek forward
+    fn extract_entries(&mut self, dest: &Path, mut indices: Vec<usize>, on_conflict: ConflictPolicy, mut report: ExtractReport) -> Result<ExtractReport> {
+        indices.sort_by_key(|&index| (self.index[index].volume, self.index[index].offset, index));
+        indices.dedup();
+        std::fs::create_dir_all(dest).map_err(|err| ResourceLibraryError::DiskFileError(dest.to_owned(), err))?;
+
+        for index in indices {
+            let entry = &self.index[index];
+            let path = entry.path.clone();
+            let (mtime, mode) = (entry.mtime, entry.mode);
+            is_valid_resource_path(&path)?;
+
+            // Neither the file nor any directory between it and dest can be a symlink
+            let target = path.components().fold(dest.to_owned(), |target, component| target.join(component));
+            for ancestor in target.ancestors().take_while(|&ancestor| ancestor != dest) {
+                match std::fs::symlink_metadata(ancestor) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => return Err(ResourceLibraryError::ExtractThroughSymlink(path.into(), ancestor.to_owned())),
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(ResourceLibraryError::DiskFileError(ancestor.to_owned(), err)),
+                    _ => ()
+                }
+            }
+
+            if target.exists() {
+                match on_conflict {
+                    ConflictPolicy::KeepExisting => {
+                        report.skipped.push(path.into());
+                        continue;
+                    },
+                    ConflictPolicy::Error => return Err(PathError::PathExists(path.into()).into()),
+                    ConflictPolicy::Overwrite => ()
+                }
+            }
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(|err| ResourceLibraryError::DiskFileError(parent.to_owned(), err))?;
+            }
+            let mut file = File::create(&target).map_err(|err| ResourceLibraryError::DiskFileError(target.clone(), err))?;
+            report.bytes += self.read_file_to(&path, &mut file)?;
+
+            if mtime != NO_MTIME {
+                file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(mtime)).map_err(|err| ResourceLibraryError::DiskFileError(target.clone(), err))?;
+            }
+            if let Ok(mode) = u32::try_from(mode) {
+                set_file_mode(&file, mode).map_err(|err| ResourceLibraryError::DiskFileError(target.clone(), err))?;
+            }
+            report.files += 1;
+        }
+
+        Ok(report)
+    }
+
+    // Replaces the contents of buf, reusing its allocation. When the entry's size is recorded buf grows to fit it
+    // at most once, so reading entries of similar sizes into the same Vec stops allocating for the output.
+    pub fn read_into(&mut self, path: impl AsRef<str>, buf: &mut Vec<u8>) -> Result<usize> {
+        let path = path.as_ref();
+        buf.clear();
+        if let Some(len) = self.uncompressed_len(path) {
+            buf.reserve_exact(len as usize);
+        }
+
+        Ok(self.read_file_to(path, buf)? as usize)
+    }
+
+    // For loaders that hand out slices of an arena. Entries with a recorded size are checked before anything is
+    // read, others fail once they run past the end of buf.
+    pub fn read_into_slice(&mut self, path: impl AsRef<str>, buf: &mut [u8]) -> Result<usize> {
+        let path = path.as_ref();
+        let capacity = buf.len();
+        if self.uncompressed_len(path).is_some_and(|len| len > capacity as u64) {
+            return Err(ResourceLibraryError::BufferTooSmall(path.to_owned(), capacity));
+        }
+
+        let mut sink = buf;
+        match self.read_file_to(path, &mut sink) {
+            Ok(len) => Ok(len as usize),
+            Err(ResourceLibraryError::SinkError(err)) if err.kind() == std::io::ErrorKind::WriteZero => Err(ResourceLibraryError::BufferTooSmall(path.to_owned(), capacity)),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub fn read_range(&mut self, path: impl AsRef<str>, range: Range<u64>) -> Result<Box<[u8]>> {
+        let path = &*self.path_rules.apply(path.as_ref());
+        is_valid_resource_path(path)?;
+        let index = self.find_entry(path).ok_or_else(|| PathError::InvalidPath(path.to_owned()))?;
+
+        if self.index[index].path != path {
+            return self.read_range(self.index[index].path.clone(), range);
+        }
+
+        let entry = &self.index[index];
+        if entry.block_size == 0 || range.start >= range.end {
+            let data = self.read_file(path)?;
+            let end = (range.end as usize).min(data.len());
+            let start = (range.start as usize).min(end);
+
+            return Ok(data[start..end].into());
+        }
+
+        // Only decompress the blocks that overlap the range
+        let block_size = entry.block_size;
+        let first = (range.start / block_size) as usize;
+        let last = ((range.end - 1) / block_size + 1).min(entry.blocks.len() as u64) as usize;
+        let data = self.read_blocks(index, first.min(last)..last)?;
+
+        let base = first as u64 * block_size;
+        let end = ((range.end - base) as usize).min(data.len());
+        let start = ((range.start.max(base) - base) as usize).min(end);
+
+        Ok(data[start..end].into())
+    }
+
+    fn read_blocks(&mut self, index: usize, blocks: Range<usize>) -> Result<Vec<u8>> {
+        let entry = &self.index[index];
+        let mut offset = entry.offset + entry.blocks[..blocks.start].iter().sum::<u64>();
+        let (volume, codec, block_size, original_len) = (entry.volume, entry.codec, entry.block_size, known_len(entry.original_len));
+        let (first, lens) = (blocks.start as u64, entry.blocks[blocks].to_vec());
+
+        let mut data = Vec::new();
+        for (b, compressed_len) in (first..).zip(lens) {
+            let mut buffer = vec![0u8; compressed_len as usize];
+            self.read_data(volume, offset, &mut buffer)?;
+
+            // Every block is full except possibly the last one
+            let expected_len = original_len.map(|len| len.saturating_sub(b * block_size).min(block_size));
+            let block = self.codecs.decompress(codec, buffer, expected_len)?;
+            check_len(&self.index[index].path, &block, expected_len)?;
+
+            data.extend(block);
+            offset += compressed_len;
+        }
+
+        Ok(data)
+    }
+
+    // The whole data section is decompressed on first use and kept around for later reads
+    fn solid_block(&mut self, volume: u64, codec: u8) -> Result<&[u8]> {
+        if self.solid_block.is_none() {
+            let data_size = if self.cipher.is_some() { plain_len(self.data_size) } else { self.data_size };
+            let mut buffer = vec![0u8; data_size as usize];
+            self.read_data(volume, 0, &mut buffer)?;
+
+            self.solid_block = Some(self.codecs.decompress(codec, buffer, None)?.into_boxed_slice());
+        }
+
+        Ok(self.solid_block.as_deref().unwrap())
+    }
+
+    // The key is only asked for when an encrypted entry is actually read
+    fn unseal_entry(&mut self, path: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key_provider.as_mut().and_then(|provider| provider(path)).ok_or_else(|| {
+            if cfg!(feature = "encryption") { ResourceLibraryError::MissingKey(path.to_owned()) } else { ResourceLibraryError::EncryptionNotEnabled }
+        })?;
+        self.entry_keys.open(&key, sealed)
+    }
+
+    // A slice of the mapped data section, for archives opened with open_mmap or from_static. Only the first volume
+    // is mapped, and encrypted data has to be decrypted into a buffer anyway.
+    fn mapped_data(&self, volume: u64, offset: u64, len: u64) -> Option<&[u8]> {
+        let map = self.map.as_ref().filter(|_| volume == 0 && self.cipher.is_none())?.as_ref();
+        let start = usize::try_from(self.data_pointer.checked_add(offset)?).ok()?;
+        map.get(start..start.checked_add(usize::try_from(len).ok()?)?)
+    }
+
+    // An entry's bytes borrowed straight from the mapping, without copying them. Only entries stored without
+    // compression, encryption or chunking in the first volume qualify. The checksum isn't checked, verify_all
+    // does that for every entry.
+    pub fn mapped_bytes(&self, path: impl AsRef<str>) -> Option<&[u8]> {
+        let entry = &self.index[self.entry_index(path)?];
+        if entry.codec != Codec::Stored.id() || entry.block_size != 0 || entry.flags & ENTRY_ENCRYPTED != 0 || self.flags & FLAG_SOLID != 0 {
+            return None;
+        }
+
+        self.mapped_data(entry.volume, entry.offset, entry.compressed_len)
+    }
+
+    // Offsets in an encrypted archive are into the decrypted data, so every segment the read touches is decrypted
+    fn read_data(&mut self, volume: u64, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        if self.cipher.is_none() {
+            self.seek_data(volume, offset)?.read_exact(buffer)?;
+            return Ok(());
+        }
+
+        let end = offset + buffer.len() as u64;
+        let segments = offset / SEGMENT_SIZE..end.div_ceil(SEGMENT_SIZE);
+        let sealed_start = segments.start * SEALED_SEGMENT_SIZE;
+        let sealed_end = (segments.end * SEALED_SEGMENT_SIZE).min(self.data_size).max(sealed_start);
+
+        let mut sealed = vec![0u8; (sealed_end - sealed_start) as usize];
+        self.seek_data(volume, sealed_start)?.read_exact(&mut sealed)?;
+
+        let cipher = self.cipher.as_ref().unwrap();
+        let mut data = Vec::with_capacity(sealed.len());
+        for (segment, chunk) in segments.clone().zip(sealed.chunks(SEALED_SEGMENT_SIZE as usize)) {
+            data.extend(cipher.open_segment(segment, chunk)?);
+        }
+
+        let start = (offset - segments.start * SEGMENT_SIZE) as usize;
+        let data = data.get(start..start + buffer.len()).ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        buffer.copy_from_slice(data);
+
+        Ok(())
+    }
+
+    // Offsets are relative to the data section in the first volume, and to the start of the file in the others
+    fn seek_data(&mut self, volume: u64, offset: u64) -> Result<&mut R> {
+        seek_volume(&mut self.file, &mut self.volumes, self.data_pointer, volume, offset)
+    }
+
+    fn check_crc(&self, path: &str, data: &[u8], crc32: u64) -> Result<()> {
+        if !self.verify_checksums || crc32 == NO_CRC {
+            return Ok(());
+        }
+
+        let actual = crc32fast::hash(data);
+        match actual as u64 == crc32 {
+            true => Ok(()),
+            false => Err(ResourceLibraryError::ChecksumMismatch(path.to_owned(), crc32 as u32, actual))
+        }
+    }
+
+    pub fn register_codec(&mut self, codec: impl CompressionCodec + 'static) -> Result<()> {
+        self.codecs.register(codec)
+    }
+
+    // Called with an entry's path to get the key for reading it, whenever an encrypted entry is read
+    #[cfg(feature = "encryption")]
+    pub fn set_key_provider(&mut self, provider: impl FnMut(&str) -> Option<ArchiveKey> + Send + 'static) {
+        self.key_provider = Some(Box::new(provider));
+    }
+
+    pub fn is_encrypted(&self, path: impl AsRef<str>) -> bool {
+        self.entry_index(path).is_some_and(|index| self.index[index].flags & ENTRY_ENCRYPTED != 0)
+    }
+
+    // The entry an alias shares its data with, or None if the path isn't an alias
+    pub fn alias_target(&self, path: impl AsRef<str>) -> Option<&str> {
+        self.entry_index(path).map(|index| &self.index[index]).filter(|entry| entry.flags & ENTRY_ALIAS != 0).map(|entry| &entry.target[..])
+    }
+
+    // None if the archive was written without hashes
+    pub fn entry_hash(&self, path: impl AsRef<str>) -> Option<[u8; 32]> {
+        let index = self.entry_index(path)?;
+
+        self.index[index].sha256[..].try_into().ok()
+    }
+
+    // Decompresses and checks every entry, collecting failures instead of stopping at the first one
+    pub fn verify_all(&mut self) -> Result<VerifyReport> {
+        let verify_checksums = std::mem::replace(&mut self.verify_checksums, true);
+        let paths: Vec<String> = self.index.iter().map(|entry| entry.path.to_string()).collect();
+
+        let mut report = VerifyReport::default();
+        for path in paths {
+            let result = self.read_file(&path).and_then(|data| match self.entry_hash(&path) {
+                Some(hash) => check_hash(&path, &data, hash),
+                None => Ok(())
+            });
+
+            match result {
+                Ok(()) => report.verified += 1,
+                Err(err) => report.failures.push((path, err))
+            }
+        }
+
+        self.verify_checksums = verify_checksums;
+        Ok(report)
+    }
+
+    // Same as ResourceLibraryWriter::set_path_normalization, for the paths given to read_file and read_range
+    pub fn set_path_normalization(&mut self, normalization: Normalize) {
+        self.path_rules.separators = normalization;
+    }
+
+    // Same as ResourceLibraryWriter::set_nfc_paths, for lookups
+    #[cfg(feature = "unicode")]
+    pub fn set_nfc_paths(&mut self, nfc: bool) {
+        self.path_rules.nfc = nfc;
+    }
+
+    // Checksums are verified by read_file unless this is turned off. Partial reads through read_range are never verified.
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+
+    // The fingerprint recorded when the archive was written. Archives that have been edited or split into volumes have none.
+    pub fn fingerprint(&mut self) -> Result<Option<[u8; 32]>> {
+        if self.flags & FLAG_FINGERPRINT == 0 {
+            return Ok(None);
+        }
+
+        let mut fingerprint = [0u8; 32];
+        self.file.seek(SeekFrom::Start(self.data_pointer + self.data_size))?;
+        self.file.read_exact(&mut fingerprint)?;
+
+        Ok(Some(fingerprint))
+    }
+
+    // Rehashes the whole data section to check it against the recorded fingerprint. A truncated archive doesn't match.
+    pub fn verify_fingerprint(&mut self) -> Result<bool> {
+        let expected = match self.fingerprint() {
+            Ok(Some(fingerprint)) => fingerprint,
+            Ok(None) => return Ok(false),
+            Err(ResourceLibraryError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err)
+        };
+
+        let mut hasher = blake3::Hasher::new();
+        self.file.seek(SeekFrom::Start(self.data_pointer))?;
+        let hashed = std::io::copy(&mut (&mut self.file).take(self.data_size), &mut hasher)?;
+
+        Ok(hashed == self.data_size && archive_fingerprint(&hasher.finalize(), &self.index_digest) == expected)
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.extras.comment.as_deref()
+    }
+
+    // None unless the writer was asked to record it
+    pub fn creation_info(&self) -> Option<&CreationInfo> {
+        self.extras.creation_info.as_ref()
+    }
+
+    // The format version the archive was written with
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn volume_count(&self) -> usize {
+        self.volumes.len() + 1
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.flags & FLAG_SOLID != 0
+    }
+
+    pub fn get_all_files(&self) -> Box<[&str]> {
+        self.index.iter().map(|entry| &entry.path[..]).collect()
+    }
+
+    pub fn glob(&self, pattern: &str) -> Result<Vec<&str>> {
+        Ok(self.glob_with(&Glob::new(pattern)?))
+    }
+
+    pub fn glob_with(&self, glob: &Glob) -> Vec<&str> {
+        self.index[self.glob_range(glob)].iter()
+            .map(|entry| &entry.path[..])
+            .filter(|path| glob.is_match(path))
+            .collect()
+    }
+
+    // The run of the sorted index starting with the glob's literal prefix, the only entries it could match
+    pub(crate) fn glob_range(&self, glob: &Glob) -> Range<usize> {
+        let prefix = glob.literal_prefix();
+        let start = self.index.partition_point(|entry| entry.path[..] < prefix[..]);
+        let len = self.index[start..].partition_point(|entry| entry.path.starts_with(&prefix[..]));
+
+        start..start + len
+    }
+
+    pub fn paths_with_extension(&self, ext: &str) -> Vec<&str> {
+        self.paths_with_extensions(&[ext])
+    }
+
+    pub fn paths_with_extensions(&self, exts: &[&str]) -> Vec<&str> {
+        self.index.iter()
+            .filter(|entry| exts.iter().any(|ext| entry.path.has_extension(ext)))
+            .map(|entry| &entry.path[..])
+            .collect()
+    }
+
+    // Every entry in the index, sorted by path
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = EntryRef<'_>> {
+        self.index.iter().map(EntryRef)
+    }
+
+    // The index entries as they were read, with their fields as stored
+    pub fn index_entries(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    // Writes the header values and one line per entry, in a fixed layout so the dumps of two archives can be diffed.
+    // Read along with the header, so this never touches the data section.
+    pub fn dump_index(&self, w: &mut impl Write) -> Result<()> {
+        writeln!(w, "version       {}", self.version)?;
+        writeln!(w, "flags         {:#06x}", self.flags)?;
+        writeln!(w, "index_size    {}", self.index_size)?;
+        writeln!(w, "data_size     {}", self.data_size)?;
+        writeln!(w, "data_pointer  {}", self.data_pointer)?;
+        writeln!(w, "entries       {}", self.index.len())?;
+
+        dump_entries(&self.index, w)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.len()
+    }
+
+    pub fn summary(&self) -> ArchiveSummary {
+        // Entries are compared by their uncompressed size where it was recorded, and the first of equally large ones wins
+        let size = |entry: &IndexEntry| known_len(entry.original_len).unwrap_or(entry.compressed_len);
+        let largest = self.index.iter().reduce(|largest, entry| if size(entry) > size(largest) { entry } else { largest });
+
+        ArchiveSummary {
+            entries: self.index.len(),
+            sizes: self.total_sizes(),
+            largest: largest.map(|entry| (entry.path.to_string(), EntrySizes { compressed: entry.compressed_len, uncompressed: known_len(entry.original_len) })),
+            index_size: self.index_size
+        }
+    }
+
+    // None if there's no such entry, or if it comes from an archive older than version 5
+    pub fn uncompressed_len(&self, path: impl AsRef<str>) -> Option<u64> {
+        self.entry_index(path).and_then(|index| known_len(self.index[index].original_len))
+    }
+
+    pub fn compressed_len(&self, path: impl AsRef<str>) -> Option<u64> {
+        self.entry_index(path).map(|index| self.index[index].compressed_len)
+    }
+
+    // Seconds since the unix epoch, or None if the entry was written without a modification time
+    pub fn entry_mtime(&self, path: impl AsRef<str>) -> Option<u64> {
+        self.entry_index(path).map(|index| self.index[index].mtime).filter(|&mtime| mtime != NO_MTIME)
+    }
+
+    // Unix permission bits, or None if the entry wasn't added from a unix filesystem
+    pub fn entry_mode(&self, path: impl AsRef<str>) -> Option<u32> {
+        self.entry_index(path).and_then(|index| u32::try_from(self.index[index].mode).ok())
+    }
+
+    pub fn content_type(&self, path: impl AsRef<str>) -> Option<&str> {
+        self.entry_index(path).map(|index| &self.index[index].content_type[..]).filter(|content_type| !content_type.is_empty())
+    }
+
+    // None if there's no such entry or nothing was attached to it
+    pub fn metadata(&self, path: impl AsRef<str>) -> Option<&BTreeMap<String, Box<[u8]>>> {
+        self.entry_index(path).map(|index| &self.index[index].metadata).filter(|metadata| !metadata.is_empty())
+    }
+
+    pub fn total_compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    // None if any entry's size wasn't recorded. Aliases share their target's data, so they aren't counted twice.
+    pub fn total_uncompressed_size(&self) -> Option<u64> {
+        self.index.iter().filter(|entry| entry.flags & ENTRY_ALIAS == 0).map(|entry| known_len(entry.original_len)).sum()
+    }
+
+    // Both sizes come from the index, nothing is read or decompressed
+    pub fn entry_sizes(&self, path: impl AsRef<str>) -> Option<EntrySizes> {
+        let entry = &self.index[self.entry_index(path)?];
+        Some(EntrySizes { compressed: entry.compressed_len, uncompressed: known_len(entry.original_len) })
+    }
+
+    pub fn total_sizes(&self) -> EntrySizes {
+        EntrySizes { compressed: self.total_compressed_size(), uncompressed: self.total_uncompressed_size() }
     }
 }
\ No newline at end of file
@@ -0,0 +1,52 @@
+use std::{ffi::OsString, fs::File, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use ed25519_dalek::{Signature, Signer, SIGNATURE_LENGTH};
+use sha2::{Digest, Sha256};
+
+use crate::resource_library::{ResourceLibraryError, Result};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+// Keeps a signature over an archive from being mistaken for one over anything else
+const SIGNATURE_CONTEXT: &[u8] = b"resource_packager archive signature v1";
+
+// Signatures are kept next to the archive as {path}.sig
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".sig");
+
+    PathBuf::from(name)
+}
+
+// Everything in the file is signed, header, index and data alike
+fn archive_digest<R: Read + Seek>(source: &mut R) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    hasher.update(SIGNATURE_CONTEXT);
+    source.seek(SeekFrom::Start(0))?;
+    std::io::copy(source, &mut hasher)?;
+
+    Ok(hasher.finalize().to_vec())
+}
+
+pub fn sign<P: AsRef<Path>>(path: P, signing_key: &SigningKey) -> Result<()> {
+    let path = path.as_ref();
+    let digest = archive_digest(&mut File::open(path)?)?;
+
+    let signature_path = signature_path(path);
+    std::fs::write(&signature_path, signing_key.sign(&digest).to_bytes()).map_err(|err| ResourceLibraryError::DiskFileError(signature_path, err))
+}
+
+// Checks the archive that's already open against the signature next to it, leaving the file at the start
+pub(crate) fn verify(path: &Path, file: &mut File, public_key: &VerifyingKey) -> Result<()> {
+    let signature = match std::fs::read(signature_path(path)) {
+        Ok(signature) => signature,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(ResourceLibraryError::Unsigned),
+        Err(err) => return Err(ResourceLibraryError::DiskFileError(signature_path(path), err))
+    };
+
+    let signature: [u8; SIGNATURE_LENGTH] = signature.try_into().map_err(|_| ResourceLibraryError::BadSignature)?;
+    let digest = archive_digest(file)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    public_key.verify_strict(&digest, &Signature::from_bytes(&signature)).map_err(|_| ResourceLibraryError::BadSignature)
+}